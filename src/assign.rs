@@ -0,0 +1,191 @@
+use crate::pcm::Peak;
+use soundlog::chip::Chip;
+use soundlog::chip::fnumber::FNumber;
+use std::fmt::Debug;
+use std::sync::Arc;
+
+/// How the peaks of a frame are spread over the voices of the chip
+/// instances.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AssignmentMode {
+    /// Each peak in turn (loudest first) takes the instance that tunes it
+    /// best among those with a free voice. Fast, but an early peak may take
+    /// the voice a later one needed more.
+    #[default]
+    Greedy,
+    /// The assignment of all peaks to all free voices with the lowest total
+    /// `AssignmentCost` (Hungarian algorithm). A peak is left out when
+    /// dropping it costs less than any voice it could take.
+    Optimal,
+}
+
+/// A peak played by one chip instance, as priced by `AssignmentCost`.
+#[derive(Debug, Clone, Copy)]
+pub struct AssignmentCandidate<'a> {
+    pub peak: &'a Peak,
+    pub chip: &'a Chip,
+    /// Index of the instance in the chip list.
+    pub instance: usize,
+    /// The F-number the instance would play the peak at.
+    pub fnumber: &'a FNumber,
+    /// Band the instance plays with full tuning resolution at its master
+    /// clock (see `resynth::chip_frequency_range_at`).
+    pub freq_range: Option<(f32, f32)>,
+    /// Quietest level (dBFS) the chip's voice is written at before muting
+    /// (see `resynth::mag_to_tl`).
+    pub level_floor_db: f32,
+    /// Distance (cents) from the peak to the closest voice the instance
+    /// played in the previous frame; `None` when it played none.
+    pub continuity_cents: Option<f32>,
+}
+
+/// Cost model of `AssignmentMode::Optimal`.
+pub trait AssignmentCost: Debug + Send + Sync {
+    /// Cost of the candidate's instance playing its peak; `None` when it
+    /// must not.
+    fn cost(&self, candidate: &AssignmentCandidate) -> Option<f32>;
+
+    /// Cost of leaving `peak` without a voice.
+    fn drop_cost(&self, peak: &Peak) -> f32;
+}
+
+/// Weighted sum of tuning error, lost level, frequency range and channel
+/// continuity; the default `AssignmentCost`. Costs are on a scale of
+/// decibels of the dropped peak.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WeightedCost {
+    /// Cost per cent of tuning error.
+    pub tuning_per_cent: f32,
+    /// Cost per dB the peak lies below the chip's level floor.
+    pub level_per_db: f32,
+    /// Cost of a peak outside the chip's range; `None` forbids it.
+    pub out_of_range: Option<f32>,
+    /// Cost of a peak that does not continue a voice the instance played in
+    /// the previous frame (within `continuity_cents`), so a partial stays
+    /// on its chip.
+    pub discontinuity: f32,
+    pub continuity_cents: f32,
+    /// Cost per dB above -60 dBFS of dropping a peak.
+    pub drop_per_db: f32,
+}
+
+impl Default for WeightedCost {
+    fn default() -> Self {
+        WeightedCost {
+            tuning_per_cent: 0.1,
+            level_per_db: 1.0,
+            out_of_range: Some(20.0),
+            discontinuity: 3.0,
+            continuity_cents: 50.0,
+            drop_per_db: 1.0,
+        }
+    }
+}
+
+impl AssignmentCost for WeightedCost {
+    fn cost(&self, candidate: &AssignmentCandidate) -> Option<f32> {
+        let peak = candidate.peak;
+        let mut cost = self.tuning_per_cent * candidate.fnumber.error_cents.abs();
+        cost += self.level_per_db * (candidate.level_floor_db - peak.magnitude_db).max(0.0);
+        if let Some((lo, hi)) = candidate.freq_range
+            && !(lo..=hi).contains(&peak.freq_hz)
+        {
+            cost += self.out_of_range?;
+        }
+        if candidate
+            .continuity_cents
+            .is_none_or(|cents| cents > self.continuity_cents)
+        {
+            cost += self.discontinuity;
+        }
+        Some(cost)
+    }
+
+    fn drop_cost(&self, peak: &Peak) -> f32 {
+        self.drop_per_db * (peak.magnitude_db + 60.0).max(0.0)
+    }
+}
+
+/// Options for assigning peaks to chip voices.
+#[derive(Debug, Clone)]
+pub struct AssignmentOptions {
+    pub mode: AssignmentMode,
+    /// Cost model of `AssignmentMode::Optimal`.
+    pub cost: Arc<dyn AssignmentCost>,
+}
+
+impl Default for AssignmentOptions {
+    fn default() -> Self {
+        AssignmentOptions {
+            mode: AssignmentMode::default(),
+            cost: Arc::new(WeightedCost::default()),
+        }
+    }
+}
+
+/// Minimum-cost assignment of rows to distinct columns (Hungarian
+/// algorithm, O(rows² × columns)).
+///
+/// `costs` holds one row per item, each with the same number of columns,
+/// at least as many as there are rows. Returns the column of each row.
+pub fn min_cost_assignment(costs: &[Vec<f32>]) -> Vec<usize> {
+    let n = costs.len();
+    let m = costs.first().map_or(0, |row| row.len());
+    assert!(n <= m, "more rows than columns");
+    // potentials and matching, 1-based with 0 as the virtual start
+    let mut u = vec![0.0f64; n + 1];
+    let mut v = vec![0.0f64; m + 1];
+    let mut row_of = vec![0usize; m + 1];
+    let mut way = vec![0usize; m + 1];
+    for i in 1..=n {
+        row_of[0] = i;
+        let mut j0 = 0usize;
+        let mut min_to = vec![f64::INFINITY; m + 1];
+        let mut used = vec![false; m + 1];
+        loop {
+            used[j0] = true;
+            let i0 = row_of[j0];
+            let mut delta = f64::INFINITY;
+            let mut j1 = 0usize;
+            for j in 1..=m {
+                if used[j] {
+                    continue;
+                }
+                let reduced = costs[i0 - 1][j - 1] as f64 - u[i0] - v[j];
+                if reduced < min_to[j] {
+                    min_to[j] = reduced;
+                    way[j] = j0;
+                }
+                if min_to[j] < delta {
+                    delta = min_to[j];
+                    j1 = j;
+                }
+            }
+            for j in 0..=m {
+                if used[j] {
+                    u[row_of[j]] += delta;
+                    v[j] -= delta;
+                } else {
+                    min_to[j] -= delta;
+                }
+            }
+            j0 = j1;
+            if row_of[j0] == 0 {
+                break;
+            }
+        }
+        // flip the augmenting path
+        while j0 != 0 {
+            let j1 = way[j0];
+            row_of[j0] = row_of[j1];
+            j0 = j1;
+        }
+    }
+    let mut column = vec![0usize; n];
+    for j in 1..=m {
+        if row_of[j] != 0 {
+            column[row_of[j] - 1] = j - 1;
+        }
+    }
+    column
+}
@@ -1,6 +1,20 @@
 use clap::{Parser, ValueEnum};
-use nanonanoda::pcm::interleaved_to_mono;
-use nanonanoda::resynth::{process_samples_resynth_multi, process_samples_resynth_multi_to_vgm};
+use nanonanoda::assign::AssignmentMode;
+use nanonanoda::filter::FilterStage;
+use nanonanoda::loudness::{GainOptions, LimiterOptions, LoudnessTarget};
+use nanonanoda::pcm::{
+    F0Options, FrequencyEstimator, OnsetMethod, OnsetOptions, PeakSelection, PeakSpacing,
+    ResidualOptions, StereoAnalysis, WindowFunction, default_resolution_bands, interleaved_to_mono,
+    interleaved_to_stereo,
+};
+use nanonanoda::quantize::{Key, QuantizeOptions, Scale};
+use nanonanoda::refine::RefineOptions;
+use nanonanoda::resynth::{
+    InputChannels, ResynthOptions, SpeechOptions, process_samples_resynth_multi,
+    process_stereo_resynth_multi, process_stereo_resynth_multi_to_vgm, resynth_onsets,
+};
+use nanonanoda::track::TrackingOptions;
+use nanonanoda::voice::StealPolicy;
 use soundlog::chip::Chip;
 use soundlog::meta::Gd3;
 use std::path::{Path, PathBuf};
@@ -24,14 +38,172 @@ struct Args {
     #[arg(short = 'w', long = "window-size", default_value_t = 512)]
     window_size: usize,
 
-    /// Output sample rate (Hz) for synthesis and written file
+    /// Hop between analysis frames in samples (defaults to the window size)
+    #[arg(long = "hop-size")]
+    hop_size: Option<usize>,
+
+    /// What the voices follow: spectral peaks, or the formants of the LPC
+    /// envelope (speech)
+    #[arg(long = "mode", value_enum, default_value_t = ModeArg::Peaks)]
+    mode: ModeArg,
+
+    /// In formant mode, add a voice at the fundamental of voiced frames
+    #[arg(long = "formant-f0")]
+    formant_f0: bool,
+
+    /// Snap voices to the nearest note (ignored in formant mode)
+    #[arg(long = "quantize")]
+    quantize: bool,
+
+    /// Only quantize to notes of this key (implies --quantize). Syntax:
+    /// tonic[:scale] e.g. "a:minor", "f#:major-pentatonic". Scales: major
+    /// (default), minor, harmonic-minor, major-pentatonic, minor-pentatonic,
+    /// chromatic
+    #[arg(long = "key")]
+    key: Option<KeyArg>,
+
+    /// How much closer (cents) a new note must be before a quantized voice
+    /// leaves the note it holds
+    #[arg(long = "note-hysteresis", default_value_t = 30.0)]
+    note_hysteresis: f32,
+
+    /// Analyze bass with long windows and treble with short ones
+    /// (8192 samples below 250 Hz down to 512 above 4 kHz)
+    #[arg(long = "multi-resolution")]
+    multi_resolution: bool,
+
+    /// Link peaks across frames into partial tracks before assigning voices
+    #[arg(long = "track-partials")]
+    track_partials: bool,
+
+    /// How each frame's peaks are spread over the chips' voices: greedily
+    /// by tuning error, or with the lowest total cost of tuning error, lost
+    /// level, frequency range and channel continuity
+    #[arg(long = "assignment", value_enum, default_value_t = AssignmentArg::Greedy)]
+    assignment: AssignmentArg,
+
+    /// Which sounding voice gives up its channel when a new one finds all
+    /// channels taken (VGM output)
+    #[arg(long = "steal", value_enum, default_value_t = StealArg::Quietest)]
+    steal: StealArg,
+
+    /// Extra peaks per frame, beyond one per voice, that compete for the
+    /// chips' channels under --steal (VGM output)
+    #[arg(long = "spare-peaks", default_value_t = 4)]
+    spare_peaks: usize,
+
+    /// Refine voice levels by resynthesizing each frame and comparing it
+    /// with the input spectrum, dropping voices that only add error
+    #[arg(long = "refine-levels")]
+    refine_levels: bool,
+
+    /// Most refinement passes per frame (implies --refine-levels)
+    #[arg(long = "refine-iterations")]
+    refine_iterations: Option<usize>,
+
+    /// Stop refining a frame once a pass improves the spectral distance by
+    /// less than this fraction (implies --refine-levels)
+    #[arg(long = "refine-tolerance")]
+    refine_tolerance: Option<f32>,
+
+    /// Render what the sinusoids leave behind (breath, fricatives) as noise:
+    /// filtered noise in WAV output, the YM2203 SSG noise channel in VGM output
+    #[arg(long = "residual-noise")]
+    residual_noise: bool,
+
+    /// Split analysis windows at detected onsets so key-on writes land on
+    /// attacks (prints the onset times)
+    #[arg(long = "onsets", value_enum)]
+    onsets: Option<OnsetArg>,
+
+    /// Crossfade WAV output between hops over this many samples instead of
+    /// gliding each voice's frequency and level across the hop
+    #[arg(long = "crossfade", default_value_t = 0)]
+    crossfade: usize,
+
+    /// Output level of WAV output. Syntax: name[:value]
+    /// Names: peak[:dbfs] (normalize, default -1), rms (match the input),
+    /// lufs (match the input's BS.1770 loudness), lufs:<target>, fixed:<dB>
+    #[arg(long = "gain", default_value = "peak")]
+    gain: GainArg,
+
+    /// Do not limit peaks of WAV output to -1 dBFS after applying the gain
+    #[arg(long = "no-limiter")]
+    no_limiter: bool,
+
+    /// How stereo input is analyzed: peaks of the mid signal placed by
+    /// channel levels, peaks of each channel merged, or a mono downmix
+    #[arg(long = "stereo", value_enum, default_value_t = StereoArg::MidSide)]
+    stereo: StereoArg,
+
+    /// Spread the voices of mono input across the stereo field by
+    /// frequency (0 = centred, 1 = full width); writes stereo WAV output
+    #[arg(long = "pan-spread", default_value_t = 0.0)]
+    pan_spread: f32,
+
+    /// Output sample rate (Hz) of the written WAV; the preview is rendered
+    /// at the analysis rate and resampled
     #[arg(short = 'r', long = "output-sample-rate", default_value_t = 44100)]
     output_sample_rate: usize,
 
-    /// Chip specifications. Can be given multiple times. Syntax: name[:count[:voices]]
-    /// Examples: --chip ymf262:1:18 --chip ym2203:2:3
+    /// Pre-processing stage run on the input before analysis. Can be given
+    /// multiple times; stages run in order. Syntax: name[:value[:value]]
+    /// Names: dc[:hz], highpass:<hz>[:q], lowpass:<hz>[:q],
+    /// preemphasis[:coef] (default 0.95), compress:<threshold dB>[:ratio]
+    /// (default 4), normalize[:dbfs] (default -1), gate:<threshold dB>
+    #[arg(long = "preprocess")]
+    preprocess: Vec<PreprocessArg>,
+
+    /// Resample the input to this rate (Hz) before analysis, so window and
+    /// hop sizes mean the same whatever the file's rate (defaults to the
+    /// input rate)
+    #[arg(long = "analysis-rate")]
+    analysis_rate: Option<usize>,
+
+    /// Chip specifications. Can be given multiple times. Syntax:
+    /// name[:count[:voices]][@clock], the clock in Hz defaulting to the
+    /// chip's usual one (all instances of a chip share one clock in a VGM)
+    /// Examples: --chip ymf262:1:18 --chip ym2203:2:3@3993600
     #[arg(long = "chip")]
     chip: Vec<ChipSpecArg>,
+
+    /// Sub-bin frequency estimator used for spectral peaks
+    #[arg(long = "freq-estimator", value_enum, default_value_t = EstimatorArg::Parabolic)]
+    freq_estimator: EstimatorArg,
+
+    /// Analysis window. Syntax: name[:param]
+    /// Names: hann, hamming, blackman, blackman-harris, kaiser[:beta], gaussian[:sigma], flat-top
+    #[arg(long = "window-function", default_value = "hann")]
+    window_function: WindowArg,
+
+    /// Discard peaks with less prominence than this (dB)
+    #[arg(long = "min-prominence-db")]
+    min_prominence_db: Option<f32>,
+
+    /// Discard peaks closer than this to a louder peak (cents)
+    #[arg(long = "min-spacing-cents")]
+    min_spacing_cents: Option<f32>,
+
+    /// Discard peaks less than this far above the median noise floor (dB)
+    #[arg(long = "noise-floor-db")]
+    noise_floor_db: Option<f32>,
+
+    /// Discard peaks whose neighbourhood spectral flatness exceeds this (0..1)
+    #[arg(long = "max-flatness")]
+    max_flatness: Option<f32>,
+
+    /// How peaks compete for voices: loudest first, or by margin above the
+    /// psychoacoustic masking threshold
+    #[arg(long = "peak-selection", value_enum, default_value_t = SelectionArg::Magnitude)]
+    peak_selection: SelectionArg,
+
+    /// Lowest peak frequency to analyze (Hz). Defaults to the chips' playable range
+    #[arg(long = "min-freq")]
+    min_freq: Option<f32>,
+
+    /// Highest peak frequency to analyze (Hz). Defaults to the chips' playable range
+    #[arg(long = "max-freq")]
+    max_freq: Option<f32>,
 }
 
 #[derive(ValueEnum, Clone, Debug)]
@@ -40,18 +212,303 @@ enum Format {
     Vgm,
 }
 
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum ModeArg {
+    Peaks,
+    Formants,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum StereoArg {
+    MidSide,
+    PerChannel,
+    Mono,
+}
+
+impl StereoArg {
+    fn analysis(self) -> Option<StereoAnalysis> {
+        match self {
+            StereoArg::MidSide => Some(StereoAnalysis::MidSide),
+            StereoArg::PerChannel => Some(StereoAnalysis::PerChannel),
+            StereoArg::Mono => None,
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum EstimatorArg {
+    Bin,
+    Parabolic,
+    Jain,
+    PhaseVocoder,
+}
+
+impl From<EstimatorArg> for FrequencyEstimator {
+    fn from(arg: EstimatorArg) -> Self {
+        match arg {
+            EstimatorArg::Bin => FrequencyEstimator::Bin,
+            EstimatorArg::Parabolic => FrequencyEstimator::Parabolic,
+            EstimatorArg::Jain => FrequencyEstimator::Jain,
+            EstimatorArg::PhaseVocoder => FrequencyEstimator::PhaseVocoder,
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum OnsetArg {
+    SpectralFlux,
+    ComplexDomain,
+}
+
+impl From<OnsetArg> for OnsetMethod {
+    fn from(arg: OnsetArg) -> Self {
+        match arg {
+            OnsetArg::SpectralFlux => OnsetMethod::SpectralFlux,
+            OnsetArg::ComplexDomain => OnsetMethod::ComplexDomain,
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum AssignmentArg {
+    Greedy,
+    Optimal,
+}
+
+impl From<AssignmentArg> for AssignmentMode {
+    fn from(arg: AssignmentArg) -> Self {
+        match arg {
+            AssignmentArg::Greedy => AssignmentMode::Greedy,
+            AssignmentArg::Optimal => AssignmentMode::Optimal,
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum StealArg {
+    Quietest,
+    Oldest,
+    FurthestPitch,
+}
+
+impl From<StealArg> for StealPolicy {
+    fn from(arg: StealArg) -> Self {
+        match arg {
+            StealArg::Quietest => StealPolicy::Quietest,
+            StealArg::Oldest => StealPolicy::Oldest,
+            StealArg::FurthestPitch => StealPolicy::FurthestPitch,
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum SelectionArg {
+    Magnitude,
+    Masking,
+}
+
+impl From<SelectionArg> for PeakSelection {
+    fn from(arg: SelectionArg) -> Self {
+        match arg {
+            SelectionArg::Magnitude => PeakSelection::Magnitude,
+            SelectionArg::Masking => PeakSelection::Masking,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct WindowArg(WindowFunction);
+
+impl FromStr for WindowArg {
+    type Err = String;
+
+    // Syntax: name[:param] e.g. "hann", "kaiser:8.6" or "gaussian:0.4".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, param) = match s.split_once(':') {
+            Some((name, param)) => {
+                let value = param
+                    .parse::<f32>()
+                    .map_err(|e| format!("invalid window parameter: {}", e))?;
+                (name, Some(value))
+            }
+            None => (s, None),
+        };
+        let function = match name.to_lowercase().as_str() {
+            "hann" => WindowFunction::Hann,
+            "hamming" => WindowFunction::Hamming,
+            "blackman" => WindowFunction::Blackman,
+            "blackman-harris" => WindowFunction::BlackmanHarris,
+            "kaiser" => WindowFunction::Kaiser {
+                beta: param.unwrap_or(8.6),
+            },
+            "gaussian" => WindowFunction::Gaussian {
+                sigma: param.unwrap_or(0.4),
+            },
+            "flat-top" => WindowFunction::FlatTop,
+            other => return Err(format!("unknown window '{}'.", other)),
+        };
+        Ok(WindowArg(function))
+    }
+}
+
+#[derive(Debug, Clone)]
+struct GainArg(LoudnessTarget);
+
+impl FromStr for GainArg {
+    type Err = String;
+
+    // Syntax: name[:value] e.g. "peak", "peak:-3", "rms", "lufs" or "lufs:-23".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, value) = match s.split_once(':') {
+            Some((name, value)) => {
+                let value = value
+                    .parse::<f32>()
+                    .map_err(|e| format!("invalid gain value: {}", e))?;
+                (name, Some(value))
+            }
+            None => (s, None),
+        };
+        let target = match (name.to_lowercase().as_str(), value) {
+            ("peak", dbfs) => LoudnessTarget::Peak {
+                dbfs: dbfs.unwrap_or(-1.0),
+            },
+            ("rms", None) => LoudnessTarget::MatchRms,
+            ("lufs", None) => LoudnessTarget::MatchLoudness,
+            ("lufs", Some(lufs)) => LoudnessTarget::Loudness { lufs },
+            ("fixed", Some(gain_db)) => LoudnessTarget::Fixed { gain_db },
+            ("fixed", None) => return Err("fixed gain needs a value in dB".into()),
+            ("rms", Some(_)) => return Err("rms takes no value".into()),
+            (other, _) => return Err(format!("unknown gain mode '{}'.", other)),
+        };
+        Ok(GainArg(target))
+    }
+}
+
+#[derive(Debug, Clone)]
+struct KeyArg(Key);
+
+impl FromStr for KeyArg {
+    type Err = String;
+
+    // Syntax: tonic[:scale] e.g. "c", "a:minor" or "bb:major-pentatonic".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lower = s.to_lowercase();
+        let (tonic, scale) = lower.split_once(':').unwrap_or((lower.as_str(), "major"));
+        let mut chars = tonic.chars();
+        let natural = match chars.next() {
+            Some('c') => 0,
+            Some('d') => 2,
+            Some('e') => 4,
+            Some('f') => 5,
+            Some('g') => 7,
+            Some('a') => 9,
+            Some('b') => 11,
+            _ => return Err(format!("unknown tonic '{}'.", tonic)),
+        };
+        let tonic = match chars.as_str() {
+            "" => natural,
+            "#" => natural + 1,
+            "b" => natural + 11,
+            other => return Err(format!("unknown accidental '{}'.", other)),
+        } % 12;
+        let scale = match scale {
+            "major" => Scale::Major,
+            "minor" => Scale::NaturalMinor,
+            "harmonic-minor" => Scale::HarmonicMinor,
+            "major-pentatonic" => Scale::MajorPentatonic,
+            "minor-pentatonic" => Scale::MinorPentatonic,
+            "chromatic" => Scale::Chromatic,
+            other => return Err(format!("unknown scale '{}'.", other)),
+        };
+        Ok(KeyArg(Key { tonic, scale }))
+    }
+}
+
+#[derive(Debug, Clone)]
+struct PreprocessArg(FilterStage);
+
+impl FromStr for PreprocessArg {
+    type Err = String;
+
+    // Syntax: name[:value[:value]] e.g. "dc", "highpass:40", "lowpass:8000:0.7",
+    // "compress:-20:4" or "gate:-50".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(':');
+        let name = parts.next().unwrap_or_default().to_lowercase();
+        let values = parts
+            .map(|v| {
+                v.parse::<f32>()
+                    .map_err(|e| format!("invalid preprocess value: {}", e))
+            })
+            .collect::<Result<Vec<f32>, String>>()?;
+        let max_values = match name.as_str() {
+            "highpass" | "lowpass" | "compress" => 2,
+            _ => 1,
+        };
+        if values.len() > max_values {
+            return Err(format!("too many values for '{}'", name));
+        }
+        let first = values.first().copied();
+        let second = values.get(1).copied();
+        let stage = match (name.as_str(), first) {
+            ("dc", None) => FilterStage::dc_removal(),
+            ("dc", Some(cutoff_hz)) => FilterStage::DcRemoval { cutoff_hz },
+            ("highpass", Some(cutoff_hz)) => match second {
+                Some(q) => FilterStage::HighPass { cutoff_hz, q },
+                None => FilterStage::high_pass(cutoff_hz),
+            },
+            ("lowpass", Some(cutoff_hz)) => match second {
+                Some(q) => FilterStage::LowPass { cutoff_hz, q },
+                None => FilterStage::low_pass(cutoff_hz),
+            },
+            ("preemphasis", coefficient) => FilterStage::PreEmphasis {
+                coefficient: coefficient.unwrap_or(0.95),
+            },
+            ("compress", Some(threshold_db)) => {
+                FilterStage::compressor(threshold_db, second.unwrap_or(4.0))
+            }
+            ("normalize", peak_dbfs) => FilterStage::Normalize {
+                peak_dbfs: peak_dbfs.unwrap_or(-1.0),
+            },
+            ("gate", Some(threshold_db)) => FilterStage::gate(threshold_db),
+            ("highpass" | "lowpass", None) => {
+                return Err(format!("{} needs a cutoff frequency", name));
+            }
+            ("compress" | "gate", None) => {
+                return Err(format!("{} needs a threshold in dB", name));
+            }
+            (other, _) => return Err(format!("unknown preprocess stage '{}'.", other)),
+        };
+        Ok(PreprocessArg(stage))
+    }
+}
+
 #[derive(Debug, Clone)]
 struct ChipSpecArg {
     chip: Chip,
     count: usize,
     voices: usize,
+    clock: Option<u32>,
 }
 
 impl FromStr for ChipSpecArg {
     type Err = String;
 
-    // Syntax: name[:count[:voices]] e.g. "ymf262:1:18" or "ym2203:2:3" or "ymf262".
+    // Syntax: name[:count[:voices]][@clock] e.g. "ymf262:1:18",
+    // "ym2203:2:3@4000000" or "ymf262".
     fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (s, clock) = match s.split_once('@') {
+            Some((spec, clock)) => {
+                let clock = clock
+                    .parse::<u32>()
+                    .map_err(|e| format!("invalid clock: {}", e))?;
+                if clock == 0 {
+                    return Err("clock must be > 0".into());
+                }
+                (spec, Some(clock))
+            }
+            None => (s, None),
+        };
         let parts: Vec<&str> = s.split(':').collect();
         if parts.is_empty() {
             return Err("empty chip spec".into());
@@ -80,27 +537,53 @@ impl FromStr for ChipSpecArg {
             chip,
             count,
             voices,
+            clock,
         })
     }
 }
 
-fn read_wav_to_mono_f32(path: &str) -> Result<(Vec<f32>, usize), Box<dyn std::error::Error>> {
+// One (mono) or two (left, right) channels of samples.
+type Channels = Vec<Vec<f32>>;
+
+// Read a WAV file as one (mono) or two (left, right) channels of f32
+// samples; `downmix` always returns mono.
+fn read_wav_f32(
+    path: &str,
+    downmix: bool,
+) -> Result<(Channels, usize), Box<dyn std::error::Error>> {
     let mut reader = hound::WavReader::open(path)?;
     let spec = reader.spec();
     let sample_rate = spec.sample_rate as usize;
 
-    let out: Vec<f32> = match (spec.sample_format, spec.bits_per_sample) {
+    let channels = spec.channels as usize;
+    let split = |mono: Vec<f32>, stereo: (Vec<f32>, Vec<f32>)| {
+        if downmix || channels < 2 {
+            vec![mono]
+        } else {
+            vec![stereo.0, stereo.1]
+        }
+    };
+    let out: Vec<Vec<f32>> = match (spec.sample_format, spec.bits_per_sample) {
         (hound::SampleFormat::Int, 16) => {
             let samples: Vec<i16> = reader.samples::<i16>().map(|s| s.unwrap_or(0)).collect();
-            interleaved_to_mono(&samples, spec.channels as usize)
+            split(
+                interleaved_to_mono(&samples, channels),
+                interleaved_to_stereo(&samples, channels),
+            )
         }
         (hound::SampleFormat::Int, 24) | (hound::SampleFormat::Int, 32) => {
             let samples_i32: Vec<i32> = reader.samples::<i32>().map(|s| s.unwrap_or(0)).collect();
-            interleaved_to_mono(&samples_i32, spec.channels as usize)
+            split(
+                interleaved_to_mono(&samples_i32, channels),
+                interleaved_to_stereo(&samples_i32, channels),
+            )
         }
         (hound::SampleFormat::Float, 32) => {
             let samples_f32: Vec<f32> = reader.samples::<f32>().map(|s| s.unwrap_or(0.0)).collect();
-            interleaved_to_mono(&samples_f32, spec.channels as usize)
+            split(
+                interleaved_to_mono(&samples_f32, channels),
+                interleaved_to_stereo(&samples_f32, channels),
+            )
         }
         _ => {
             return Err(format!(
@@ -114,22 +597,26 @@ fn read_wav_to_mono_f32(path: &str) -> Result<(Vec<f32>, usize), Box<dyn std::er
     Ok((out, sample_rate))
 }
 
-fn write_mono_f32_wav(
+// Write `channels` (mono, or left and right) as an interleaved 16-bit WAV.
+fn write_f32_wav(
     path: &Path,
-    samples: &[f32],
+    channels: &[&[f32]],
     sample_rate: usize,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let spec = hound::WavSpec {
-        channels: 1,
+        channels: channels.len() as u16,
         sample_rate: sample_rate as u32,
         bits_per_sample: 16,
         sample_format: hound::SampleFormat::Int,
     };
     let mut writer = hound::WavWriter::create(path, spec)?;
-    for &s in samples {
-        let s_clamped = s.clamp(-1.0, 1.0);
-        let sample_i16 = (s_clamped * (i16::MAX as f32)) as i16;
-        writer.write_sample(sample_i16)?;
+    let frames = channels.iter().map(|c| c.len()).min().unwrap_or(0);
+    for idx in 0..frames {
+        for channel in channels {
+            let s_clamped = channel[idx].clamp(-1.0, 1.0);
+            let sample_i16 = (s_clamped * (i16::MAX as f32)) as i16;
+            writer.write_sample(sample_i16)?;
+        }
     }
     writer.finalize()?;
     Ok(())
@@ -141,24 +628,41 @@ fn generate_wav_file(
     window_size: usize,
     output_sample_rate: usize,
     chip_instances: &[(Chip, usize)],
+    options: &ResynthOptions,
+    downmix: bool,
 ) -> Result<(), Box<dyn std::error::Error>> {
     println!("Reading input WAV: {}", input);
 
-    let (buf, sample_rate) = match read_wav_to_mono_f32(input) {
+    let (buf, sample_rate) = match read_wav_f32(input, downmix) {
         Ok(v) => v,
         Err(e) => {
             eprintln!("failed to read WAV {}: {:?}", input, e);
             return Err(e);
         }
     };
+    print_onsets(InputChannels::from_channels(&buf), sample_rate, options);
 
-    let resynth = process_samples_resynth_multi(
-        &buf,
-        sample_rate,
-        window_size,
-        output_sample_rate,
-        chip_instances,
-    )?;
+    // stereo output for stereo input, or when spreading mono input
+    let resynth: Vec<Vec<f32>> = if buf.len() == 2 || options.stereo.mono_spread > 0.0 {
+        let (left, right) = process_stereo_resynth_multi(
+            InputChannels::from_channels(&buf),
+            sample_rate,
+            window_size,
+            output_sample_rate,
+            chip_instances,
+            options,
+        )?;
+        vec![left, right]
+    } else {
+        vec![process_samples_resynth_multi(
+            &buf[0],
+            sample_rate,
+            window_size,
+            output_sample_rate,
+            chip_instances,
+            options,
+        )?]
+    };
 
     let out_path = if let Some(p) = output {
         p
@@ -173,34 +677,47 @@ fn generate_wav_file(
     };
 
     println!("Resynth out path: {:?}", out_path);
-    write_mono_f32_wav(&out_path, &resynth, output_sample_rate)?;
+    let channels: Vec<&[f32]> = resynth.iter().map(|c| c.as_slice()).collect();
+    write_f32_wav(&out_path, &channels, output_sample_rate)?;
     println!("Wrote resynth WAV for {}", input);
 
     Ok(())
 }
 
+fn print_onsets(input: InputChannels, sample_rate: usize, options: &ResynthOptions) {
+    if options.onsets.is_some() {
+        let onsets = resynth_onsets(input, sample_rate, options);
+        let times: Vec<String> = onsets.iter().map(|o| format!("{:.3}", o.time_s)).collect();
+        println!("Onsets ({}): {}", onsets.len(), times.join(" "));
+    }
+}
+
 fn generate_vgm_file(
     input: &str,
     output: Option<PathBuf>,
     window_size: usize,
     chip_instances: &[(Chip, usize)],
+    options: &ResynthOptions,
+    downmix: bool,
 ) -> Result<(), Box<dyn std::error::Error>> {
     println!("Reading input WAV: {}", input);
 
-    let (buf, sample_rate) = match read_wav_to_mono_f32(input) {
+    let (buf, sample_rate) = match read_wav_f32(input, downmix) {
         Ok(v) => v,
         Err(e) => {
             eprintln!("failed to read WAV {}: {:?}", input, e);
             return Err(e);
         }
     };
+    print_onsets(InputChannels::from_channels(&buf), sample_rate, options);
 
-    let mut vgm = process_samples_resynth_multi_to_vgm(
-        &buf,
+    let mut vgm = process_stereo_resynth_multi_to_vgm(
+        InputChannels::from_channels(&buf),
         sample_rate,
         window_size,
         0x16, // max_tl
         chip_instances,
+        options,
     )?;
 
     let track_name = Path::new(input)
@@ -238,6 +755,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = Args::parse();
 
     let mut chip_instances: Vec<(Chip, usize)> = Vec::new();
+    let mut master_clocks: Vec<Option<u32>> = Vec::new();
     if args.chip.is_empty() {
         // default: one ymf262 18 voices, two ym2203 3 voices
         chip_instances.push((Chip::Ymf262, 18));
@@ -247,10 +765,67 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         for spec in args.chip.into_iter() {
             for _ in 0..spec.count {
                 chip_instances.push((spec.chip.clone(), spec.voices));
+                master_clocks.push(spec.clock);
             }
         }
     }
 
+    let mut options = ResynthOptions::default();
+    options.analysis.estimator = args.freq_estimator.into();
+    options.analysis.window = args.window_function.0;
+    options.hop_size = args.hop_size;
+    options.analysis_sample_rate = args.analysis_rate;
+    options.preprocess = args.preprocess.iter().map(|stage| stage.0).collect();
+    options.quantize = (args.quantize || args.key.is_some()).then(|| QuantizeOptions {
+        key: args.key.as_ref().map(|key| key.0).unwrap_or_default(),
+        hysteresis_cents: args.note_hysteresis,
+    });
+    options.speech = (args.mode == ModeArg::Formants).then(|| SpeechOptions {
+        f0: args.formant_f0.then(F0Options::default),
+        ..Default::default()
+    });
+    options.tracking = args.track_partials.then(TrackingOptions::default);
+    options.assignment.mode = args.assignment.into();
+    options.voices.steal = args.steal.into();
+    options.voices.spare_peaks = args.spare_peaks;
+    options.master_clocks = master_clocks;
+    options.residual = args.residual_noise.then(ResidualOptions::default);
+    let refine =
+        args.refine_levels || args.refine_iterations.is_some() || args.refine_tolerance.is_some();
+    options.refine = refine.then(|| {
+        let defaults = RefineOptions::default();
+        RefineOptions {
+            iterations: args.refine_iterations.unwrap_or(defaults.iterations),
+            tolerance: args.refine_tolerance.unwrap_or(defaults.tolerance),
+            ..defaults
+        }
+    });
+    options.onsets = args.onsets.map(|method| OnsetOptions {
+        method: method.into(),
+        ..Default::default()
+    });
+    options.synthesis.overlap_samples = args.crossfade;
+    options.gain = GainOptions {
+        target: args.gain.0,
+        limiter: (!args.no_limiter).then(LimiterOptions::default),
+    };
+    options.analysis.picking.min_prominence_db = args.min_prominence_db;
+    options.analysis.picking.min_spacing = args.min_spacing_cents.map(PeakSpacing::Cents);
+    options.analysis.picking.min_above_noise_floor_db = args.noise_floor_db;
+    options.analysis.picking.max_flatness = args.max_flatness;
+    options.analysis.selection = args.peak_selection.into();
+    options.analysis.min_freq_hz = args.min_freq;
+    options.analysis.max_freq_hz = args.max_freq;
+    if args.multi_resolution {
+        options.analysis.resolution_bands = default_resolution_bands();
+    }
+
+    let downmix = args.stereo == StereoArg::Mono;
+    if let Some(analysis) = args.stereo.analysis() {
+        options.stereo.analysis = analysis;
+    }
+    options.stereo.mono_spread = args.pan_spread;
+
     match args.format {
         Format::Wav => generate_wav_file(
             &args.input,
@@ -258,9 +833,16 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             args.window_size,
             args.output_sample_rate,
             &chip_instances,
+            &options,
+            downmix,
+        ),
+        Format::Vgm => generate_vgm_file(
+            &args.input,
+            args.output,
+            args.window_size,
+            &chip_instances,
+            &options,
+            downmix,
         ),
-        Format::Vgm => {
-            generate_vgm_file(&args.input, args.output, args.window_size, &chip_instances)
-        }
     }
 }
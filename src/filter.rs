@@ -0,0 +1,298 @@
+use std::f64::consts::PI;
+
+/// One stage of the pre-processing chain run on the input before analysis.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilterStage {
+    /// Remove DC offset with a one-pole high pass at `cutoff_hz`.
+    DcRemoval { cutoff_hz: f32 },
+    /// Second-order high pass (rumble, handling noise).
+    HighPass { cutoff_hz: f32, q: f32 },
+    /// Second-order low pass (hiss, content the chips cannot play).
+    LowPass { cutoff_hz: f32, q: f32 },
+    /// First-order pre-emphasis `y[n] = x[n] - coefficient * x[n - 1]`,
+    /// lifting the treble so quiet upper partials compete with the bass.
+    PreEmphasis { coefficient: f32 },
+    /// Feed-forward compressor: levels above `threshold_db` rise only
+    /// `1 / ratio` dB per dB, followed by `makeup_db` of gain.
+    Compressor {
+        threshold_db: f32,
+        ratio: f32,
+        attack_ms: f32,
+        release_ms: f32,
+        makeup_db: f32,
+    },
+    /// One gain for the whole signal, bringing its highest peak to
+    /// `peak_dbfs`.
+    Normalize { peak_dbfs: f32 },
+    /// Noise gate: mute the signal while its level stays below
+    /// `threshold_db`, opening within `attack_ms` and closing over
+    /// `release_ms` once it has been quiet for `hold_ms`.
+    Gate {
+        threshold_db: f32,
+        attack_ms: f32,
+        hold_ms: f32,
+        release_ms: f32,
+    },
+}
+
+impl FilterStage {
+    /// DC removal at 10 Hz.
+    pub fn dc_removal() -> Self {
+        FilterStage::DcRemoval { cutoff_hz: 10.0 }
+    }
+
+    /// Butterworth high pass at `cutoff_hz`.
+    pub fn high_pass(cutoff_hz: f32) -> Self {
+        FilterStage::HighPass {
+            cutoff_hz,
+            q: std::f32::consts::FRAC_1_SQRT_2,
+        }
+    }
+
+    /// Butterworth low pass at `cutoff_hz`.
+    pub fn low_pass(cutoff_hz: f32) -> Self {
+        FilterStage::LowPass {
+            cutoff_hz,
+            q: std::f32::consts::FRAC_1_SQRT_2,
+        }
+    }
+
+    /// Compressor above `threshold_db` at `ratio`, with 5 ms attack, 100 ms
+    /// release and no makeup gain.
+    pub fn compressor(threshold_db: f32, ratio: f32) -> Self {
+        FilterStage::Compressor {
+            threshold_db,
+            ratio,
+            attack_ms: 5.0,
+            release_ms: 100.0,
+            makeup_db: 0.0,
+        }
+    }
+
+    /// Gate below `threshold_db`, with 1 ms attack, 50 ms hold and 100 ms
+    /// release.
+    pub fn gate(threshold_db: f32) -> Self {
+        FilterStage::Gate {
+            threshold_db,
+            attack_ms: 1.0,
+            hold_ms: 50.0,
+            release_ms: 100.0,
+        }
+    }
+}
+
+/// Run `stages` in order over mono `samples`.
+pub fn preprocess(samples: &mut [f32], sample_rate: usize, stages: &[FilterStage]) {
+    preprocess_channels(&mut [samples], sample_rate, stages);
+}
+
+/// `preprocess` for several channels (left, right, ...). Filters run on
+/// each channel; level-dependent stages (compressor, normalizer, gate)
+/// follow the loudest channel and apply one gain to all, so the stereo
+/// image holds.
+pub fn preprocess_channels(
+    channels: &mut [&mut [f32]],
+    sample_rate: usize,
+    stages: &[FilterStage],
+) {
+    if sample_rate == 0 {
+        return;
+    }
+    let rate = sample_rate as f64;
+    for stage in stages {
+        match *stage {
+            FilterStage::DcRemoval { cutoff_hz } => {
+                let pole = (-2.0 * PI * cutoff_hz.max(0.0) as f64 / rate).exp();
+                for channel in channels.iter_mut() {
+                    let (mut x1, mut y1) = (0.0f64, 0.0f64);
+                    for sample in channel.iter_mut() {
+                        let x = *sample as f64;
+                        y1 = x - x1 + pole * y1;
+                        x1 = x;
+                        *sample = y1 as f32;
+                    }
+                }
+            }
+            FilterStage::HighPass { cutoff_hz, q } => {
+                let filter = Biquad::high_pass(cutoff_hz as f64, q as f64, rate);
+                run_biquad(channels, filter);
+            }
+            FilterStage::LowPass { cutoff_hz, q } => {
+                let filter = Biquad::low_pass(cutoff_hz as f64, q as f64, rate);
+                run_biquad(channels, filter);
+            }
+            FilterStage::PreEmphasis { coefficient } => {
+                for channel in channels.iter_mut() {
+                    let mut previous = 0.0f32;
+                    for sample in channel.iter_mut() {
+                        let x = *sample;
+                        *sample = x - coefficient * previous;
+                        previous = x;
+                    }
+                }
+            }
+            FilterStage::Compressor {
+                threshold_db,
+                ratio,
+                attack_ms,
+                release_ms,
+                makeup_db,
+            } => {
+                let attack = smoothing(attack_ms, rate);
+                let release = smoothing(release_ms, rate);
+                let slope = 1.0 - 1.0 / ratio.max(1.0);
+                let mut envelope = 0.0f32;
+                apply_gain(channels, |level| {
+                    let k = if level > envelope { attack } else { release };
+                    envelope += (level - envelope) * k;
+                    let over = to_db(envelope) - threshold_db;
+                    let reduction = if over > 0.0 { over * slope } else { 0.0 };
+                    from_db(makeup_db - reduction)
+                });
+            }
+            FilterStage::Normalize { peak_dbfs } => {
+                let peak = channels
+                    .iter()
+                    .flat_map(|c| c.iter())
+                    .fold(0.0f32, |m, x| m.max(x.abs()));
+                if peak > 0.0 {
+                    let gain = from_db(peak_dbfs) / peak;
+                    apply_gain(channels, |_| gain);
+                }
+            }
+            FilterStage::Gate {
+                threshold_db,
+                attack_ms,
+                hold_ms,
+                release_ms,
+            } => {
+                let threshold = from_db(threshold_db);
+                let attack = 1.0 / (attack_ms.max(0.0) / 1000.0 * rate as f32).max(1.0);
+                let release = 1.0 / (release_ms.max(0.0) / 1000.0 * rate as f32).max(1.0);
+                let hold = (hold_ms.max(0.0) / 1000.0 * rate as f32) as usize;
+                // the level is followed with a short release so the gate
+                // does not chatter within one cycle of a low tone
+                let follow = smoothing(10.0, rate);
+                let mut envelope = 0.0f32;
+                let mut quiet_for = usize::MAX;
+                let mut gain = 0.0f32;
+                apply_gain(channels, |level| {
+                    envelope = level.max(envelope + (level - envelope) * follow);
+                    if envelope >= threshold {
+                        quiet_for = 0;
+                    } else {
+                        quiet_for = quiet_for.saturating_add(1);
+                    }
+                    gain = if quiet_for <= hold {
+                        (gain + attack).min(1.0)
+                    } else {
+                        (gain - release).max(0.0)
+                    };
+                    gain
+                });
+            }
+        }
+    }
+}
+
+// Multiply each frame by `gain_at(level)`, where `level` is the frame's
+// highest absolute sample over all channels.
+fn apply_gain(channels: &mut [&mut [f32]], mut gain_at: impl FnMut(f32) -> f32) {
+    let len = channels.iter().map(|c| c.len()).min().unwrap_or(0);
+    for idx in 0..len {
+        let level = channels.iter().fold(0.0f32, |m, c| m.max(c[idx].abs()));
+        let gain = gain_at(level);
+        for channel in channels.iter_mut() {
+            channel[idx] *= gain;
+        }
+    }
+}
+
+fn run_biquad(channels: &mut [&mut [f32]], filter: Biquad) {
+    for channel in channels.iter_mut() {
+        let mut filter = filter;
+        for sample in channel.iter_mut() {
+            *sample = filter.process(*sample as f64) as f32;
+        }
+    }
+}
+
+// One-pole smoothing coefficient reaching 63 % of a step in `time_ms`.
+fn smoothing(time_ms: f32, rate: f64) -> f32 {
+    let samples = (time_ms.max(0.0) as f64 / 1000.0 * rate).max(1.0);
+    (1.0 - (-1.0 / samples).exp()) as f32
+}
+
+fn to_db(level: f32) -> f32 {
+    20.0 * level.max(1e-10).log10()
+}
+
+fn from_db(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
+
+/// Direct form I biquad, coefficients normalized by a0.
+#[derive(Debug, Clone, Copy)]
+pub struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    x: [f64; 2],
+    y: [f64; 2],
+}
+
+impl Biquad {
+    /// Biquad with feed-forward coefficients `b` and feedback coefficients
+    /// `a` (`a1`, `a2`; `a0` is 1).
+    pub fn new(b: [f64; 3], a: [f64; 2]) -> Self {
+        Biquad {
+            b,
+            a,
+            x: [0.0; 2],
+            y: [0.0; 2],
+        }
+    }
+
+    /// Second-order high pass (RBJ cookbook).
+    pub fn high_pass(cutoff_hz: f64, q: f64, sample_rate: f64) -> Self {
+        let (cos, alpha) = Self::prototype(cutoff_hz, q, sample_rate);
+        let a0 = 1.0 + alpha;
+        Biquad::new(
+            [
+                (1.0 + cos) / 2.0 / a0,
+                -(1.0 + cos) / a0,
+                (1.0 + cos) / 2.0 / a0,
+            ],
+            [-2.0 * cos / a0, (1.0 - alpha) / a0],
+        )
+    }
+
+    /// Second-order low pass (RBJ cookbook).
+    pub fn low_pass(cutoff_hz: f64, q: f64, sample_rate: f64) -> Self {
+        let (cos, alpha) = Self::prototype(cutoff_hz, q, sample_rate);
+        let a0 = 1.0 + alpha;
+        Biquad::new(
+            [
+                (1.0 - cos) / 2.0 / a0,
+                (1.0 - cos) / a0,
+                (1.0 - cos) / 2.0 / a0,
+            ],
+            [-2.0 * cos / a0, (1.0 - alpha) / a0],
+        )
+    }
+
+    // cos(w0) and alpha of the cookbook formulas; the cutoff is kept just
+    // below Nyquist.
+    fn prototype(cutoff_hz: f64, q: f64, sample_rate: f64) -> (f64, f64) {
+        let w0 = 2.0 * PI * cutoff_hz.clamp(1e-3, 0.49 * sample_rate) / sample_rate;
+        (w0.cos(), w0.sin() / (2.0 * q.max(1e-3)))
+    }
+
+    pub fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+            - self.a[0] * self.y[0]
+            - self.a[1] * self.y[1];
+        self.x = [x, self.x[0]];
+        self.y = [y, self.y[0]];
+        y
+    }
+}
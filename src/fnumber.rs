@@ -0,0 +1,140 @@
+use soundlog::chip::fnumber::{ChipTypeSpec, FNumber, FNumberEntry, FNumberError};
+use std::any::TypeId;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// 12-EDO F-number table of `soundlog::chip::fnumber::generate_12edo_fnum_table`,
+/// indexed `[block][semitone]`.
+pub type FNumberTable = [[Option<FNumberEntry>; 12]; 8];
+
+/// Closed-form F-number solver for one chip type at one master clock.
+///
+/// The chip frequency is linear in the F-number and doubles with each
+/// block (`freq = f_num × step × 2^block`), so the best F-number of a block
+/// is the rounded quotient of the target by that block's step, clamped to
+/// the register range. Every block is tried and the least error in cents
+/// wins; on a tie the lower block (the larger, finer F-number) is kept.
+/// Any frequency is solved directly, without going through a note table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FNumberSolver {
+    master_clock: f32,
+    fnum_max: u32,
+    // frequency of F-number 1 in each block (Hz)
+    steps: Vec<f64>,
+}
+
+impl FNumberSolver {
+    pub fn new<C: ChipTypeSpec>(master_clock: f32) -> Result<Self, FNumberError> {
+        let config = C::config();
+        let fnum_max = (1u32 << config.fnum_bits.min(31)) - 1;
+        let max_block = ((1u32 << config.block_bits) - 1).min(7) as u8;
+        let top = C::fnum_block_to_freq(fnum_max, 0, master_clock)?;
+        if !(top.is_finite() && top > 0.0) {
+            return Err(FNumberError::InvalidInput);
+        }
+        let base = top as f64 / fnum_max as f64;
+        let steps = (0..=max_block)
+            .map(|block| base * f64::from(1u32 << block))
+            .collect();
+        Ok(FNumberSolver {
+            master_clock,
+            fnum_max,
+            steps,
+        })
+    }
+
+    pub fn master_clock(&self) -> f32 {
+        self.master_clock
+    }
+
+    /// Band (Hz) the chip plays with full tuning resolution: from the
+    /// lowest frequency that still uses the upper half of the F-number range
+    /// in block 0 (below it the F-number steps grow coarser) to the largest
+    /// F-number in the highest block.
+    pub fn frequency_range(&self) -> (f32, f32) {
+        let half = (self.fnum_max / 2 + 1) as f64;
+        let lo = self.steps.first().copied().unwrap_or(0.0) * half;
+        let hi = self.steps.last().copied().unwrap_or(0.0) * self.fnum_max as f64;
+        (lo as f32, hi as f32)
+    }
+
+    /// Best block and F-number for `freq_hz`. Frequencies below F-number 1
+    /// in block 0 or above the largest F-number in the highest block get
+    /// the nearest of the two.
+    pub fn solve(&self, freq_hz: f32) -> Result<FNumber, FNumberError> {
+        if !(freq_hz.is_finite() && freq_hz > 0.0) {
+            return Err(FNumberError::InvalidInput);
+        }
+        let target = freq_hz as f64;
+        let mut best: Option<(f64, u32, u8, f64)> = None;
+        for (block, &step) in self.steps.iter().enumerate() {
+            let f_num = (target / step).round().clamp(1.0, self.fnum_max as f64) as u32;
+            let actual = f_num as f64 * step;
+            let cents = (1200.0 * (actual / target).log2()).abs();
+            // rounding noise must not favour a higher block at an equal pitch
+            if best.is_none_or(|(best_cents, ..)| cents < best_cents - 1e-9) {
+                best = Some((cents, f_num, block as u8, actual));
+            }
+        }
+        let (cents, f_num, block, actual) = best.ok_or(FNumberError::InvalidInput)?;
+        Ok(FNumber {
+            f_num,
+            block,
+            actual_freq_hz: actual as f32,
+            error_hz: (actual - target).abs() as f32,
+            error_cents: cents as f32,
+        })
+    }
+}
+
+type Cache<T> = OnceLock<Mutex<HashMap<(TypeId, u32), Arc<T>>>>;
+
+// The entry of chip `C` at `master_clock` in `cache`, built on first use.
+fn cached<C: 'static, T>(
+    cache: &Cache<T>,
+    master_clock: f32,
+    build: impl FnOnce() -> Result<T, FNumberError>,
+) -> Result<Arc<T>, FNumberError> {
+    let key = (TypeId::of::<C>(), master_clock.to_bits());
+    let mut entries = cache
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    if let Some(entry) = entries.get(&key) {
+        return Ok(entry.clone());
+    }
+    let entry = Arc::new(build()?);
+    entries.insert(key, entry.clone());
+    Ok(entry)
+}
+
+/// The `FNumberSolver` of chip `C` at `master_clock`, computed once per
+/// process and shared.
+pub fn fnumber_solver<C: ChipTypeSpec + 'static>(
+    master_clock: f32,
+) -> Result<Arc<FNumberSolver>, FNumberError> {
+    static SOLVERS: Cache<FNumberSolver> = OnceLock::new();
+    cached::<C, _>(&SOLVERS, master_clock, || {
+        FNumberSolver::new::<C>(master_clock)
+    })
+}
+
+/// Best block and F-number of chip `C` at `master_clock` for `freq_hz`
+/// (see `FNumberSolver::solve`).
+pub fn solve_fnumber<C: ChipTypeSpec + 'static>(
+    freq_hz: f32,
+    master_clock: f32,
+) -> Result<FNumber, FNumberError> {
+    fnumber_solver::<C>(master_clock)?.solve(freq_hz)
+}
+
+/// The 12-EDO table of chip `C` at `master_clock`, generated once per
+/// process and shared.
+pub fn fnumber_table<C: ChipTypeSpec + 'static>(
+    master_clock: f32,
+) -> Result<Arc<FNumberTable>, FNumberError> {
+    static TABLES: Cache<FNumberTable> = OnceLock::new();
+    cached::<C, _>(&TABLES, master_clock, || {
+        soundlog::chip::fnumber::generate_12edo_fnum_table::<C>(master_clock)
+    })
+}
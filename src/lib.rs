@@ -1,3 +1,12 @@
+pub mod assign;
+pub mod filter;
+pub mod fnumber;
+pub mod loudness;
 pub mod pcm;
+pub mod quantize;
+pub mod refine;
+pub mod resample;
 pub mod resynth;
+pub mod track;
+pub mod voice;
 pub mod ym;
@@ -0,0 +1,312 @@
+use crate::filter::Biquad;
+
+/// Level the resynthesized output is brought to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LoudnessTarget {
+    /// Normalize the whole output so its highest peak sits at `dbfs`.
+    Peak { dbfs: f32 },
+    /// Match the RMS level of the input.
+    MatchRms,
+    /// Match the integrated loudness (ITU-R BS.1770) of the input.
+    MatchLoudness,
+    /// Reach an absolute integrated loudness in LUFS.
+    Loudness { lufs: f32 },
+    /// Apply a fixed gain, whatever the level.
+    Fixed { gain_db: f32 },
+}
+
+impl Default for LoudnessTarget {
+    fn default() -> Self {
+        LoudnessTarget::Peak { dbfs: -1.0 }
+    }
+}
+
+/// Lookahead peak limiter settings (see `limit`).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LimiterOptions {
+    /// Highest absolute sample value allowed, in dBFS.
+    pub ceiling_dbfs: f32,
+    /// Time the gain takes to ramp down ahead of a peak (ms).
+    pub lookahead_ms: f32,
+    /// Time constant of the gain recovering after a peak (ms).
+    pub release_ms: f32,
+}
+
+impl Default for LimiterOptions {
+    fn default() -> Self {
+        LimiterOptions {
+            ceiling_dbfs: -1.0,
+            lookahead_ms: 5.0,
+            release_ms: 50.0,
+        }
+    }
+}
+
+/// Output gain stage: one gain for the whole output, chosen to reach
+/// `target`, followed by an optional limiter.
+///
+/// Because the gain is constant, quiet passages of the input stay quiet
+/// relative to loud ones.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GainOptions {
+    pub target: LoudnessTarget,
+    /// Limit peaks after the gain is applied. `None` leaves the signal as
+    /// is, which may clip when matching a level.
+    pub limiter: Option<LimiterOptions>,
+}
+
+impl Default for GainOptions {
+    fn default() -> Self {
+        GainOptions {
+            target: LoudnessTarget::default(),
+            limiter: Some(LimiterOptions::default()),
+        }
+    }
+}
+
+/// RMS level of `samples` in dBFS (a full-scale sine reads -3.01 dB).
+/// Silence reads negative infinity.
+pub fn rms_dbfs(samples: &[f32]) -> f32 {
+    channels_rms_dbfs(&[samples])
+}
+
+/// Highest absolute sample value of `samples` in dBFS.
+pub fn peak_dbfs(samples: &[f32]) -> f32 {
+    let peak = samples.iter().fold(0.0f32, |m, &x| m.max(x.abs()));
+    20.0 * peak.log10()
+}
+
+// BS.1770 K-weighting (high shelf, then high pass), derived for any sample
+// rate from the analog prototypes of the 48 kHz reference coefficients.
+fn k_weighting(sample_rate: f64) -> [Biquad; 2] {
+    let shelf = {
+        let (f0, gain_db, q) = (
+            1_681.974_450_955_533,
+            3.999_843_853_973_347,
+            0.707_175_236_955_419_6,
+        );
+        let k = (std::f64::consts::PI * f0 / sample_rate).tan();
+        let vh = 10f64.powf(gain_db / 20.0);
+        let vb = vh.powf(0.499_666_774_154_541_6);
+        let a0 = 1.0 + k / q + k * k;
+        Biquad::new(
+            [
+                (vh + vb * k / q + k * k) / a0,
+                2.0 * (k * k - vh) / a0,
+                (vh - vb * k / q + k * k) / a0,
+            ],
+            [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        )
+    };
+    let high_pass = {
+        let (f0, q) = (38.135_470_876_024_44, 0.500_327_037_323_877_3);
+        let k = (std::f64::consts::PI * f0 / sample_rate).tan();
+        let a0 = 1.0 + k / q + k * k;
+        Biquad::new(
+            [1.0, -2.0, 1.0],
+            [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        )
+    };
+    [shelf, high_pass]
+}
+
+/// Integrated loudness of mono `samples` in LUFS (ITU-R BS.1770-4).
+///
+/// The K-weighted signal is measured in 400 ms blocks overlapping by 75 %;
+/// blocks below -70 LUFS, then blocks more than 10 LU below the loudness
+/// of the remaining ones, are gated out. Inputs shorter than one block are
+/// measured as a single block. Silence reads negative infinity.
+pub fn integrated_loudness(samples: &[f32], sample_rate: usize) -> f32 {
+    integrated_loudness_channels(&[samples], sample_rate)
+}
+
+/// Integrated loudness of several channels (left, right, ...) played
+/// together; the mean-square power of every channel adds up with unit
+/// weight, as for the front channels of BS.1770.
+pub fn integrated_loudness_channels(channels: &[&[f32]], sample_rate: usize) -> f32 {
+    let len = channels.iter().map(|c| c.len()).min().unwrap_or(0);
+    if len == 0 || sample_rate == 0 {
+        return f32::NEG_INFINITY;
+    }
+    let mut squares = vec![0.0f64; len];
+    for channel in channels {
+        let mut filters = k_weighting(sample_rate as f64);
+        for (square, &x) in squares.iter_mut().zip(channel.iter()) {
+            let y = filters
+                .iter_mut()
+                .fold(x as f64, |acc, filter| filter.process(acc));
+            *square += y * y;
+        }
+    }
+
+    let block = (sample_rate * 2 / 5).min(len);
+    let step = (sample_rate / 10).max(1);
+    let blocks: Vec<f64> = (0..=len - block)
+        .step_by(step)
+        .map(|start| squares[start..start + block].iter().sum::<f64>() / block as f64)
+        .collect();
+
+    let loudness = |z: f64| -0.691 + 10.0 * z.log10();
+    let gated_mean = |threshold: f64| {
+        let passed: Vec<f64> = blocks
+            .iter()
+            .copied()
+            .filter(|&z| loudness(z) > threshold)
+            .collect();
+        (!passed.is_empty()).then(|| passed.iter().sum::<f64>() / passed.len() as f64)
+    };
+    let Some(absolute) = gated_mean(-70.0) else {
+        return f32::NEG_INFINITY;
+    };
+    let relative = gated_mean(loudness(absolute) - 10.0).unwrap_or(absolute);
+    loudness(relative) as f32
+}
+
+/// Keep `samples` within the limiter ceiling without clipping.
+///
+/// The gain needed at each sample is reached by a linear ramp that starts
+/// `lookahead_ms` ahead of it, and recovers exponentially with
+/// `release_ms` afterwards. Samples well below the ceiling pass unchanged.
+pub fn limit(samples: &mut [f32], sample_rate: usize, options: &LimiterOptions) {
+    limit_channels(&mut [samples], sample_rate, options);
+}
+
+/// `limit` for several channels sharing one gain, so the stereo image
+/// does not move while a peak in one channel is limited.
+pub fn limit_channels(channels: &mut [&mut [f32]], sample_rate: usize, options: &LimiterOptions) {
+    let ceiling = 10f32.powf(options.ceiling_dbfs / 20.0);
+    let len = channels.iter().map(|c| c.len()).min().unwrap_or(0);
+    let required: Vec<f32> = (0..len)
+        .map(|idx| {
+            let x = channels.iter().fold(0.0f32, |m, c| m.max(c[idx].abs()));
+            if x > ceiling { ceiling / x } else { 1.0 }
+        })
+        .collect();
+    // positions that need gain reduction
+    let limited: Vec<usize> = (0..required.len())
+        .filter(|&idx| required[idx] < 1.0)
+        .collect();
+    if limited.is_empty() {
+        return;
+    }
+
+    let rate = sample_rate.max(1) as f32;
+    let lookahead = ((options.lookahead_ms / 1000.0 * rate) as usize).max(1);
+    let release = 1.0 - (-1.0 / (options.release_ms.max(1e-3) / 1000.0 * rate)).exp();
+    let mut gain = 1.0f32;
+    let mut next = 0usize;
+    for idx in 0..len {
+        while next < limited.len() && limited[next] < idx {
+            next += 1;
+        }
+        let ramp = limited[next..]
+            .iter()
+            .take_while(|&&pos| pos < idx + lookahead)
+            .map(|&pos| {
+                let g = required[pos];
+                g + (1.0 - g) * (pos - idx) as f32 / lookahead as f32
+            })
+            .fold(1.0f32, f32::min);
+        gain = (gain + (1.0 - gain) * release).min(ramp);
+        for channel in channels.iter_mut() {
+            channel[idx] = (channel[idx] * gain).clamp(-ceiling, ceiling);
+        }
+    }
+}
+
+/// Apply the output gain stage to `output` (at `output_rate`), measuring
+/// `reference` (the input, at `reference_rate`) when the target matches
+/// its level. Returns the gain applied before limiting, in dB. Silent
+/// signals are left untouched (0 dB).
+pub fn apply_output_gain(
+    output: &mut [f32],
+    output_rate: usize,
+    reference: &[f32],
+    reference_rate: usize,
+    options: &GainOptions,
+) -> f32 {
+    apply_output_gain_channels(
+        &mut [output],
+        output_rate,
+        &[reference],
+        reference_rate,
+        options,
+    )
+}
+
+/// `apply_output_gain` for multi-channel output and reference: levels
+/// are measured over all channels together and one gain applies to all.
+/// A reference with another channel count is first brought to the
+/// output's: a mono reference plays on every output channel (dual mono),
+/// and a multi-channel one is mixed down for mono output.
+pub fn apply_output_gain_channels(
+    outputs: &mut [&mut [f32]],
+    output_rate: usize,
+    references: &[&[f32]],
+    reference_rate: usize,
+    options: &GainOptions,
+) -> f32 {
+    let mixdown: Vec<f32>;
+    let references: Vec<&[f32]> = match (references, outputs.len()) {
+        ([mono], channels) if channels > 1 => vec![*mono; channels],
+        (channels, 1) if channels.len() > 1 => {
+            mixdown = mix_down(channels);
+            vec![&mixdown]
+        }
+        (channels, _) => channels.to_vec(),
+    };
+    let references = references.as_slice();
+    let measured: Vec<&[f32]> = outputs.iter().map(|c| &**c).collect();
+    let gain_db = match options.target {
+        LoudnessTarget::Peak { dbfs } => dbfs - channels_peak_dbfs(&measured),
+        LoudnessTarget::MatchRms => channels_rms_dbfs(references) - channels_rms_dbfs(&measured),
+        LoudnessTarget::MatchLoudness => {
+            integrated_loudness_channels(references, reference_rate)
+                - integrated_loudness_channels(&measured, output_rate)
+        }
+        LoudnessTarget::Loudness { lufs } => {
+            lufs - integrated_loudness_channels(&measured, output_rate)
+        }
+        LoudnessTarget::Fixed { gain_db } => gain_db,
+    };
+    let gain_db = if gain_db.is_finite() { gain_db } else { 0.0 };
+    let gain = 10f32.powf(gain_db / 20.0);
+    for channel in outputs.iter_mut() {
+        for sample in channel.iter_mut() {
+            *sample *= gain;
+        }
+    }
+    if let Some(limiter) = &options.limiter {
+        limit_channels(outputs, output_rate, limiter);
+    }
+    gain_db
+}
+
+// Average of the channels, sample by sample.
+fn mix_down(channels: &[&[f32]]) -> Vec<f32> {
+    let len = channels.iter().map(|c| c.len()).min().unwrap_or(0);
+    let scale = 1.0 / channels.len().max(1) as f32;
+    (0..len)
+        .map(|i| channels.iter().map(|c| c[i]).sum::<f32>() * scale)
+        .collect()
+}
+
+// RMS over the samples of all channels.
+fn channels_rms_dbfs(channels: &[&[f32]]) -> f32 {
+    let len: usize = channels.iter().map(|c| c.len()).sum();
+    if len == 0 {
+        return f32::NEG_INFINITY;
+    }
+    let sum: f64 = channels
+        .iter()
+        .map(|c| c.iter().map(|&x| (x as f64) * (x as f64)).sum::<f64>())
+        .sum();
+    (10.0 * (sum / len as f64).log10()) as f32
+}
+
+fn channels_peak_dbfs(channels: &[&[f32]]) -> f32 {
+    channels
+        .iter()
+        .map(|c| peak_dbfs(c))
+        .fold(f32::NEG_INFINITY, f32::max)
+}
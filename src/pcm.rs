@@ -1,104 +1,872 @@
-use rustfft::{FftPlanner, num_complex::Complex};
+mod f0;
+mod formant;
+mod masking;
+mod onset;
+mod oscillator;
+mod residual;
+mod stereo;
+
+pub use f0::{
+    F0Estimate, F0Estimator, F0Method, F0Options, Harmonic, estimate_f0, group_harmonics,
+};
+pub use formant::{Formant, FormantAnalyzer, FormantOptions, estimate_formants, lpc_coefficients};
+pub use masking::hz_to_bark;
+pub use onset::{Onset, OnsetMethod, OnsetOptions, detect_onsets, onset_detection_function};
+pub use oscillator::{OscillatorBank, OscillatorOptions};
+pub use residual::{
+    DEFAULT_NOISE_BAND_EDGES_HZ, NoiseSynthesizer, ResidualAnalyzer, ResidualOptions,
+};
+pub use stereo::{
+    StereoAnalysis, StereoOptions, estimate_pan, merge_channel_peaks, pan_from_levels,
+    pan_to_levels, spread_pan,
+};
+
+use masking::MaskingModel;
+
+use rustfft::{Fft, FftPlanner, num_complex::Complex};
 use std::f32::consts::PI;
+use std::sync::Arc;
 
 /// A detected spectral peak.
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Peak {
-    /// Frequency in Hz
+    /// Frequency in Hz (refined below bin resolution by the selected `FrequencyEstimator`)
     pub freq_hz: f32,
-    /// Linear magnitude (sqrt(re^2+im^2))
+    /// Linear amplitude, refined together with the frequency. Calibrated so
+    /// a full-scale sine reads 1.0 for any window size, FFT size and window
+    /// function.
     pub magnitude: f32,
-    /// Magnitude in dB (20*log10), approximate; -inf mapped to -200.0
+    /// Magnitude in dBFS (20*log10), approximate; -inf mapped to -200.0
     pub magnitude_db: f32,
     /// FFT bin index
     pub bin: usize,
 }
 
+/// Method used to estimate a peak's frequency between FFT bins.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FrequencyEstimator {
+    /// Report the centre frequency of the peak bin (no refinement).
+    Bin,
+    /// Quadratic interpolation of the log magnitude around the peak bin
+    /// (QIFFT). Also refines the peak magnitude.
+    #[default]
+    Parabolic,
+    /// Jain's interpolation using the ratio of the peak bin to its larger
+    /// neighbour.
+    Jain,
+    /// Phase-vocoder instantaneous frequency from the phase advance between
+    /// two overlapping frames taken from the analyzed samples.
+    PhaseVocoder,
+}
+
+/// Analysis window applied to each frame before the FFT.
+///
+/// Peak magnitudes are normalized by the window's coefficient sum, so the
+/// same sinusoid reports the same `Peak::magnitude` whichever window is used.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum WindowFunction {
+    #[default]
+    Hann,
+    Hamming,
+    Blackman,
+    /// 4-term Blackman-Harris (-92 dB sidelobes).
+    BlackmanHarris,
+    /// Kaiser window with shape parameter `beta` (larger = lower sidelobes,
+    /// wider main lobe).
+    Kaiser {
+        beta: f32,
+    },
+    /// Gaussian window; `sigma` is the standard deviation relative to half
+    /// the window length (typically 0.3..0.5).
+    Gaussian {
+        sigma: f32,
+    },
+    /// Flat-top window: very low scalloping loss for accurate amplitudes.
+    FlatTop,
+}
+
+impl WindowFunction {
+    /// Window coefficients for a frame of `len` samples.
+    pub fn coefficients(&self, len: usize) -> Vec<f32> {
+        if len <= 1 {
+            return vec![1.0; len];
+        }
+        let denom = (len - 1) as f64;
+        (0..len)
+            .map(|idx| {
+                let x = 2.0 * std::f64::consts::PI * (idx as f64) / denom;
+                let w = match *self {
+                    WindowFunction::Hann => cosine_sum(x, &[0.5, 0.5]),
+                    WindowFunction::Hamming => cosine_sum(x, &[0.54, 0.46]),
+                    WindowFunction::Blackman => cosine_sum(x, &[0.42, 0.5, 0.08]),
+                    WindowFunction::BlackmanHarris => {
+                        cosine_sum(x, &[0.35875, 0.48829, 0.14128, 0.01168])
+                    }
+                    WindowFunction::FlatTop => cosine_sum(
+                        x,
+                        &[
+                            0.21557895,
+                            0.41663158,
+                            0.277263158,
+                            0.083578947,
+                            0.006947368,
+                        ],
+                    ),
+                    WindowFunction::Kaiser { beta } => {
+                        let beta = beta as f64;
+                        let r = 2.0 * (idx as f64) / denom - 1.0;
+                        bessel_i0(beta * (1.0 - r * r).max(0.0).sqrt()) / bessel_i0(beta)
+                    }
+                    WindowFunction::Gaussian { sigma } => {
+                        let half = denom / 2.0;
+                        let r = ((idx as f64) - half) / ((sigma as f64).max(1e-3) * half);
+                        (-0.5 * r * r).exp()
+                    }
+                };
+                w as f32
+            })
+            .collect()
+    }
+
+    /// Coherent (DC) gain of the window: the mean of its coefficients.
+    pub fn coherent_gain(&self, len: usize) -> f32 {
+        if len == 0 {
+            return 1.0;
+        }
+        let coeffs = self.coefficients(len);
+        coeffs.iter().sum::<f32>() / (len as f32)
+    }
+}
+
+// Generalized cosine-sum window: a0 - a1 cos(x) + a2 cos(2x) - ...
+fn cosine_sum(x: f64, coeffs: &[f64]) -> f64 {
+    coeffs
+        .iter()
+        .enumerate()
+        .map(|(k, &a)| {
+            let sign = if k % 2 == 0 { 1.0 } else { -1.0 };
+            sign * a * ((k as f64) * x).cos()
+        })
+        .sum()
+}
+
+// Zeroth-order modified Bessel function of the first kind (series expansion).
+pub(crate) fn bessel_i0(x: f64) -> f64 {
+    let half_sq = (x / 2.0) * (x / 2.0);
+    let mut term = 1.0;
+    let mut sum = 1.0;
+    for k in 1..64 {
+        term *= half_sq / ((k * k) as f64);
+        sum += term;
+        if term < sum * 1e-12 {
+            break;
+        }
+    }
+    sum
+}
+
+/// Minimum distance between two returned peaks.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PeakSpacing {
+    Hz(f32),
+    Cents(f32),
+}
+
+impl PeakSpacing {
+    fn too_close(&self, a_hz: f32, b_hz: f32) -> bool {
+        match *self {
+            PeakSpacing::Hz(hz) => (a_hz - b_hz).abs() < hz,
+            PeakSpacing::Cents(cents) => {
+                if a_hz <= 0.0 || b_hz <= 0.0 {
+                    return false;
+                }
+                (1200.0 * (a_hz / b_hz).log2()).abs() < cents
+            }
+        }
+    }
+}
+
+/// Local-maximum filtering applied before the strongest peaks are taken.
+///
+/// Every criterion is optional; the default keeps every local maximum, which
+/// matches plain "loudest N bins" picking.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PeakPicking {
+    /// Minimum topographic prominence in dB (height above the higher of the
+    /// two valleys separating the peak from any taller one).
+    pub min_prominence_db: Option<f32>,
+    /// Minimum spacing to any stronger, already accepted peak.
+    pub min_spacing: Option<PeakSpacing>,
+    /// Minimum height in dB above the adaptive noise floor (median of the
+    /// magnitude spectrum over `neighborhood_bins`).
+    pub min_above_noise_floor_db: Option<f32>,
+    /// Maximum spectral flatness (0 = pure tone, 1 = white noise) of the
+    /// `neighborhood_bins` around the peak.
+    pub max_flatness: Option<f32>,
+    /// Width in bins of the neighbourhood used by the noise floor and
+    /// flatness checks.
+    pub neighborhood_bins: usize,
+    /// Record rejected candidates in `PeakAnalysis::rejected`.
+    pub debug: bool,
+}
+
+impl Default for PeakPicking {
+    fn default() -> Self {
+        PeakPicking {
+            min_prominence_db: None,
+            min_spacing: None,
+            min_above_noise_floor_db: None,
+            max_flatness: None,
+            neighborhood_bins: 31,
+            debug: false,
+        }
+    }
+}
+
+/// How the accepted candidates are ranked against `max_peaks`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PeakSelection {
+    /// Loudest peaks first.
+    #[default]
+    Magnitude,
+    /// Peaks ranked by how far they exceed the psychoacoustic masking
+    /// threshold of the rest of the frame; fully masked peaks are dropped.
+    Masking,
+}
+
+/// A frequency band analyzed with its own window length (see
+/// `AnalysisOptions::resolution_bands`).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ResolutionBand {
+    /// Upper edge of the band (Hz). The band starts at the previous band's
+    /// edge (0 Hz for the first); the last band extends to Nyquist.
+    pub max_freq_hz: f32,
+    /// Window length in samples, taken from the centre of the analyzed
+    /// frame (clamped to the frame length).
+    pub window_size: usize,
+}
+
+/// Long windows for bass, short ones for treble: 8192 samples below 250 Hz
+/// down to 512 samples above 4 kHz.
+pub fn default_resolution_bands() -> Vec<ResolutionBand> {
+    vec![
+        ResolutionBand {
+            max_freq_hz: 250.0,
+            window_size: 8192,
+        },
+        ResolutionBand {
+            max_freq_hz: 1000.0,
+            window_size: 4096,
+        },
+        ResolutionBand {
+            max_freq_hz: 4000.0,
+            window_size: 1024,
+        },
+        ResolutionBand {
+            max_freq_hz: f32::MAX,
+            window_size: 512,
+        },
+    ]
+}
+
+/// Why a local maximum was not returned as a peak.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RejectReason {
+    /// Outside `AnalysisOptions::min_freq_hz..=max_freq_hz`.
+    OutOfRange,
+    Prominence,
+    Spacing,
+    NoiseFloor,
+    Flatness,
+    /// Below the masking threshold (`PeakSelection::Masking` only).
+    Masked,
+    /// Passed every filter but `max_peaks` stronger peaks were already taken.
+    MaxPeaks,
+}
+
+/// A candidate rejected by `PeakPicking`, recorded when `debug` is set.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RejectedPeak {
+    pub peak: Peak,
+    pub reason: RejectReason,
+}
+
+/// Result of `SpectralAnalyzer::analyze_detailed`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PeakAnalysis {
+    /// Accepted peaks, strongest (or, with `PeakSelection::Masking`, most
+    /// audible) first.
+    pub peaks: Vec<Peak>,
+    /// Rejected candidates (only filled when `PeakPicking::debug` is set).
+    pub rejected: Vec<RejectedPeak>,
+}
+
+/// Options controlling `analyze_pcm_peaks_with_options`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AnalysisOptions {
+    /// Sub-bin frequency estimator applied to each detected peak.
+    pub estimator: FrequencyEstimator,
+    /// Analysis window applied before the FFT.
+    pub window: WindowFunction,
+    /// Filtering of local maxima before the strongest peaks are taken.
+    pub picking: PeakPicking,
+    /// Ranking of the filtered peaks.
+    pub selection: PeakSelection,
+    /// Lowest peak frequency considered (Hz). Peaks outside the band do not
+    /// count towards `max_peaks`.
+    pub min_freq_hz: Option<f32>,
+    /// Highest peak frequency considered (Hz).
+    pub max_freq_hz: Option<f32>,
+    /// Multi-resolution analysis: when non-empty, each band is analyzed on
+    /// its own centred slice of the input and the per-band peaks are merged
+    /// by magnitude into one list. Callers should pass frames at least as
+    /// long as the longest band window.
+    pub resolution_bands: Vec<ResolutionBand>,
+}
+
+impl AnalysisOptions {
+    /// Frame length needed to feed every band of `resolution_bands`, or
+    /// `window_size` if that is longer.
+    pub fn frame_len(&self, window_size: usize) -> usize {
+        self.resolution_bands
+            .iter()
+            .map(|band| band.window_size)
+            .fold(window_size, usize::max)
+    }
+}
+
 /// Analyze PCM samples and return up to `max_peaks` dominant spectral peaks.
 ///
 /// - `samples`: mono PCM samples (f32). If the slice length is not a power
 ///   of two, the input is zero-padded to the next power of two.
 /// - `sample_rate`: sampling rate in Hz.
 /// - `max_peaks`: maximum number of peaks to return (0 => empty vec).
+///
+/// Uses `AnalysisOptions::default()`; see `analyze_pcm_peaks_with_options`.
+/// Its `FrequencyEstimator::Parabolic` refines each peak's frequency and
+/// magnitude between bins; `FrequencyEstimator::Bin` gives the bin-centre
+/// frequencies this function returned before estimators were added.
 pub fn analyze_pcm_peaks(samples: &[f32], sample_rate: usize, max_peaks: usize) -> Vec<Peak> {
-    if samples.is_empty() || max_peaks == 0 || sample_rate == 0 {
-        return Vec::new();
+    analyze_pcm_peaks_with_options(samples, sample_rate, max_peaks, &AnalysisOptions::default())
+}
+
+/// Same as `analyze_pcm_peaks`, with explicit analysis `options`.
+///
+/// This builds a one-shot `SpectralAnalyzer`; callers analyzing many
+/// windows should keep an analyzer around instead.
+pub fn analyze_pcm_peaks_with_options(
+    samples: &[f32],
+    sample_rate: usize,
+    max_peaks: usize,
+    options: &AnalysisOptions,
+) -> Vec<Peak> {
+    SpectralAnalyzer::new(options.clone()).analyze(samples, sample_rate, max_peaks)
+}
+
+/// Reusable spectral peak analyzer.
+///
+/// Owns the FFT plan, the precomputed analysis window and all scratch
+/// buffers so that repeated calls on equally sized windows do not allocate
+/// or re-plan. Plans and windows are rebuilt transparently when the input
+/// length changes.
+pub struct SpectralAnalyzer {
+    options: AnalysisOptions,
+    planner: FftPlanner<f32>,
+    fft: Option<Arc<dyn Fft<f32>>>,
+    fft_size: usize,
+    window: Vec<f32>,
+    magnitude_scale: f32,
+    pv_window: Vec<f32>,
+    buffer: Vec<Complex<f32>>,
+    pv_first: Vec<Complex<f32>>,
+    pv_second: Vec<Complex<f32>>,
+    scratch: Vec<Complex<f32>>,
+    mags: Vec<f32>,
+    candidates: Vec<(usize, f32)>,
+    neighborhood: Vec<f32>,
+    masking: MaskingModel,
+    // one single-resolution analyzer per `resolution_bands` entry
+    bands: Vec<SpectralAnalyzer>,
+}
+
+impl SpectralAnalyzer {
+    pub fn new(options: AnalysisOptions) -> Self {
+        SpectralAnalyzer {
+            options,
+            planner: FftPlanner::new(),
+            fft: None,
+            fft_size: 0,
+            window: Vec::new(),
+            magnitude_scale: 1.0,
+            pv_window: Vec::new(),
+            buffer: Vec::new(),
+            pv_first: Vec::new(),
+            pv_second: Vec::new(),
+            scratch: Vec::new(),
+            mags: Vec::new(),
+            candidates: Vec::new(),
+            neighborhood: Vec::new(),
+            masking: MaskingModel::default(),
+            bands: Vec::new(),
+        }
     }
 
-    let len = samples.len();
-    let fft_size = len.next_power_of_two();
+    pub fn options(&self) -> &AnalysisOptions {
+        &self.options
+    }
 
-    let mut buffer: Vec<Complex<f32>> = vec![Complex::new(0.0, 0.0); fft_size];
-    for idx in 0..len {
-        // Hann window
-        let win = 0.5 * (1.0 - (2.0 * PI * (idx as f32) / ((len - 1) as f32)).cos());
-        buffer[idx].re = samples[idx] * win;
+    /// Analyze `samples` and return up to `max_peaks` dominant spectral
+    /// peaks, strongest first. Same contract as `analyze_pcm_peaks`.
+    pub fn analyze(&mut self, samples: &[f32], sample_rate: usize, max_peaks: usize) -> Vec<Peak> {
+        self.analyze_detailed(samples, sample_rate, max_peaks).peaks
     }
 
-    let mut planner = FftPlanner::<f32>::new();
-    let fft = planner.plan_fft_forward(fft_size);
-    fft.process(&mut buffer);
+    /// Like `analyze`, but also reports rejected candidates when
+    /// `PeakPicking::debug` is enabled.
+    pub fn analyze_detailed(
+        &mut self,
+        samples: &[f32],
+        sample_rate: usize,
+        max_peaks: usize,
+    ) -> PeakAnalysis {
+        let mut result = PeakAnalysis::default();
+        if samples.is_empty() || max_peaks == 0 || sample_rate == 0 {
+            return result;
+        }
+        if !self.options.resolution_bands.is_empty() {
+            return self.analyze_multi_resolution(samples, sample_rate, max_peaks);
+        }
+
+        let len = samples.len();
+        self.prepare(len);
+        let fft_size = self.fft_size;
+        let fft = Arc::clone(self.fft.as_ref().expect("fft planned in prepare"));
+
+        fill_windowed(&mut self.buffer, samples, &self.window);
+        fft.process_with_scratch(&mut self.buffer, &mut self.scratch);
+
+        let half = fft_size / 2;
+        self.mags.clear();
+        // scale to the amplitude of the underlying sinusoid (dBFS)
+        let scale = self.magnitude_scale;
+        self.mags.extend(
+            self.buffer
+                .iter()
+                .take(half)
+                .map(|comp| comp.norm() * scale),
+        );
+        let mags = &self.mags;
 
-    let half = fft_size / 2;
-    let mut mags: Vec<f32> = Vec::with_capacity(half);
-    for comp in buffer.iter().take(half) {
-        let mag = (comp.re.powi(2) + comp.im.powi(2)).sqrt();
-        mags.push(mag);
+        // local maxima with their ranking key: magnitude, or signal-to-mask
+        // ratio in dB
+        let selection = self.options.selection;
+        let bin_hz = sample_rate as f32 / fft_size as f32;
+        if selection == PeakSelection::Masking {
+            self.masking.update(mags, bin_hz);
+        }
+        self.candidates.clear();
+        for bin_idx in 1..mags.len().saturating_sub(1) {
+            let mag = mags[bin_idx];
+            if mag > mags[bin_idx - 1] && mag > mags[bin_idx + 1] {
+                let key = match selection {
+                    PeakSelection::Magnitude => mag,
+                    PeakSelection::Masking => self.masking.signal_to_mask_db(mags, bin_idx, bin_hz),
+                };
+                self.candidates.push((bin_idx, key));
+            }
+        }
+
+        self.candidates
+            .sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+
+        // Phase-vocoder estimation needs the spectra of two overlapping frames
+        // offset by `hop` samples.
+        let estimator = self.options.estimator;
+        let pv_hop = (len / 4).max(1);
+        let use_pv = estimator == FrequencyEstimator::PhaseVocoder && len > pv_hop;
+        if use_pv {
+            fill_windowed(
+                &mut self.pv_first,
+                &samples[..len - pv_hop],
+                &self.pv_window,
+            );
+            fill_windowed(&mut self.pv_second, &samples[pv_hop..], &self.pv_window);
+            fft.process_with_scratch(&mut self.pv_first, &mut self.scratch);
+            fft.process_with_scratch(&mut self.pv_second, &mut self.scratch);
+        }
+        let pv = if use_pv {
+            Some(PhaseVocoderFrames {
+                first: &self.pv_first,
+                second: &self.pv_second,
+                hop: pv_hop,
+            })
+        } else {
+            None
+        };
+
+        let picking = &self.options.picking;
+        for &(bin, key) in self.candidates.iter() {
+            let full = result.peaks.len() >= max_peaks;
+            let masked = selection == PeakSelection::Masking && key <= 0.0;
+            if (full || masked) && !picking.debug {
+                break;
+            }
+            let peak = refine_peak(mags, bin, mags[bin], estimator, pv, sample_rate, fft_size);
+            let in_range = self.options.min_freq_hz.is_none_or(|lo| peak.freq_hz >= lo)
+                && self.options.max_freq_hz.is_none_or(|hi| peak.freq_hz <= hi);
+            let mut reason = if !in_range {
+                Some(RejectReason::OutOfRange)
+            } else if masked {
+                Some(RejectReason::Masked)
+            } else {
+                candidate_rejection(mags, bin, picking, &mut self.neighborhood)
+            };
+            if reason.is_none()
+                && let Some(spacing) = picking.min_spacing
+                && result
+                    .peaks
+                    .iter()
+                    .any(|p| spacing.too_close(p.freq_hz, peak.freq_hz))
+            {
+                reason = Some(RejectReason::Spacing);
+            }
+            if reason.is_none() && full {
+                reason = Some(RejectReason::MaxPeaks);
+            }
+            match reason {
+                None => result.peaks.push(peak),
+                Some(reason) => {
+                    if picking.debug {
+                        result.rejected.push(RejectedPeak { peak, reason });
+                    }
+                }
+            }
+        }
+
+        result
     }
 
-    let mut candidates: Vec<(usize, f32)> = Vec::new();
-    for bin_idx in 1..(mags.len() - 1) {
-        let mag = mags[bin_idx];
-        if mag > mags[bin_idx - 1] && mag > mags[bin_idx + 1] {
-            candidates.push((bin_idx, mag));
+    // Analyze every resolution band on its centred slice of `samples` and
+    // merge the results, strongest first. Spacing and `max_peaks` are
+    // re-applied across bands.
+    fn analyze_multi_resolution(
+        &mut self,
+        samples: &[f32],
+        sample_rate: usize,
+        max_peaks: usize,
+    ) -> PeakAnalysis {
+        if self.bands.len() != self.options.resolution_bands.len() {
+            self.bands.clear();
+            let mut lo = 0.0f32;
+            let last = self.options.resolution_bands.len() - 1;
+            for (idx, band) in self.options.resolution_bands.iter().enumerate() {
+                let mut options = self.options.clone();
+                options.resolution_bands.clear();
+                options.min_freq_hz = Some(options.min_freq_hz.map_or(lo, |m| m.max(lo)));
+                if idx < last {
+                    let hi = band.max_freq_hz;
+                    options.max_freq_hz = Some(options.max_freq_hz.map_or(hi, |m| m.min(hi)));
+                }
+                lo = band.max_freq_hz;
+                self.bands.push(SpectralAnalyzer::new(options));
+            }
+        }
+
+        let picking = &self.options.picking;
+        let in_range = |freq: f32| {
+            self.options.min_freq_hz.is_none_or(|lo| freq >= lo)
+                && self.options.max_freq_hz.is_none_or(|hi| freq <= hi)
+        };
+        let mut candidates = PeakAnalysis::default();
+        for (band, analyzer) in self.options.resolution_bands.iter().zip(&mut self.bands) {
+            let len = band.window_size.clamp(1, samples.len());
+            let start = (samples.len() - len) / 2;
+            let part =
+                analyzer.analyze_detailed(&samples[start..start + len], sample_rate, max_peaks);
+            candidates.peaks.extend(part.peaks);
+            // a peak outside one band is usually inside another
+            candidates.rejected.extend(
+                part.rejected
+                    .into_iter()
+                    .filter(|r| r.reason != RejectReason::OutOfRange || !in_range(r.peak.freq_hz)),
+            );
+        }
+        candidates
+            .peaks
+            .sort_by(|a, b| b.magnitude.total_cmp(&a.magnitude));
+
+        let mut result = PeakAnalysis {
+            peaks: Vec::new(),
+            rejected: candidates.rejected,
+        };
+        for peak in candidates.peaks {
+            let reason = if let Some(spacing) = picking.min_spacing
+                && result
+                    .peaks
+                    .iter()
+                    .any(|p| spacing.too_close(p.freq_hz, peak.freq_hz))
+            {
+                Some(RejectReason::Spacing)
+            } else if result.peaks.len() >= max_peaks {
+                Some(RejectReason::MaxPeaks)
+            } else {
+                None
+            };
+            match reason {
+                None => result.peaks.push(peak),
+                Some(reason) => {
+                    if picking.debug {
+                        result.rejected.push(RejectedPeak { peak, reason });
+                    }
+                }
+            }
+        }
+        result
+    }
+
+    // (Re)build the FFT plan, windows and buffers for an input of `len` samples.
+    fn prepare(&mut self, len: usize) {
+        let fft_size = len.next_power_of_two();
+        if self.fft.is_none() || self.fft_size != fft_size {
+            let fft = self.planner.plan_fft_forward(fft_size);
+            self.scratch = vec![Complex::new(0.0, 0.0); fft.get_inplace_scratch_len()];
+            self.buffer = vec![Complex::new(0.0, 0.0); fft_size];
+            self.pv_first = vec![Complex::new(0.0, 0.0); fft_size];
+            self.pv_second = vec![Complex::new(0.0, 0.0); fft_size];
+            self.fft = Some(fft);
+            self.fft_size = fft_size;
+        }
+        if self.window.len() != len {
+            let function = self.options.window;
+            self.window = function.coefficients(len);
+            // a sinusoid of amplitude A yields |X| = A * sum(w) / 2 at its bin
+            self.magnitude_scale = match self.window.iter().sum::<f32>() {
+                sum if sum > 0.0 => 2.0 / sum,
+                _ => 1.0,
+            };
+            self.pv_window = function.coefficients(len - (len / 4).max(1).min(len));
         }
     }
+}
 
-    candidates.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
-    let take_n = candidates.len().min(max_peaks);
+// Spectra of the two overlapping phase-vocoder frames and their hop.
+#[derive(Clone, Copy)]
+struct PhaseVocoderFrames<'a> {
+    first: &'a [Complex<f32>],
+    second: &'a [Complex<f32>],
+    hop: usize,
+}
 
-    let mut peaks: Vec<Peak> = Vec::with_capacity(take_n);
-    for &(bin, mag) in candidates.iter().take(take_n) {
-        let freq = (bin as f32) * (sample_rate as f32) / (fft_size as f32);
-        let mag_db = if mag <= 0.0 {
-            -200.0
-        } else {
-            20.0 * mag.log10()
+// Refine the local maximum at `bin` into a `Peak` using `estimator`.
+fn refine_peak(
+    mags: &[f32],
+    bin: usize,
+    mag: f32,
+    estimator: FrequencyEstimator,
+    pv: Option<PhaseVocoderFrames>,
+    sample_rate: usize,
+    fft_size: usize,
+) -> Peak {
+    let (left, right) = (mags[bin - 1], mags[bin + 1]);
+    let offset = match (estimator, pv) {
+        (FrequencyEstimator::Bin, _) => 0.0,
+        (FrequencyEstimator::Parabolic, _) => parabolic_offset(left, mag, right),
+        (FrequencyEstimator::Jain, _) => jain_offset(left, mag, right),
+        (FrequencyEstimator::PhaseVocoder, Some(pv)) => {
+            phase_vocoder_offset(pv.first[bin], pv.second[bin], bin, pv.hop, fft_size)
+        }
+        (FrequencyEstimator::PhaseVocoder, None) => parabolic_offset(left, mag, right),
+    };
+    let mag = if estimator == FrequencyEstimator::Bin {
+        mag
+    } else {
+        interpolated_magnitude(left, mag, right, offset)
+    };
+    let freq = ((bin as f32) + offset) * (sample_rate as f32) / (fft_size as f32);
+    let mag_db = if mag <= 0.0 {
+        -200.0
+    } else {
+        20.0 * mag.log10()
+    };
+    Peak {
+        freq_hz: freq,
+        magnitude: mag,
+        magnitude_db: mag_db,
+        bin,
+    }
+}
+
+// Apply the bin-level `PeakPicking` criteria (everything except spacing,
+// which depends on the refined frequencies of already accepted peaks).
+fn candidate_rejection(
+    mags: &[f32],
+    bin: usize,
+    picking: &PeakPicking,
+    neighborhood: &mut Vec<f32>,
+) -> Option<RejectReason> {
+    let mag = mags[bin];
+    if let Some(min_db) = picking.min_above_noise_floor_db {
+        let radius = picking.neighborhood_bins / 2;
+        let lo = bin.saturating_sub(radius);
+        let hi = (bin + radius + 1).min(mags.len());
+        neighborhood.clear();
+        neighborhood.extend_from_slice(&mags[lo..hi]);
+        let mid = neighborhood.len() / 2;
+        let (_, median, _) = neighborhood.select_nth_unstable_by(mid, |a, b| a.total_cmp(b));
+        if to_db(mag) - to_db(*median) < min_db {
+            return Some(RejectReason::NoiseFloor);
+        }
+    }
+    if let Some(min_db) = picking.min_prominence_db
+        && prominence_db(mags, bin) < min_db
+    {
+        return Some(RejectReason::Prominence);
+    }
+    if let Some(max_flatness) = picking.max_flatness {
+        let radius = picking.neighborhood_bins / 2;
+        let lo = bin.saturating_sub(radius);
+        let hi = (bin + radius + 1).min(mags.len());
+        if spectral_flatness(&mags[lo..hi]) > max_flatness {
+            return Some(RejectReason::Flatness);
+        }
+    }
+    None
+}
+
+// Topographic prominence of the local maximum at `bin`, in dB.
+fn prominence_db(mags: &[f32], bin: usize) -> f32 {
+    let peak = mags[bin];
+    let mut left_min = peak;
+    for &m in mags[..bin].iter().rev() {
+        if m > peak {
+            break;
+        }
+        left_min = left_min.min(m);
+    }
+    let mut right_min = peak;
+    for &m in &mags[bin + 1..] {
+        if m > peak {
+            break;
+        }
+        right_min = right_min.min(m);
+    }
+    to_db(peak) - to_db(left_min.max(right_min))
+}
+
+/// Spectral flatness (geometric / arithmetic mean of power) of `mags`.
+///
+/// Close to 0 for a tonal spectrum and close to 1 for white noise.
+pub fn spectral_flatness(mags: &[f32]) -> f32 {
+    if mags.is_empty() {
+        return 0.0;
+    }
+    let n = mags.len() as f64;
+    let mut log_sum = 0.0f64;
+    let mut sum = 0.0f64;
+    for &m in mags {
+        let power = (m as f64) * (m as f64) + 1e-20;
+        log_sum += power.ln();
+        sum += power;
+    }
+    let arith = sum / n;
+    if arith <= 0.0 {
+        return 0.0;
+    }
+    ((log_sum / n).exp() / arith) as f32
+}
+
+// Write `samples * window` into `buffer`, zero-padding the rest.
+fn fill_windowed(buffer: &mut [Complex<f32>], samples: &[f32], window: &[f32]) {
+    for (idx, slot) in buffer.iter_mut().enumerate() {
+        *slot = match (samples.get(idx), window.get(idx)) {
+            (Some(&s), Some(&w)) => Complex::new(s * w, 0.0),
+            _ => Complex::new(0.0, 0.0),
         };
-        peaks.push(Peak {
-            freq_hz: freq,
-            magnitude: mag,
-            magnitude_db: mag_db,
-            bin,
-        });
     }
+}
+
+fn to_db(mag: f32) -> f32 {
+    20.0 * mag.max(1e-10).log10()
+}
+
+// Vertex offset (in bins, within -0.5..0.5) of the parabola through the log
+// magnitudes of the peak bin and its two neighbours.
+fn parabolic_offset(left: f32, center: f32, right: f32) -> f32 {
+    let (a, b, c) = (to_db(left), to_db(center), to_db(right));
+    let denom = a - 2.0 * b + c;
+    if denom.abs() < f32::EPSILON {
+        return 0.0;
+    }
+    (0.5 * (a - c) / denom).clamp(-0.5, 0.5)
+}
+
+// Jain's method: interpolate towards the larger neighbour using the
+// magnitude ratio.
+fn jain_offset(left: f32, center: f32, right: f32) -> f32 {
+    if center <= 0.0 {
+        return 0.0;
+    }
+    let offset = if left > right {
+        let ratio = center / left;
+        ratio / (1.0 + ratio) - 1.0
+    } else {
+        let ratio = right / center;
+        ratio / (1.0 + ratio)
+    };
+    offset.clamp(-1.0, 1.0)
+}
+
+// Instantaneous frequency deviation (in bins) of `bin` from the phase
+// advance between two frames `hop` samples apart.
+fn phase_vocoder_offset(
+    first: Complex<f32>,
+    second: Complex<f32>,
+    bin: usize,
+    hop: usize,
+    fft_size: usize,
+) -> f32 {
+    let two_pi = 2.0 * PI;
+    let expected = two_pi * (bin as f32) * (hop as f32) / (fft_size as f32);
+    let delta = second.arg() - first.arg() - expected;
+    // wrap to -pi..pi
+    let wrapped = delta - two_pi * (delta / two_pi).round();
+    (wrapped * (fft_size as f32) / (two_pi * (hop as f32))).clamp(-1.0, 1.0)
+}
 
-    peaks
+// Height of the log-magnitude parabola through the three bins, evaluated at
+// `offset` bins from the centre.
+fn interpolated_magnitude(left: f32, center: f32, right: f32, offset: f32) -> f32 {
+    let (a, b, c) = (to_db(left), to_db(center), to_db(right));
+    let offset = offset.clamp(-1.0, 1.0);
+    let db = b + 0.5 * (c - a) * offset + 0.5 * (a - 2.0 * b + c) * offset * offset;
+    // the vertex of a concave parabola is the maximum; never report less
+    // than the measured bin
+    10f32.powf(db / 20.0).max(center)
 }
 
 /// Synthesize a mono buffer of `sample_count` samples at `sample_rate` Hz
 /// by summing sinusoids for each provided `peaks` entry.
 ///
-/// The per-peak magnitude is taken from `Peak::magnitude` and normalized
-/// relative to the strongest peak to form amplitude weights. The final
-/// output is scaled to avoid clipping (max absolute value <= 0.95).
+/// Each sinusoid has the amplitude given by `Peak::magnitude`; the sum is
+/// neither normalized nor protected against clipping (see
+/// `loudness::apply_output_gain`).
+///
+/// Every sinusoid starts at phase zero, so consecutive buffers do not join
+/// smoothly; use `OscillatorBank` to render a sequence of blocks.
 pub fn synthesize_sines(peaks: &[Peak], sample_rate: usize, sample_count: usize) -> Vec<f32> {
     let mut out = vec![0.0_f32; sample_count];
     if peaks.is_empty() || sample_rate == 0 || sample_count == 0 {
         return out;
     }
 
-    let max_mag = peaks.iter().map(|p| p.magnitude).fold(0.0_f32, f32::max);
-    let max_mag = if max_mag.is_finite() && max_mag > 0.0 {
-        max_mag
-    } else {
-        1.0
-    };
-
     let mut comps: Vec<(f32, f32)> = Vec::with_capacity(peaks.len()); // (omega, amp)
     for peak in peaks {
         let omega = 2.0 * std::f32::consts::PI * peak.freq_hz / (sample_rate as f32);
-        let amp = peak.magnitude / max_mag;
+        let amp = if peak.magnitude.is_finite() {
+            peak.magnitude
+        } else {
+            0.0
+        };
         comps.push((omega, amp));
     }
 
@@ -111,21 +879,6 @@ pub fn synthesize_sines(peaks: &[Peak], sample_rate: usize, sample_count: usize)
         *slot = sum;
     }
 
-    let max_abs = out.iter().fold(0.0f32, |m, &v| m.max(v.abs()));
-    if max_abs > 0.0 {
-        let scale = 0.95_f32 / max_abs;
-        if scale < 1.0 {
-            for val in &mut out {
-                *val *= scale;
-            }
-        }
-    }
-
-    // TODO: adjust overall
-    for val in &mut out {
-        *val *= 0.2_f32;
-    }
-
     out
 }
 
@@ -177,3 +930,28 @@ pub fn interleaved_to_mono<S: SampleToF32 + Copy>(samples: &[S], channels: usize
     }
     out
 }
+
+/// Split interleaved multi-channel samples into left and right `Vec<f32>`s.
+///
+/// Mono input is copied to both sides; channels beyond the first two are
+/// ignored.
+pub fn interleaved_to_stereo<S: SampleToF32 + Copy>(
+    samples: &[S],
+    channels: usize,
+) -> (Vec<f32>, Vec<f32>) {
+    if channels == 0 || samples.is_empty() {
+        return (Vec::new(), Vec::new());
+    }
+    if channels == 1 {
+        let mono: Vec<f32> = samples.iter().map(|&s| s.to_f32_normalized()).collect();
+        return (mono.clone(), mono);
+    }
+    let frames = samples.len() / channels;
+    let mut left = Vec::with_capacity(frames);
+    let mut right = Vec::with_capacity(frames);
+    for frame in samples.chunks_exact(channels) {
+        left.push(frame[0].to_f32_normalized());
+        right.push(frame[1].to_f32_normalized());
+    }
+    (left, right)
+}
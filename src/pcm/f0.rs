@@ -0,0 +1,372 @@
+use super::{Peak, WindowFunction};
+use rustfft::{Fft, FftPlanner, num_complex::Complex};
+use std::sync::Arc;
+
+/// Fundamental-frequency estimation algorithm.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum F0Method {
+    /// YIN (cumulative mean normalized difference function).
+    #[default]
+    Yin,
+    /// Harmonic product spectrum with sub-octave correction.
+    HarmonicProductSpectrum,
+}
+
+/// Options for `estimate_f0`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct F0Options {
+    pub method: F0Method,
+    /// Lowest fundamental searched (Hz). YIN compares the frame with itself
+    /// shifted by up to half its length, so it only reaches this in frames
+    /// of at least `min_frame_len` samples.
+    pub min_f0_hz: f32,
+    /// Highest fundamental searched (Hz).
+    pub max_f0_hz: f32,
+    /// YIN aperiodicity threshold; dips below it are accepted as the period.
+    pub yin_threshold: f32,
+    /// Number of spectra multiplied by the harmonic product spectrum.
+    pub hps_harmonics: usize,
+    /// Minimum confidence for a frame to be reported as voiced.
+    pub voicing_threshold: f32,
+    /// Maximum deviation of a peak from `index * f0` to be grouped as a
+    /// harmonic (cents).
+    pub harmonic_tolerance_cents: f32,
+}
+
+impl Default for F0Options {
+    fn default() -> Self {
+        F0Options {
+            method: F0Method::Yin,
+            min_f0_hz: 50.0,
+            max_f0_hz: 1000.0,
+            yin_threshold: 0.15,
+            hps_harmonics: 5,
+            voicing_threshold: 0.5,
+            harmonic_tolerance_cents: 50.0,
+        }
+    }
+}
+
+impl F0Options {
+    /// Shortest frame (in samples at `sample_rate`) in which YIN can find
+    /// a fundamental as low as `min_f0_hz`: two of its periods.
+    pub fn min_frame_len(&self, sample_rate: usize) -> usize {
+        if self.min_f0_hz <= 0.0 {
+            return 0;
+        }
+        (2.0 * sample_rate as f32 / self.min_f0_hz).ceil() as usize
+    }
+}
+
+/// A spectral peak identified as the `index`-th harmonic of the fundamental
+/// (`index == 1` is the fundamental itself).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Harmonic {
+    pub index: usize,
+    pub peak: Peak,
+}
+
+/// Result of `estimate_f0`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct F0Estimate {
+    /// Estimated fundamental in Hz, `None` when the frame is unvoiced.
+    pub f0_hz: Option<f32>,
+    /// Voicing confidence in 0..1 (periodicity of the frame).
+    pub confidence: f32,
+    /// Peaks that lie on the harmonic series of `f0_hz`, ordered by index.
+    /// At most one peak (the strongest) is kept per index.
+    pub harmonics: Vec<Harmonic>,
+}
+
+/// Estimate the fundamental frequency of a mono frame and group `peaks`
+/// (typically from `SpectralAnalyzer::analyze`) as its harmonics.
+///
+/// Uses a one-off `F0Estimator`; keep one around to estimate many frames.
+pub fn estimate_f0(
+    samples: &[f32],
+    sample_rate: usize,
+    peaks: &[Peak],
+    options: &F0Options,
+) -> F0Estimate {
+    F0Estimator::new(options.clone()).estimate(samples, sample_rate, peaks)
+}
+
+/// Reusable fundamental-frequency estimator. Keeps the FFT plan and
+/// buffers of the harmonic product spectrum between frames.
+pub struct F0Estimator {
+    options: F0Options,
+    planner: FftPlanner<f32>,
+    fft: Option<Arc<dyn Fft<f32>>>,
+    fft_size: usize,
+    window: Vec<f32>,
+    buffer: Vec<Complex<f32>>,
+    scratch: Vec<Complex<f32>>,
+}
+
+impl F0Estimator {
+    pub fn new(options: F0Options) -> Self {
+        F0Estimator {
+            options,
+            planner: FftPlanner::new(),
+            fft: None,
+            fft_size: 0,
+            window: Vec::new(),
+            buffer: Vec::new(),
+            scratch: Vec::new(),
+        }
+    }
+
+    pub fn options(&self) -> &F0Options {
+        &self.options
+    }
+
+    /// See `estimate_f0`.
+    pub fn estimate(&mut self, samples: &[f32], sample_rate: usize, peaks: &[Peak]) -> F0Estimate {
+        if samples.is_empty() || sample_rate == 0 || self.options.min_f0_hz <= 0.0 {
+            return F0Estimate::default();
+        }
+        let candidate = match self.options.method {
+            F0Method::Yin => yin(samples, sample_rate, &self.options),
+            F0Method::HarmonicProductSpectrum => {
+                let mags = self.magnitude_spectrum(samples);
+                harmonic_product_spectrum(&mags, sample_rate, self.fft_size, &self.options)
+            }
+        };
+        let options = &self.options;
+        let Some((f0, confidence)) = candidate else {
+            return F0Estimate::default();
+        };
+        if confidence < options.voicing_threshold {
+            return F0Estimate {
+                f0_hz: None,
+                confidence,
+                harmonics: Vec::new(),
+            };
+        }
+        F0Estimate {
+            f0_hz: Some(f0),
+            confidence,
+            harmonics: group_harmonics(f0, peaks, options.harmonic_tolerance_cents),
+        }
+    }
+
+    // Magnitudes of the Hann-windowed spectrum of `samples`, zero-padded to
+    // at least twice their length for finer bin spacing, DC up to Nyquist
+    // (excluded).
+    fn magnitude_spectrum(&mut self, samples: &[f32]) -> Vec<f32> {
+        let fft_size = (samples.len() * 2).next_power_of_two();
+        if self.fft.is_none() || self.fft_size != fft_size {
+            let fft = self.planner.plan_fft_forward(fft_size);
+            self.scratch = vec![Complex::new(0.0, 0.0); fft.get_inplace_scratch_len()];
+            self.buffer = vec![Complex::new(0.0, 0.0); fft_size];
+            self.fft = Some(fft);
+            self.fft_size = fft_size;
+        }
+        if self.window.len() != samples.len() {
+            self.window = WindowFunction::Hann.coefficients(samples.len());
+        }
+        for (idx, slot) in self.buffer.iter_mut().enumerate() {
+            *slot = match (samples.get(idx), self.window.get(idx)) {
+                (Some(&s), Some(&w)) => Complex::new(s * w, 0.0),
+                _ => Complex::new(0.0, 0.0),
+            };
+        }
+        let fft = Arc::clone(self.fft.as_ref().expect("fft planned above"));
+        fft.process_with_scratch(&mut self.buffer, &mut self.scratch);
+        self.buffer[..fft_size / 2]
+            .iter()
+            .map(|c| c.norm())
+            .collect()
+    }
+}
+
+/// Assign each peak within `tolerance_cents` of `index * f0_hz` to that
+/// harmonic index, keeping the strongest peak per index.
+pub fn group_harmonics(f0_hz: f32, peaks: &[Peak], tolerance_cents: f32) -> Vec<Harmonic> {
+    let mut out: Vec<Harmonic> = Vec::new();
+    if f0_hz <= 0.0 {
+        return out;
+    }
+    for peak in peaks {
+        if peak.freq_hz <= 0.0 {
+            continue;
+        }
+        let index = (peak.freq_hz / f0_hz).round();
+        if index < 1.0 {
+            continue;
+        }
+        let cents = 1200.0 * (peak.freq_hz / (index * f0_hz)).log2();
+        if cents.abs() > tolerance_cents {
+            continue;
+        }
+        let index = index as usize;
+        match out.iter_mut().find(|h| h.index == index) {
+            Some(existing) if existing.peak.magnitude < peak.magnitude => existing.peak = *peak,
+            Some(_) => {}
+            None => out.push(Harmonic { index, peak: *peak }),
+        }
+    }
+    out.sort_by_key(|h| h.index);
+    out
+}
+
+// YIN: returns (f0, confidence) where confidence is 1 - aperiodicity.
+fn yin(samples: &[f32], sample_rate: usize, options: &F0Options) -> Option<(f32, f32)> {
+    let sr = sample_rate as f32;
+    let tau_min = ((sr / options.max_f0_hz.max(1.0)).floor() as usize).max(2);
+    let tau_max = ((sr / options.min_f0_hz).ceil() as usize).min(samples.len() / 2);
+    if tau_max <= tau_min + 1 {
+        return None;
+    }
+    let width = samples.len() - tau_max;
+
+    // difference function d(tau) and its cumulative mean normalization
+    let mut cmnd = vec![1.0f32; tau_max + 1];
+    let mut running = 0.0f32;
+    for tau in 1..=tau_max {
+        let mut diff = 0.0f32;
+        for i in 0..width {
+            let d = samples[i] - samples[i + tau];
+            diff += d * d;
+        }
+        running += diff;
+        cmnd[tau] = if running > 0.0 {
+            diff * (tau as f32) / running
+        } else {
+            1.0
+        };
+    }
+
+    // first dip below the threshold, followed down to its local minimum;
+    // otherwise the global minimum
+    let mut best = None;
+    let mut tau = tau_min;
+    while tau < tau_max {
+        if cmnd[tau] < options.yin_threshold {
+            while tau + 1 < tau_max && cmnd[tau + 1] < cmnd[tau] {
+                tau += 1;
+            }
+            best = Some(tau);
+            break;
+        }
+        tau += 1;
+    }
+    let tau = best.unwrap_or_else(|| {
+        (tau_min..tau_max)
+            .min_by(|&a, &b| cmnd[a].total_cmp(&cmnd[b]))
+            .unwrap_or(tau_min)
+    });
+
+    // parabolic interpolation of the dip
+    let refined = if tau > 1 && tau < tau_max {
+        let (a, b, c) = (cmnd[tau - 1], cmnd[tau], cmnd[tau + 1]);
+        let denom = a - 2.0 * b + c;
+        if denom.abs() > f32::EPSILON {
+            tau as f32 + (0.5 * (a - c) / denom).clamp(-1.0, 1.0)
+        } else {
+            tau as f32
+        }
+    } else {
+        tau as f32
+    };
+    let confidence = (1.0 - cmnd[tau]).clamp(0.0, 1.0);
+    Some((sr / refined, confidence))
+}
+
+// Harmonic product spectrum of the magnitudes `mags` of an `fft_size`
+// transform: returns (f0, confidence) where confidence is the share of
+// spectral energy lying on the detected harmonic series.
+fn harmonic_product_spectrum(
+    mags: &[f32],
+    sample_rate: usize,
+    fft_size: usize,
+    options: &F0Options,
+) -> Option<(f32, f32)> {
+    let harmonics = options.hps_harmonics.max(1);
+    // too short to hold a bin for each harmonic of any candidate
+    if mags.len() < 2 * harmonics + 1 {
+        return None;
+    }
+
+    let bin_hz = sample_rate as f32 / fft_size as f32;
+    let k_min = ((options.min_f0_hz / bin_hz).ceil() as usize).max(1);
+    let k_max = ((options.max_f0_hz / bin_hz).floor() as usize).min((mags.len() - 2) / harmonics);
+    if k_max <= k_min {
+        return None;
+    }
+    let log_hps = |k: usize| -> f32 {
+        (1..=harmonics)
+            .map(|h| (mags[k * h] + 1e-12).ln())
+            .sum::<f32>()
+    };
+    let mut best_k = k_min;
+    let mut best = f32::MIN;
+    for k in k_min..=k_max {
+        let v = log_hps(k);
+        if v > best {
+            best = v;
+            best_k = k;
+        }
+    }
+
+    // parabolic interpolation of the log product around the maximum
+    let offset = if best_k > k_min && best_k < k_max {
+        let (a, b, c) = (log_hps(best_k - 1), log_hps(best_k), log_hps(best_k + 1));
+        let denom = a - 2.0 * b + c;
+        if denom.abs() > f32::EPSILON {
+            (0.5 * (a - c) / denom).clamp(-0.5, 0.5)
+        } else {
+            0.0
+        }
+    } else {
+        0.0
+    };
+    let mut f0_bins = best_k as f32 + offset;
+
+    // strongest local maximum within one bin of fractional bin position `pos`
+    let peak_near = |pos: f32| -> f32 {
+        let k = pos.round() as usize;
+        (k.saturating_sub(1).max(1)..=(k + 1).min(mags.len() - 2))
+            .filter(|&j| mags[j] >= mags[j - 1] && mags[j] >= mags[j + 1])
+            .map(|j| mags[j])
+            .fold(1e-12f32, f32::max)
+    };
+
+    // sub-octave correction: the product often peaks an octave too high when
+    // odd harmonics are weak. Move down when the odd multiples of f0/2 (which
+    // would be the missing harmonics) are real spectral peaks within 20 dB of
+    // the detected series, not just main-lobe leakage.
+    let half = f0_bins / 2.0;
+    if half >= k_min as f32 {
+        let series: Vec<f32> = (1..=harmonics)
+            .map(|h| peak_near(f0_bins * h as f32).ln())
+            .collect();
+        let odd: Vec<f32> = (0..harmonics.div_ceil(2))
+            .map(|j| half * (2 * j + 1) as f32)
+            .filter(|&pos| (pos as usize) + 2 < mags.len())
+            .map(|pos| peak_near(pos).ln())
+            .collect();
+        let mean = |v: &[f32]| v.iter().sum::<f32>() / (v.len().max(1) as f32);
+        if !odd.is_empty() && mean(&odd) > mean(&series) + (0.1f32).ln() {
+            f0_bins = half;
+        }
+    }
+
+    let total: f32 = mags.iter().map(|m| m * m).sum();
+    let mut on_series = 0.0f32;
+    let mut h = 1.0f32;
+    while (f0_bins * h).round() as usize + 1 < mags.len() {
+        let k = (f0_bins * h).round() as usize;
+        on_series += mags[k.saturating_sub(1)..=k + 1]
+            .iter()
+            .map(|m| m * m)
+            .sum::<f32>();
+        h += 1.0;
+    }
+    let confidence = if total > 0.0 {
+        (on_series / total).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    Some((f0_bins * bin_hz, confidence))
+}
@@ -0,0 +1,354 @@
+use super::{Peak, WindowFunction};
+use crate::resample::{ResampleOptions, Resampler};
+use rustfft::{Fft, FftPlanner, num_complex::Complex};
+use std::sync::Arc;
+
+/// Options for `FormantAnalyzer` and `estimate_formants`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FormantOptions {
+    /// Number of formants reported (F1, F2, ...).
+    pub max_formants: usize,
+    /// LPC order. `None` uses `2 + rate / 1000` for the rate the LPC runs
+    /// at (one resonance per kHz plus two for the glottal and radiation
+    /// slope).
+    pub lpc_order: Option<usize>,
+    /// Pre-emphasis coefficient applied before LPC, flattening the
+    /// spectral tilt of the voice so upper formants are modelled as well
+    /// as the lower ones.
+    pub pre_emphasis: f32,
+    /// Lowest formant frequency accepted (Hz).
+    pub min_freq_hz: f32,
+    /// Highest formant frequency accepted (Hz). LPC runs on the frame
+    /// resampled to a little over twice this rate, so its poles describe
+    /// the formant region instead of single harmonics.
+    pub max_freq_hz: f32,
+    /// Widest formant bandwidth accepted (Hz); broader poles shape the
+    /// overall tilt rather than a resonance.
+    pub max_bandwidth_hz: f32,
+}
+
+impl Default for FormantOptions {
+    fn default() -> Self {
+        FormantOptions {
+            max_formants: 4,
+            lpc_order: None,
+            pre_emphasis: 0.97,
+            min_freq_hz: 90.0,
+            max_freq_hz: 5000.0,
+            max_bandwidth_hz: 600.0,
+        }
+    }
+}
+
+/// A resonance of the LPC spectral envelope.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Formant {
+    /// Centre frequency in Hz.
+    pub freq_hz: f32,
+    /// -3 dB bandwidth in Hz.
+    pub bandwidth_hz: f32,
+    /// Energy of the frame within the formant's band, as the amplitude of
+    /// a sinusoid of the same energy (on the scale of `Peak::magnitude`).
+    pub magnitude: f32,
+}
+
+impl Formant {
+    /// The formant as a spectral peak at its centre frequency.
+    pub fn to_peak(&self) -> Peak {
+        Peak {
+            freq_hz: self.freq_hz,
+            magnitude: self.magnitude,
+            magnitude_db: if self.magnitude <= 0.0 {
+                -200.0
+            } else {
+                20.0 * self.magnitude.log10()
+            },
+            bin: 0,
+        }
+    }
+}
+
+/// Linear prediction coefficients of `samples` (autocorrelation method,
+/// Levinson-Durbin recursion).
+///
+/// Returns `a[1..=order]` of the predictor polynomial
+/// `A(z) = 1 + a1 z^-1 + ... + ap z^-p` and the prediction error power.
+/// Silent input gives all-zero coefficients.
+pub fn lpc_coefficients(samples: &[f32], order: usize) -> (Vec<f64>, f64) {
+    let autocorrelation: Vec<f64> = (0..=order)
+        .map(|lag| {
+            samples
+                .iter()
+                .zip(samples.iter().skip(lag))
+                .map(|(&a, &b)| a as f64 * b as f64)
+                .sum()
+        })
+        .collect();
+    let mut a = vec![0.0f64; order];
+    // slight white-noise correction keeps the recursion stable on
+    // near-singular (e.g. pure tone) input
+    let mut error = autocorrelation[0] * (1.0 + 1e-9);
+    if error <= 0.0 {
+        return (a, 0.0);
+    }
+    for i in 0..order {
+        let acc =
+            autocorrelation[i + 1] + (0..i).map(|j| a[j] * autocorrelation[i - j]).sum::<f64>();
+        let k = -acc / error;
+        let previous = a.clone();
+        a[i] = k;
+        for j in 0..i {
+            a[j] = previous[j] + k * previous[i - 1 - j];
+        }
+        error *= 1.0 - k * k;
+        if error <= 0.0 {
+            break;
+        }
+    }
+    (a, error.max(0.0))
+}
+
+// Roots of the monic polynomial z^n + c[0] z^(n-1) + ... + c[n-1]
+// (Durand-Kerner iteration).
+fn polynomial_roots(c: &[f64]) -> Vec<Complex<f64>> {
+    let n = c.len();
+    let eval = |z: Complex<f64>| {
+        c.iter()
+            .fold(Complex::new(1.0, 0.0), |acc, &coeff| acc * z + coeff)
+    };
+    let seed = Complex::new(0.4, 0.9);
+    let mut roots: Vec<Complex<f64>> = (0..n).map(|i| seed.powu(i as u32)).collect();
+    for _ in 0..500 {
+        let mut change = 0.0f64;
+        for i in 0..n {
+            let denominator = (0..n)
+                .filter(|&j| j != i)
+                .fold(Complex::new(1.0, 0.0), |acc, j| acc * (roots[i] - roots[j]));
+            if denominator.norm() == 0.0 {
+                continue;
+            }
+            let delta = eval(roots[i]) / denominator;
+            roots[i] -= delta;
+            change = change.max(delta.norm());
+        }
+        if change < 1e-12 {
+            break;
+        }
+    }
+    roots
+}
+
+/// Formant candidates of one frame, lowest first: the resonances of its
+/// LPC envelope within `min_freq_hz..max_freq_hz` and narrower than
+/// `max_bandwidth_hz`. Each analysis builds its own resampler; use
+/// `FormantAnalyzer` for a sequence of frames.
+pub fn estimate_formants(
+    samples: &[f32],
+    sample_rate: usize,
+    options: &FormantOptions,
+) -> Vec<Formant> {
+    FormantAnalyzer::new(options.clone()).candidates(samples, sample_rate)
+}
+
+/// Formant analysis of consecutive frames, tracking F1..Fn over time.
+///
+/// Each frame's candidates are assigned to the formant slots in order of
+/// frequency, each slot taking the candidate closest (on a log scale) to
+/// what it held in the previous frame, or to a neutral vowel's formant at
+/// first. A slot left without a candidate keeps its frequency at zero
+/// magnitude, so slot `i` is always formant `i + 1` and the voice it maps
+/// to does not jump between formants.
+#[derive(Clone)]
+pub struct FormantAnalyzer {
+    options: FormantOptions,
+    // resampler to the LPC rate, kept for the last input rate
+    resampler: Option<Resampler>,
+    fft_size: usize,
+    fft: Option<Arc<dyn Fft<f32>>>,
+    slots: Vec<f32>,
+}
+
+impl FormantAnalyzer {
+    pub fn new(options: FormantOptions) -> Self {
+        // formants of a neutral vowel (uniform 17.5 cm tube)
+        let slots = (0..options.max_formants)
+            .map(|i| 500.0 * (2 * i + 1) as f32)
+            .collect();
+        FormantAnalyzer {
+            options,
+            resampler: None,
+            fft_size: 0,
+            fft: None,
+            slots,
+        }
+    }
+
+    pub fn options(&self) -> &FormantOptions {
+        &self.options
+    }
+
+    /// Tracked formants of the next frame: `max_formants` entries, F1
+    /// first. A formant not found in this frame has magnitude 0.
+    pub fn analyze(&mut self, samples: &[f32], sample_rate: usize) -> Vec<Formant> {
+        let candidates = self.candidates(samples, sample_rate);
+        let assignment = assign_slots(&self.slots, &candidates);
+        self.slots
+            .iter_mut()
+            .zip(assignment)
+            .map(|(slot, candidate)| match candidate {
+                Some(c) => {
+                    *slot = candidates[c].freq_hz;
+                    candidates[c]
+                }
+                None => Formant {
+                    freq_hz: *slot,
+                    bandwidth_hz: 0.0,
+                    magnitude: 0.0,
+                },
+            })
+            .collect()
+    }
+
+    // Untracked formant candidates, lowest first.
+    fn candidates(&mut self, samples: &[f32], sample_rate: usize) -> Vec<Formant> {
+        if samples.len() < 4 || sample_rate == 0 {
+            return Vec::new();
+        }
+        let options = &self.options;
+        let lpc_rate = ((2.2 * options.max_freq_hz) as usize).clamp(1, sample_rate);
+        let resampler = match &self.resampler {
+            Some(r) if r.from_rate() == sample_rate && r.to_rate() == lpc_rate => r,
+            _ => self.resampler.insert(Resampler::new(
+                sample_rate,
+                lpc_rate,
+                &ResampleOptions {
+                    zero_crossings: 8,
+                    ..Default::default()
+                },
+            )),
+        };
+        let mut frame = resampler.process(samples);
+        // a short frame at a high rate may keep no sample for LPC
+        if frame.len() < 2 {
+            return Vec::new();
+        }
+        for idx in (1..frame.len()).rev() {
+            frame[idx] -= options.pre_emphasis * frame[idx - 1];
+        }
+        let window = WindowFunction::Hamming.coefficients(frame.len());
+        for (x, w) in frame.iter_mut().zip(&window) {
+            *x *= w;
+        }
+        let order = options
+            .lpc_order
+            .unwrap_or(2 + lpc_rate / 1000)
+            .min(frame.len() - 1);
+        let (a, error) = lpc_coefficients(&frame, order);
+        if error <= 0.0 {
+            return Vec::new();
+        }
+
+        let rate = lpc_rate as f64;
+        let mut formants: Vec<(f32, f32)> = polynomial_roots(&a)
+            .into_iter()
+            .filter(|root| root.im > 0.0)
+            .map(|root| {
+                let freq = root.arg() * rate / std::f64::consts::TAU;
+                let bandwidth = -root.norm().ln() * rate / std::f64::consts::PI;
+                (freq as f32, bandwidth as f32)
+            })
+            .filter(|&(freq, bandwidth)| {
+                freq >= options.min_freq_hz
+                    && freq <= options.max_freq_hz
+                    && bandwidth > 0.0
+                    && bandwidth <= options.max_bandwidth_hz
+            })
+            .collect();
+        formants.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let magnitudes = self.band_magnitudes(samples, sample_rate, &formants);
+        formants
+            .into_iter()
+            .zip(magnitudes)
+            .map(|((freq_hz, bandwidth_hz), magnitude)| Formant {
+                freq_hz,
+                bandwidth_hz,
+                magnitude,
+            })
+            .collect()
+    }
+
+    // Energy of `samples` within each formant's band (centre +/- bandwidth,
+    // at least the window's main lobe), as the amplitude of a sinusoid of
+    // equal energy.
+    fn band_magnitudes(
+        &mut self,
+        samples: &[f32],
+        sample_rate: usize,
+        formants: &[(f32, f32)],
+    ) -> Vec<f32> {
+        let len = samples.len();
+        let size = len.next_power_of_two();
+        if self.fft_size != size || self.fft.is_none() {
+            self.fft = Some(FftPlanner::new().plan_fft_forward(size));
+            self.fft_size = size;
+        }
+        let window = WindowFunction::Hann.coefficients(len);
+        let mut buffer: Vec<Complex<f32>> = samples
+            .iter()
+            .zip(&window)
+            .map(|(&x, &w)| Complex::new(x * w, 0.0))
+            .collect();
+        buffer.resize(size, Complex::new(0.0, 0.0));
+        if let Some(fft) = &self.fft {
+            fft.process(&mut buffer);
+        }
+        let power_sum: f32 = window.iter().map(|w| w * w).sum();
+        let bin_hz = sample_rate as f32 / size as f32;
+        let main_lobe_hz = 2.0 * sample_rate as f32 / len as f32;
+        formants
+            .iter()
+            .map(|&(freq, bandwidth)| {
+                let half = bandwidth.max(main_lobe_hz);
+                let lo = ((freq - half) / bin_hz).ceil().max(0.0) as usize;
+                let hi = (((freq + half) / bin_hz).floor() as usize).min(size / 2);
+                let energy: f32 = buffer[lo..=hi.max(lo)].iter().map(|c| c.norm_sqr()).sum();
+                (4.0 * energy / (size as f32 * power_sum)).sqrt()
+            })
+            .collect()
+    }
+}
+
+// Assign candidates (sorted by frequency) to slots in order, minimizing the
+// total log-frequency distance to each slot's previous value; a slot may
+// stay empty at a fixed cost. Returns the candidate index of each slot.
+fn assign_slots(slots: &[f32], candidates: &[Formant]) -> Vec<Option<usize>> {
+    // skipping a slot costs as much as moving it by an octave
+    const SKIP: f32 = 1.0;
+    let (n, m) = (slots.len(), candidates.len());
+    let distance = |s: usize, c: usize| (candidates[c].freq_hz / slots[s].max(1.0)).log2().abs();
+    // cost[s][c]: best cost of slots s.. using candidates c..
+    let mut cost = vec![vec![0.0f32; m + 1]; n + 1];
+    for s in (0..n).rev() {
+        cost[s][m] = cost[s + 1][m] + SKIP;
+        for c in (0..m).rev() {
+            let skip_slot = cost[s + 1][c] + SKIP;
+            let skip_candidate = cost[s][c + 1];
+            let take = cost[s + 1][c + 1] + distance(s, c);
+            cost[s][c] = skip_slot.min(skip_candidate).min(take);
+        }
+    }
+    let mut assignment = vec![None; n];
+    let (mut s, mut c) = (0, 0);
+    while s < n {
+        if c < m && cost[s][c] == cost[s + 1][c + 1] + distance(s, c) {
+            assignment[s] = Some(c);
+            s += 1;
+            c += 1;
+        } else if c < m && cost[s][c] == cost[s][c + 1] {
+            c += 1;
+        } else {
+            s += 1;
+        }
+    }
+    assignment
+}
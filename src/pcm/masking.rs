@@ -0,0 +1,84 @@
+use super::{spectral_flatness, to_db};
+
+// Playback level of a full-scale sine, used to place the absolute threshold
+// of hearing on the dBFS scale.
+const FULL_SCALE_SPL_DB: f32 = 96.0;
+
+/// Critical-band rate in Bark for `freq_hz` (Zwicker & Terhardt).
+pub fn hz_to_bark(freq_hz: f32) -> f32 {
+    let f = freq_hz.max(0.0);
+    13.0 * (0.00076 * f).atan() + 3.5 * (f / 7500.0).powi(2).atan()
+}
+
+// Absolute threshold of hearing (Terhardt) in dBFS.
+fn absolute_threshold_dbfs(freq_hz: f32) -> f32 {
+    let khz = freq_hz.max(20.0) / 1000.0;
+    let spl = 3.64 * khz.powf(-0.8) - 6.5 * (-0.6 * (khz - 3.3).powi(2)).exp() + 1e-3 * khz.powi(4);
+    spl - FULL_SCALE_SPL_DB
+}
+
+// Schroeder spreading function in dB for a maskee `dz` Bark above the masker.
+fn spreading_db(dz: f32) -> f32 {
+    let x = dz + 0.474;
+    15.81 + 7.5 * x - 17.5 * (1.0 + x * x).sqrt()
+}
+
+/// Per-frame simultaneous masking threshold (simplified Johnston model).
+///
+/// Spectral power is collected into one-Bark critical bands, spread across
+/// bands with the Schroeder spreading function and lowered by a
+/// tonality-dependent offset. The threshold never drops below the absolute
+/// threshold of hearing.
+#[derive(Debug, Clone, Default)]
+pub(super) struct MaskingModel {
+    band_power: Vec<f32>,
+    tonal_alpha: f32,
+}
+
+impl MaskingModel {
+    /// Rebuild the band powers from calibrated magnitudes `mags` (bin `k`
+    /// centred at `k * bin_hz`).
+    pub(super) fn update(&mut self, mags: &[f32], bin_hz: f32) {
+        let bands = (hz_to_bark(bin_hz * mags.len() as f32).floor() as usize) + 1;
+        self.band_power.clear();
+        self.band_power.resize(bands, 0.0);
+        for (k, &m) in mags.iter().enumerate().skip(1) {
+            let band = (hz_to_bark(k as f32 * bin_hz) as usize).min(bands - 1);
+            self.band_power[band] += m * m;
+        }
+        // 1 for a pure tone (SFM <= -60 dB), 0 for white noise
+        let sfm_db = 10.0 * spectral_flatness(mags).max(1e-12).log10();
+        self.tonal_alpha = (sfm_db / -60.0).clamp(0.0, 1.0);
+    }
+
+    /// Signal-to-mask ratio in dB of the peak at `bin`, masked by every
+    /// other component of the frame (the peak's own main lobe is excluded).
+    pub(super) fn signal_to_mask_db(&self, mags: &[f32], bin: usize, bin_hz: f32) -> f32 {
+        let freq = bin as f32 * bin_hz;
+        let z = hz_to_bark(freq);
+        let own_band = (z as usize).min(self.band_power.len().saturating_sub(1));
+        let own_power: f32 = mags[bin.saturating_sub(2)..(bin + 3).min(mags.len())]
+            .iter()
+            .map(|m| m * m)
+            .sum();
+
+        let spread = |band: usize, power: f32| {
+            power * 10f32.powf(spreading_db(z - (band as f32 + 0.5)) / 10.0)
+        };
+        let mut masker_power: f32 = self
+            .band_power
+            .iter()
+            .enumerate()
+            .map(|(band, &power)| spread(band, power))
+            .sum();
+        masker_power = (masker_power - spread(own_band, own_power)).max(0.0);
+
+        let offset_db = self.tonal_alpha * (14.5 + z) + (1.0 - self.tonal_alpha) * 5.5;
+        let spread_db = if masker_power > 0.0 {
+            10.0 * masker_power.log10() - offset_db
+        } else {
+            f32::MIN
+        };
+        to_db(mags[bin]) - spread_db.max(absolute_threshold_dbfs(freq))
+    }
+}
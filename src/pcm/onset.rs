@@ -0,0 +1,160 @@
+use super::WindowFunction;
+use rustfft::{FftPlanner, num_complex::Complex};
+
+/// Onset detection function.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OnsetMethod {
+    /// Half-wave rectified increase of the magnitude spectrum.
+    #[default]
+    SpectralFlux,
+    /// Distance from the spectrum predicted from the two previous frames
+    /// (steady magnitude, steady phase advance), counted only in bins whose
+    /// magnitude grows.
+    ComplexDomain,
+}
+
+/// Options for `detect_onsets`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OnsetOptions {
+    pub method: OnsetMethod,
+    /// STFT frame length in samples.
+    pub frame_size: usize,
+    /// STFT hop in samples; sets the time resolution of the onsets.
+    pub hop_size: usize,
+    /// Fixed part of the adaptive threshold, relative to the strongest
+    /// value of the (max-normalized) detection function.
+    pub threshold: f32,
+    /// Half-width in frames of the moving median added to `threshold`.
+    pub median_frames: usize,
+    /// Minimum time between two onsets (seconds).
+    pub min_interval_s: f32,
+}
+
+impl Default for OnsetOptions {
+    fn default() -> Self {
+        OnsetOptions {
+            method: OnsetMethod::SpectralFlux,
+            frame_size: 1024,
+            hop_size: 128,
+            threshold: 0.1,
+            median_frames: 8,
+            min_interval_s: 0.05,
+        }
+    }
+}
+
+/// A detected onset.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Onset {
+    /// Position in input samples.
+    pub sample: usize,
+    /// Position in seconds.
+    pub time_s: f32,
+    /// Normalized detection function value at the onset (0..1).
+    pub strength: f32,
+}
+
+/// Onset detection function of `samples`, one value per `hop_size`, where
+/// value `n` describes the STFT frame centred on sample `n * hop_size`.
+/// Frames reaching past the end of `samples` read 0.
+pub fn onset_detection_function(samples: &[f32], options: &OnsetOptions) -> Vec<f32> {
+    let frame_size = options.frame_size.max(2);
+    let hop = options.hop_size.max(1);
+    if samples.is_empty() {
+        return Vec::new();
+    }
+    let frames = samples.len().div_ceil(hop);
+    let fft_size = frame_size.next_power_of_two();
+    let fft = FftPlanner::<f32>::new().plan_fft_forward(fft_size);
+    let window = WindowFunction::Hann.coefficients(frame_size);
+    let bins = fft_size / 2 + 1;
+
+    let mut buffer = vec![Complex::new(0.0f32, 0.0); fft_size];
+    let mut prev = vec![Complex::new(0.0f32, 0.0); bins];
+    let mut prev2 = vec![Complex::new(0.0f32, 0.0); bins];
+    let mut odf = Vec::with_capacity(frames);
+    for n in 0..frames {
+        let start = (n * hop) as isize - (frame_size / 2) as isize;
+        for (i, slot) in buffer.iter_mut().enumerate() {
+            let idx = start + i as isize;
+            let sample = if i < frame_size && idx >= 0 {
+                samples.get(idx as usize).copied().unwrap_or(0.0)
+            } else {
+                0.0
+            };
+            *slot = Complex::new(sample * window.get(i).copied().unwrap_or(0.0), 0.0);
+        }
+        fft.process(&mut buffer);
+
+        let value: f32 = match options.method {
+            OnsetMethod::SpectralFlux => (0..bins)
+                .map(|k| (buffer[k].norm() - prev[k].norm()).max(0.0))
+                .sum(),
+            OnsetMethod::ComplexDomain => (0..bins)
+                .filter(|&k| buffer[k].norm() >= prev[k].norm())
+                .map(|k| {
+                    let phase = 2.0 * prev[k].arg() - prev2[k].arg();
+                    let predicted = Complex::from_polar(prev[k].norm(), phase);
+                    (buffer[k] - predicted).norm()
+                })
+                .sum(),
+        };
+        // the end of the input looks like a broadband transient
+        let past_end = start + frame_size as isize > samples.len() as isize;
+        odf.push(if past_end { 0.0 } else { value });
+        prev2.copy_from_slice(&prev);
+        prev.copy_from_slice(&buffer[..bins]);
+    }
+    odf
+}
+
+/// Detect onsets in mono `samples`.
+///
+/// The detection function is normalized to its maximum and peak-picked
+/// against an adaptive threshold (`threshold` plus the moving median over
+/// `median_frames`); peaks closer than `min_interval_s` to the previous
+/// onset are skipped.
+pub fn detect_onsets(samples: &[f32], sample_rate: usize, options: &OnsetOptions) -> Vec<Onset> {
+    let mut odf = onset_detection_function(samples, options);
+    if odf.len() < 3 || sample_rate == 0 {
+        return Vec::new();
+    }
+    let max = odf.iter().copied().fold(0.0f32, f32::max);
+    if max <= 0.0 {
+        return Vec::new();
+    }
+    for v in &mut odf {
+        *v /= max;
+    }
+
+    let hop = options.hop_size.max(1);
+    let min_gap = (options.min_interval_s.max(0.0) * sample_rate as f32) as usize;
+    let radius = options.median_frames;
+    let mut neighborhood: Vec<f32> = Vec::with_capacity(2 * radius + 1);
+    let mut onsets: Vec<Onset> = Vec::new();
+    for n in 1..odf.len() - 1 {
+        let v = odf[n];
+        if v < odf[n - 1] || v <= odf[n + 1] {
+            continue;
+        }
+        let lo = n.saturating_sub(radius);
+        let hi = (n + radius + 1).min(odf.len());
+        neighborhood.clear();
+        neighborhood.extend_from_slice(&odf[lo..hi]);
+        let mid = neighborhood.len() / 2;
+        let (_, median, _) = neighborhood.select_nth_unstable_by(mid, |a, b| a.total_cmp(b));
+        if v < options.threshold + *median {
+            continue;
+        }
+        let sample = n * hop;
+        if onsets.last().is_some_and(|o| sample - o.sample < min_gap) {
+            continue;
+        }
+        onsets.push(Onset {
+            sample,
+            time_s: sample as f32 / sample_rate as f32,
+            strength: v,
+        });
+    }
+    onsets
+}
@@ -0,0 +1,223 @@
+use super::Peak;
+use std::f32::consts::{PI, TAU};
+
+/// Options for `OscillatorBank`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OscillatorOptions {
+    /// Largest pitch difference (cents) between a sinusoid of the previous
+    /// block and one of the next block for both to be rendered by the same
+    /// oscillator, keeping its phase.
+    pub max_freq_deviation_cents: f32,
+    /// Crossfade length in samples. `0` interpolates frequency and
+    /// amplitude across each block. Otherwise each block holds its
+    /// parameters and runs this many samples past its end, where it is
+    /// faded out under the next block's raised-cosine fade-in (overlap-add).
+    /// It should not exceed the shortest block.
+    pub overlap_samples: usize,
+}
+
+impl Default for OscillatorOptions {
+    fn default() -> Self {
+        OscillatorOptions {
+            max_freq_deviation_cents: 50.0,
+            overlap_samples: 0,
+        }
+    }
+}
+
+// One running sinusoid.
+#[derive(Debug, Clone, Copy)]
+struct Oscillator {
+    freq_hz: f32,
+    amp: f32,
+    phase: f32,
+}
+
+// What an oscillator does over the block being rendered.
+#[derive(Debug, Clone, Copy)]
+struct Segment {
+    from: Oscillator,
+    to_freq_hz: f32,
+    to_amp: f32,
+}
+
+/// A bank of sinusoidal oscillators that renders consecutive blocks of
+/// sinusoids without discontinuities.
+///
+/// Each call to `render` describes the next block by its sinusoids. A
+/// sinusoid close in pitch to one of the previous block continues that
+/// oscillator's phase; new sinusoids fade in from silence and sinusoids
+/// that disappear fade out, so held notes sound like a chip voice holding
+/// its key rather than being restarted at every block boundary.
+///
+/// Amplitudes are taken as-is from `Peak::magnitude` (no normalization).
+#[derive(Debug, Clone)]
+pub struct OscillatorBank {
+    options: OscillatorOptions,
+    sample_rate: usize,
+    oscillators: Vec<Oscillator>,
+    // overlap-add tail of the previous block
+    tail: Vec<f32>,
+}
+
+impl OscillatorBank {
+    pub fn new(options: OscillatorOptions, sample_rate: usize) -> Self {
+        OscillatorBank {
+            options,
+            sample_rate,
+            oscillators: Vec::new(),
+            tail: Vec::new(),
+        }
+    }
+
+    pub fn options(&self) -> &OscillatorOptions {
+        &self.options
+    }
+
+    /// Number of oscillators still sounding at the end of the last block.
+    pub fn active_count(&self) -> usize {
+        self.oscillators.len()
+    }
+
+    /// Add the next block, sounding `peaks`, to `out`.
+    pub fn render(&mut self, peaks: &[Peak], out: &mut [f32]) {
+        if self.sample_rate == 0 {
+            return;
+        }
+        let targets: Vec<&Peak> = peaks
+            .iter()
+            .filter(|p| p.freq_hz > 0.0 && p.magnitude > 0.0)
+            .collect();
+        let segments = self.match_oscillators(&targets);
+        if self.options.overlap_samples == 0 {
+            self.render_interpolated(&segments, out);
+        } else {
+            self.render_overlap_add(&segments, out);
+        }
+    }
+
+    // Pair the running oscillators with the next block's sinusoids, closest
+    // in pitch first. Unpaired oscillators fade out; unpaired sinusoids
+    // start a new oscillator from silence.
+    fn match_oscillators(&self, targets: &[&Peak]) -> Vec<Segment> {
+        let mut pairs: Vec<(f32, usize, usize)> = Vec::new();
+        for (o, osc) in self.oscillators.iter().enumerate() {
+            for (t, target) in targets.iter().enumerate() {
+                let cents = (1200.0 * (target.freq_hz / osc.freq_hz).log2()).abs();
+                if cents <= self.options.max_freq_deviation_cents {
+                    pairs.push((cents, o, t));
+                }
+            }
+        }
+        pairs.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let mut osc_taken = vec![false; self.oscillators.len()];
+        let mut target_taken = vec![false; targets.len()];
+        let mut segments: Vec<Segment> = Vec::with_capacity(targets.len() + osc_taken.len());
+        for &(_, o, t) in &pairs {
+            if osc_taken[o] || target_taken[t] {
+                continue;
+            }
+            osc_taken[o] = true;
+            target_taken[t] = true;
+            segments.push(Segment {
+                from: self.oscillators[o],
+                to_freq_hz: targets[t].freq_hz,
+                to_amp: targets[t].magnitude,
+            });
+        }
+        for (o, osc) in self.oscillators.iter().enumerate() {
+            if !osc_taken[o] {
+                segments.push(Segment {
+                    from: *osc,
+                    to_freq_hz: osc.freq_hz,
+                    to_amp: 0.0,
+                });
+            }
+        }
+        for (t, target) in targets.iter().enumerate() {
+            if !target_taken[t] {
+                segments.push(Segment {
+                    from: Oscillator {
+                        freq_hz: target.freq_hz,
+                        amp: 0.0,
+                        phase: 0.0,
+                    },
+                    to_freq_hz: target.freq_hz,
+                    to_amp: target.magnitude,
+                });
+            }
+        }
+        segments
+    }
+
+    // Frequency and amplitude move linearly from the previous block's values
+    // to the new ones across the block.
+    fn render_interpolated(&mut self, segments: &[Segment], out: &mut [f32]) {
+        let count = out.len().max(1) as f32;
+        let to_omega = TAU / self.sample_rate as f32;
+        self.oscillators.clear();
+        for seg in segments {
+            let mut phase = seg.from.phase;
+            for (idx, slot) in out.iter_mut().enumerate() {
+                let t = (idx + 1) as f32 / count;
+                let freq = seg.from.freq_hz + (seg.to_freq_hz - seg.from.freq_hz) * t;
+                let amp = seg.from.amp + (seg.to_amp - seg.from.amp) * t;
+                phase = (phase + freq * to_omega) % TAU;
+                *slot += amp * phase.sin();
+            }
+            if seg.to_amp > 0.0 {
+                self.oscillators.push(Oscillator {
+                    freq_hz: seg.to_freq_hz,
+                    amp: seg.to_amp,
+                    phase,
+                });
+            }
+        }
+    }
+
+    // Each oscillator holds its new parameters over the block and the
+    // `overlap_samples` after it; the block fades in over the previous
+    // block's tail, and its own tail is kept for the next block.
+    fn render_overlap_add(&mut self, segments: &[Segment], out: &mut [f32]) {
+        let len = out.len();
+        let overlap = self.options.overlap_samples;
+        let to_omega = TAU / self.sample_rate as f32;
+        let fade_in = |i: usize| 0.5 - 0.5 * (PI * (i as f32 + 0.5) / overlap as f32).cos();
+
+        let previous_tail = std::mem::take(&mut self.tail);
+        let mut tail = vec![0.0f32; overlap];
+        self.oscillators.clear();
+        for seg in segments.iter().filter(|seg| seg.to_amp > 0.0) {
+            let omega = seg.to_freq_hz * to_omega;
+            let mut phase = seg.from.phase;
+            for idx in 0..len + overlap {
+                if idx == len {
+                    self.oscillators.push(Oscillator {
+                        freq_hz: seg.to_freq_hz,
+                        amp: seg.to_amp,
+                        phase,
+                    });
+                }
+                phase = (phase + omega) % TAU;
+                let mut value = seg.to_amp * phase.sin();
+                if idx < overlap {
+                    value *= fade_in(idx);
+                }
+                if idx < len {
+                    out[idx] += value;
+                } else {
+                    tail[idx - len] += value * (1.0 - fade_in(idx - len));
+                }
+            }
+        }
+        for (idx, value) in previous_tail.into_iter().enumerate() {
+            if idx < len {
+                out[idx] += value;
+            } else {
+                tail[idx - len] += value;
+            }
+        }
+        self.tail = tail;
+    }
+}
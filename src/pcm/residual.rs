@@ -0,0 +1,258 @@
+use super::{Peak, WindowFunction};
+use rustfft::{Fft, FftPlanner, num_complex::Complex};
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+/// Octave band edges (Hz) used by `ResidualOptions::default`.
+pub const DEFAULT_NOISE_BAND_EDGES_HZ: [f32; 8] =
+    [125.0, 250.0, 500.0, 1000.0, 2000.0, 4000.0, 8000.0, 16000.0];
+
+/// Options for the sinusoidal-plus-noise residual (`ResidualAnalyzer`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResidualOptions {
+    /// Ascending band edges in Hz; band `i` spans `edges[i]..edges[i + 1]`.
+    pub band_edges_hz: Vec<f32>,
+    /// Half-width in bins of the region removed around each sinusoid. It
+    /// should cover the main lobe of `window` (2 bins for Hann).
+    pub peak_half_width_bins: usize,
+    /// Analysis window applied before the FFT.
+    pub window: WindowFunction,
+}
+
+impl Default for ResidualOptions {
+    fn default() -> Self {
+        ResidualOptions {
+            band_edges_hz: DEFAULT_NOISE_BAND_EDGES_HZ.to_vec(),
+            peak_half_width_bins: 3,
+            window: WindowFunction::Hann,
+        }
+    }
+}
+
+impl ResidualOptions {
+    /// Number of bands described by `band_edges_hz`.
+    pub fn band_count(&self) -> usize {
+        self.band_edges_hz.len().saturating_sub(1)
+    }
+}
+
+/// Describes what is left of a frame once its sinusoids are removed as a
+/// band-limited noise envelope.
+///
+/// The sinusoids are subtracted in the power spectrum: the bins under each
+/// sinusoid's main lobe are replaced by a line joining the spectrum on both
+/// sides of the lobe. The remaining power is summed per band and reported as
+/// the RMS amplitude of the residual signal within that band.
+pub struct ResidualAnalyzer {
+    options: ResidualOptions,
+    planner: FftPlanner<f32>,
+    fft: Option<Arc<dyn Fft<f32>>>,
+    fft_size: usize,
+    window: Vec<f32>,
+    window_power: f32,
+    buffer: Vec<Complex<f32>>,
+    scratch: Vec<Complex<f32>>,
+    power: Vec<f32>,
+}
+
+impl ResidualAnalyzer {
+    pub fn new(options: ResidualOptions) -> Self {
+        ResidualAnalyzer {
+            options,
+            planner: FftPlanner::new(),
+            fft: None,
+            fft_size: 0,
+            window: Vec::new(),
+            window_power: 0.0,
+            buffer: Vec::new(),
+            scratch: Vec::new(),
+            power: Vec::new(),
+        }
+    }
+
+    pub fn options(&self) -> &ResidualOptions {
+        &self.options
+    }
+
+    /// Per-band RMS amplitude of `samples` with `sinusoids` removed, one
+    /// value per band of `ResidualOptions::band_edges_hz`.
+    pub fn analyze(&mut self, samples: &[f32], sample_rate: usize, sinusoids: &[Peak]) -> Vec<f32> {
+        let bands = self.options.band_count();
+        let mut levels = vec![0.0f32; bands];
+        if samples.is_empty() || sample_rate == 0 || bands == 0 {
+            return levels;
+        }
+
+        self.prepare(samples.len());
+        let fft = Arc::clone(self.fft.as_ref().expect("fft planned in prepare"));
+        for (idx, slot) in self.buffer.iter_mut().enumerate() {
+            *slot = match (samples.get(idx), self.window.get(idx)) {
+                (Some(&s), Some(&w)) => Complex::new(s * w, 0.0),
+                _ => Complex::new(0.0, 0.0),
+            };
+        }
+        fft.process_with_scratch(&mut self.buffer, &mut self.scratch);
+
+        let fft_size = self.fft_size;
+        let half = fft_size / 2;
+        self.power.clear();
+        self.power
+            .extend(self.buffer[..=half].iter().map(|c| c.norm_sqr()));
+
+        let bin_hz = sample_rate as f32 / fft_size as f32;
+        let width = self.options.peak_half_width_bins;
+        for peak in sinusoids {
+            let center = (peak.freq_hz / bin_hz).round();
+            if !(center > 0.0 && center < half as f32) {
+                continue;
+            }
+            let center = center as usize;
+            let lo = center.saturating_sub(width + 1);
+            let hi = (center + width + 1).min(half);
+            let (p_lo, p_hi) = (self.power[lo], self.power[hi]);
+            for k in lo + 1..hi {
+                let t = (k - lo) as f32 / (hi - lo) as f32;
+                self.power[k] = self.power[k].min(p_lo + (p_hi - p_lo) * t);
+            }
+        }
+
+        // Parseval: a one-sided bin carries 2 |X|^2 / (N * sum(w^2)) of the
+        // signal's mean square
+        let scale = 2.0 / (fft_size as f32 * self.window_power);
+        let edges = &self.options.band_edges_hz;
+        for (band, level) in levels.iter_mut().enumerate() {
+            let lo = ((edges[band] / bin_hz).ceil() as usize).max(1);
+            let hi = ((edges[band + 1] / bin_hz).ceil() as usize).min(half);
+            if lo >= hi {
+                continue;
+            }
+            let sum: f32 = self.power[lo..hi].iter().sum();
+            *level = (sum * scale).sqrt();
+        }
+        levels
+    }
+
+    fn prepare(&mut self, len: usize) {
+        let fft_size = len.next_power_of_two();
+        if self.fft.is_none() || self.fft_size != fft_size {
+            let fft = self.planner.plan_fft_forward(fft_size);
+            self.scratch = vec![Complex::new(0.0, 0.0); fft.get_inplace_scratch_len()];
+            self.buffer = vec![Complex::new(0.0, 0.0); fft_size];
+            self.fft = Some(fft);
+            self.fft_size = fft_size;
+        }
+        if self.window.len() != len {
+            self.window = self.options.window.coefficients(len);
+            self.window_power = self.window.iter().map(|w| w * w).sum::<f32>().max(1e-12);
+        }
+    }
+}
+
+// RBJ band-pass biquad (0 dB peak gain) with its state.
+#[derive(Debug, Clone)]
+struct BandPass {
+    b0: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+    x1: f32,
+    x2: f32,
+    y1: f32,
+    y2: f32,
+    // white-noise drive that yields unit RMS at the output
+    drive: f32,
+}
+
+impl BandPass {
+    fn new(lo_hz: f32, hi_hz: f32, sample_rate: f32) -> Self {
+        let center = (lo_hz * hi_hz).sqrt();
+        let bandwidth = hi_hz - lo_hz;
+        let w0 = 2.0 * PI * center / sample_rate;
+        // bandwidth in octaves, prewarped for the bilinear transform
+        let octaves = (hi_hz / lo_hz).log2();
+        let alpha = w0.sin() * (std::f32::consts::LN_2 / 2.0 * octaves * w0 / w0.sin()).sinh();
+        let a0 = 1.0 + alpha;
+        BandPass {
+            b0: alpha / a0,
+            b2: -alpha / a0,
+            a1: -2.0 * w0.cos() / a0,
+            a2: (1.0 - alpha) / a0,
+            x1: 0.0,
+            x2: 0.0,
+            y1: 0.0,
+            y2: 0.0,
+            // equivalent noise bandwidth of a second-order band-pass is
+            // pi/2 times its -3 dB bandwidth
+            drive: (sample_rate / (PI * bandwidth)).sqrt(),
+        }
+    }
+
+    fn process(&mut self, x: f32) -> f32 {
+        let y = self.b0 * x + self.b2 * self.x2 - self.a1 * self.y1 - self.a2 * self.y2;
+        self.x2 = self.x1;
+        self.x1 = x;
+        self.y2 = self.y1;
+        self.y1 = y;
+        y
+    }
+}
+
+/// Renders a residual noise envelope as filtered white noise.
+///
+/// A white-noise generator feeds one band-pass filter per band; band
+/// levels are interpolated linearly across each rendered block and filter
+/// state carries over between blocks, so consecutive blocks join without
+/// clicks. Bands reaching past the output Nyquist frequency are skipped.
+pub struct NoiseSynthesizer {
+    bands: Vec<Option<BandPass>>,
+    levels: Vec<f32>,
+    state: u32,
+}
+
+impl NoiseSynthesizer {
+    pub fn new(band_edges_hz: &[f32], sample_rate: usize) -> Self {
+        let nyquist = sample_rate as f32 / 2.0;
+        let bands: Vec<Option<BandPass>> = band_edges_hz
+            .windows(2)
+            .map(|edge| {
+                (edge[0] > 0.0 && edge[1] > edge[0] && edge[1] < nyquist)
+                    .then(|| BandPass::new(edge[0], edge[1], sample_rate as f32))
+            })
+            .collect();
+        let levels = vec![0.0; bands.len()];
+        NoiseSynthesizer {
+            bands,
+            levels,
+            state: 0x1234_5678,
+        }
+    }
+
+    /// Add noise to `out`, moving from the previous band levels to `levels`
+    /// (per-band RMS amplitudes as produced by `ResidualAnalyzer`).
+    pub fn render(&mut self, levels: &[f32], out: &mut [f32]) {
+        let count = out.len().max(1) as f32;
+        for (idx, slot) in out.iter_mut().enumerate() {
+            let t = (idx + 1) as f32 / count;
+            let mut sum = 0.0f32;
+            for (band, filter) in self.bands.iter_mut().enumerate() {
+                let Some(filter) = filter else {
+                    continue;
+                };
+                let from = self.levels[band];
+                let to = levels.get(band).copied().unwrap_or(0.0);
+                let level = from + (to - from) * t;
+                self.state = self
+                    .state
+                    .wrapping_mul(1_664_525)
+                    .wrapping_add(1_013_904_223);
+                // uniform in -1..1 has RMS 1/sqrt(3)
+                let white = ((self.state >> 8) as f32 / (1u32 << 23) as f32 - 1.0) * 3f32.sqrt();
+                sum += level * filter.process(white * filter.drive);
+            }
+            *slot += sum;
+        }
+        for (band, level) in self.levels.iter_mut().enumerate() {
+            *level = levels.get(band).copied().unwrap_or(0.0);
+        }
+    }
+}
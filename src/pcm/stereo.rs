@@ -0,0 +1,179 @@
+use super::{Peak, WindowFunction};
+use std::f32::consts::TAU;
+
+/// How stereo input is analyzed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StereoAnalysis {
+    /// Pick peaks on the mid signal `(left + right) / 2` and measure each
+    /// peak's level in both channels to place it.
+    #[default]
+    MidSide,
+    /// Pick peaks in each channel separately and merge them; a partial
+    /// present in one channel only is panned fully to that side.
+    PerChannel,
+}
+
+/// Stereo placement options.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StereoOptions {
+    pub analysis: StereoAnalysis,
+    /// For mono input: place voices across the stereo field by frequency,
+    /// from the left (bottom of the analysis band) to the right (top of
+    /// it), on a log scale. `0` keeps every voice centred, `1` reaches both
+    /// sides.
+    pub mono_spread: f32,
+    /// Two peaks of the left and right channel closer than this (cents) are
+    /// the same partial (`StereoAnalysis::PerChannel`).
+    pub merge_tolerance_cents: f32,
+}
+
+impl Default for StereoOptions {
+    fn default() -> Self {
+        StereoOptions {
+            analysis: StereoAnalysis::MidSide,
+            mono_spread: 0.0,
+            merge_tolerance_cents: 25.0,
+        }
+    }
+}
+
+/// Pan position from the amplitudes of a partial in the left and right
+/// channels: `-1` is left only, `0` centre, `1` right only (the normalized
+/// power difference). A partial absent from both channels is centred.
+pub fn pan_from_levels(left: f32, right: f32) -> f32 {
+    let (l, r) = (left * left, right * right);
+    if l + r > 0.0 { (r - l) / (r + l) } else { 0.0 }
+}
+
+/// Left and right amplitudes of a partial with mid magnitude `magnitude`
+/// (the mean of both channels) at `pan`; the inverse of `pan_from_levels`.
+pub fn pan_to_levels(magnitude: f32, pan: f32) -> (f32, f32) {
+    let pan = pan.clamp(-1.0, 1.0);
+    let (l, r) = (((1.0 - pan) / 2.0).sqrt(), ((1.0 + pan) / 2.0).sqrt());
+    let scale = 2.0 * magnitude / (l + r);
+    (l * scale, r * scale)
+}
+
+/// Pan of each of `peaks` (found in the mid signal) from the level of its
+/// frequency in `left` and `right`, measured with a single-bin DFT over
+/// the windowed frames.
+pub fn estimate_pan(
+    left: &[f32],
+    right: &[f32],
+    sample_rate: usize,
+    peaks: &[Peak],
+    window: WindowFunction,
+) -> Vec<f32> {
+    let len = left.len().min(right.len());
+    if len == 0 || sample_rate == 0 {
+        return vec![0.0; peaks.len()];
+    }
+    let coefficients = window.coefficients(len);
+    let level = |samples: &[f32], omega: f32| {
+        let (mut re, mut im) = (0.0f32, 0.0f32);
+        for (n, (&x, &w)) in samples.iter().zip(&coefficients).enumerate() {
+            let (sin, cos) = (omega * n as f32).sin_cos();
+            re += x * w * cos;
+            im -= x * w * sin;
+        }
+        re.hypot(im)
+    };
+    peaks
+        .iter()
+        .map(|peak| {
+            let omega = TAU * peak.freq_hz / sample_rate as f32;
+            pan_from_levels(level(&left[..len], omega), level(&right[..len], omega))
+        })
+        .collect()
+}
+
+/// Merge the peaks picked in the left and right channels into one list of
+/// partials with their pans, loudest first.
+///
+/// Peaks within `tolerance_cents` of each other (closest pairs first) are
+/// one partial at their magnitude-weighted frequency; its magnitude is the
+/// mean of both channels, as for the mid signal. Unpaired peaks count as
+/// silent in the other channel.
+pub fn merge_channel_peaks(
+    left: &[Peak],
+    right: &[Peak],
+    tolerance_cents: f32,
+) -> (Vec<Peak>, Vec<f32>) {
+    let mut pairs: Vec<(f32, usize, usize)> = Vec::new();
+    for (l, lp) in left.iter().enumerate() {
+        for (r, rp) in right.iter().enumerate() {
+            if lp.freq_hz <= 0.0 || rp.freq_hz <= 0.0 {
+                continue;
+            }
+            let cents = (1200.0 * (rp.freq_hz / lp.freq_hz).log2()).abs();
+            if cents <= tolerance_cents {
+                pairs.push((cents, l, r));
+            }
+        }
+    }
+    pairs.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let mut left_taken = vec![false; left.len()];
+    let mut right_taken = vec![false; right.len()];
+    let mut merged: Vec<(Peak, f32)> = Vec::with_capacity(left.len() + right.len());
+    for &(_, l, r) in &pairs {
+        if left_taken[l] || right_taken[r] {
+            continue;
+        }
+        left_taken[l] = true;
+        right_taken[r] = true;
+        let (lp, rp) = (&left[l], &right[r]);
+        let total = lp.magnitude + rp.magnitude;
+        let freq_hz = if total > 0.0 {
+            (lp.freq_hz * lp.magnitude + rp.freq_hz * rp.magnitude) / total
+        } else {
+            lp.freq_hz
+        };
+        let louder = if lp.magnitude >= rp.magnitude { lp } else { rp };
+        merged.push((
+            with_magnitude(louder, freq_hz, total / 2.0),
+            pan_from_levels(lp.magnitude, rp.magnitude),
+        ));
+    }
+    for (l, peak) in left.iter().enumerate() {
+        if !left_taken[l] {
+            merged.push((
+                with_magnitude(peak, peak.freq_hz, peak.magnitude / 2.0),
+                -1.0,
+            ));
+        }
+    }
+    for (r, peak) in right.iter().enumerate() {
+        if !right_taken[r] {
+            merged.push((
+                with_magnitude(peak, peak.freq_hz, peak.magnitude / 2.0),
+                1.0,
+            ));
+        }
+    }
+    merged.sort_by(|a, b| b.0.magnitude.total_cmp(&a.0.magnitude));
+    merged.into_iter().unzip()
+}
+
+fn with_magnitude(peak: &Peak, freq_hz: f32, magnitude: f32) -> Peak {
+    Peak {
+        freq_hz,
+        magnitude,
+        magnitude_db: if magnitude <= 0.0 {
+            -200.0
+        } else {
+            20.0 * magnitude.log10()
+        },
+        bin: peak.bin,
+    }
+}
+
+/// Pan for a partial at `freq_hz` spread by frequency across
+/// `min_hz..max_hz` (log scale), scaled by `width` (0..1).
+pub fn spread_pan(freq_hz: f32, min_hz: f32, max_hz: f32, width: f32) -> f32 {
+    if !(freq_hz > 0.0 && min_hz > 0.0 && max_hz > min_hz) {
+        return 0.0;
+    }
+    let t = ((freq_hz / min_hz).ln() / (max_hz / min_hz).ln()).clamp(0.0, 1.0);
+    width.clamp(0.0, 1.0) * (2.0 * t - 1.0)
+}
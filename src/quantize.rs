@@ -0,0 +1,152 @@
+/// MIDI note number of A4 (440 Hz).
+pub const A4_NOTE: u8 = 69;
+
+/// Fractional MIDI note number of `freq_hz` (69.0 = A4 = 440 Hz, one unit
+/// per 12-EDO semitone).
+pub fn midi_note(freq_hz: f32) -> f32 {
+    A4_NOTE as f32 + 12.0 * (freq_hz / 440.0).log2()
+}
+
+/// Frequency of MIDI note `note` in 12-EDO tuning (A4 = 440 Hz).
+pub fn note_freq(note: u8) -> f32 {
+    440.0 * 2f32.powf((note as f32 - A4_NOTE as f32) / 12.0)
+}
+
+/// Set of pitch classes notes are quantized to, relative to the tonic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Scale {
+    /// All twelve semitones.
+    #[default]
+    Chromatic,
+    Major,
+    NaturalMinor,
+    HarmonicMinor,
+    MajorPentatonic,
+    MinorPentatonic,
+}
+
+impl Scale {
+    /// Semitones above the tonic that belong to the scale.
+    pub fn intervals(self) -> &'static [u8] {
+        match self {
+            Scale::Chromatic => &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11],
+            Scale::Major => &[0, 2, 4, 5, 7, 9, 11],
+            Scale::NaturalMinor => &[0, 2, 3, 5, 7, 8, 10],
+            Scale::HarmonicMinor => &[0, 2, 3, 5, 7, 8, 11],
+            Scale::MajorPentatonic => &[0, 2, 4, 7, 9],
+            Scale::MinorPentatonic => &[0, 3, 5, 7, 10],
+        }
+    }
+}
+
+/// A key: tonic pitch class (0 = C, 1 = C#, ..., 11 = B) and scale.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Key {
+    pub tonic: u8,
+    pub scale: Scale,
+}
+
+impl Key {
+    /// Whether MIDI note `note` belongs to the key.
+    pub fn contains(&self, note: u8) -> bool {
+        let degree = (note as i32 - self.tonic as i32).rem_euclid(12) as u8;
+        self.scale.intervals().contains(&degree)
+    }
+
+    /// Note of the key nearest to the fractional MIDI pitch `pitch`, within
+    /// `0..=127`.
+    pub fn nearest(&self, pitch: f32) -> u8 {
+        let centre = pitch.round().clamp(0.0, 127.0) as i32;
+        (0..=12)
+            .flat_map(|offset| [centre - offset, centre + offset])
+            .filter(|note| (0..=127).contains(note))
+            .map(|note| note as u8)
+            .filter(|&note| self.contains(note))
+            .min_by(|&a, &b| {
+                let da = (a as f32 - pitch).abs();
+                let db = (b as f32 - pitch).abs();
+                da.total_cmp(&db)
+            })
+            .unwrap_or(centre as u8)
+    }
+}
+
+/// Options for `NoteQuantizer`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuantizeOptions {
+    /// Notes voices may take.
+    pub key: Key,
+    /// A voice holding a note moves to a nearer one only when the new note
+    /// is closer to its pitch by more than this (cents); `0` always takes
+    /// the nearest note.
+    pub hysteresis_cents: f32,
+}
+
+impl Default for QuantizeOptions {
+    fn default() -> Self {
+        QuantizeOptions {
+            key: Key::default(),
+            hysteresis_cents: 30.0,
+        }
+    }
+}
+
+/// Snaps the pitches of consecutive frames to notes of a key, with
+/// hysteresis so a pitch sitting between two notes does not flutter.
+///
+/// The notes chosen in one frame are held: in the next frame, a pitch close
+/// to a held note (within the hysteresis of its nearest note) keeps that
+/// note. Each held note is kept by at most one pitch; held notes no pitch
+/// stays close to are released.
+#[derive(Debug, Clone)]
+pub struct NoteQuantizer {
+    options: QuantizeOptions,
+    held: Vec<u8>,
+}
+
+impl NoteQuantizer {
+    pub fn new(options: QuantizeOptions) -> Self {
+        NoteQuantizer {
+            options,
+            held: Vec::new(),
+        }
+    }
+
+    pub fn options(&self) -> &QuantizeOptions {
+        &self.options
+    }
+
+    /// Notes (MIDI numbers) of the next frame's pitches `freqs_hz`, in the
+    /// same order; `None` for a frequency that is not positive and finite.
+    pub fn quantize(&mut self, freqs_hz: &[f32]) -> Vec<Option<u8>> {
+        let hysteresis = self.options.hysteresis_cents.max(0.0) / 100.0;
+        let mut available = std::mem::take(&mut self.held);
+        let notes: Vec<Option<u8>> = freqs_hz
+            .iter()
+            .map(|&freq_hz| {
+                if !(freq_hz.is_finite() && freq_hz > 0.0) {
+                    return None;
+                }
+                let pitch = midi_note(freq_hz);
+                let nearest = self.options.key.nearest(pitch);
+                let limit = (nearest as f32 - pitch).abs() + hysteresis;
+                let held = available
+                    .iter()
+                    .enumerate()
+                    .filter(|&(_, &note)| (note as f32 - pitch).abs() <= limit)
+                    .min_by(|a, b| {
+                        let da = (*a.1 as f32 - pitch).abs();
+                        let db = (*b.1 as f32 - pitch).abs();
+                        da.total_cmp(&db)
+                    })
+                    .map(|(idx, _)| idx);
+                Some(match held {
+                    Some(idx) => available.swap_remove(idx),
+                    None => nearest,
+                })
+            })
+            .collect();
+        self.held = notes.iter().flatten().copied().collect();
+        notes
+    }
+}
@@ -0,0 +1,326 @@
+use crate::pcm::{Peak, WindowFunction, synthesize_sines};
+use rustfft::{Fft, FftPlanner, num_complex::Complex};
+use std::sync::Arc;
+
+// Half-width in bins of the part of a voice's spectrum kept as its
+// contribution; leakage further out is ignored.
+const LEAKAGE_HALF_WIDTH_BINS: usize = 16;
+// Largest factor a level changes by in one pass (12 dB).
+const MAX_LEVEL_STEP: f32 = 4.0;
+
+/// Options for `LevelRefiner`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RefineOptions {
+    /// Most synthesize-and-compare passes per frame.
+    pub iterations: usize,
+    /// Refinement stops once a pass lowers the spectral distance by less
+    /// than this fraction.
+    pub tolerance: f32,
+    /// Half-width in bins of the region around each voice whose level is
+    /// matched to the input's. It should cover the main lobe of `window`
+    /// (2 bins for Hann).
+    pub half_width_bins: usize,
+    /// Analysis window applied before the FFT.
+    pub window: WindowFunction,
+}
+
+impl Default for RefineOptions {
+    fn default() -> Self {
+        RefineOptions {
+            iterations: 8,
+            tolerance: 0.01,
+            half_width_bins: 2,
+            window: WindowFunction::Hann,
+        }
+    }
+}
+
+/// Levels a voice can take, as set by a chip's attenuation (TL) register.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LevelScale {
+    /// Levels are whole steps of this many dB at or below 0 dBFS; `None`
+    /// leaves them continuous.
+    pub step_db: Option<f32>,
+    /// Voices at or below this level (dBFS) are muted.
+    pub floor_db: f32,
+}
+
+impl Default for LevelScale {
+    fn default() -> Self {
+        LevelScale {
+            step_db: None,
+            floor_db: f32::NEG_INFINITY,
+        }
+    }
+}
+
+impl LevelScale {
+    /// `magnitude` rounded to the nearest level of the scale; 0 when muted.
+    pub fn quantize(&self, magnitude: f32) -> f32 {
+        if !(magnitude.is_finite() && magnitude > 0.0) {
+            return 0.0;
+        }
+        let mut db = 20.0 * magnitude.log10();
+        if let Some(step) = self.step_db.filter(|&step| step > 0.0) {
+            db = (db / step).round().min(0.0) * step;
+        }
+        if db <= self.floor_db {
+            0.0
+        } else {
+            10f32.powf(db / 20.0)
+        }
+    }
+}
+
+/// Outcome of `LevelRefiner::refine` for one frame.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LevelRefinement {
+    /// Refined amplitude of each voice, in the order given; 0 for a voice
+    /// left out.
+    pub magnitudes: Vec<f32>,
+    /// Spectral distance of the voices at their given levels (on the
+    /// scale), and at the refined ones.
+    pub initial_distance: f32,
+    pub distance: f32,
+    /// Passes run.
+    pub iterations: usize,
+}
+
+// A voice's windowed spectrum at unit amplitude, kept around its pitch.
+struct VoiceSpectrum {
+    centre: usize,
+    start: usize,
+    bins: Vec<Complex<f32>>,
+}
+
+/// Adjusts voice levels by analysis-by-synthesis, so that the voices as a
+/// chip plays them reproduce the input spectrum rather than each matching
+/// its own measured peak.
+///
+/// Each voice is synthesized at its played pitch (unit amplitude, through
+/// the analysis window), and the frame's synthesis is the level-weighted sum
+/// of those spectra. The distance to the input is the squared difference of
+/// the magnitude spectra relative to the input's energy, so voices close in
+/// frequency that each carry the other's leakage, or level rounding on the
+/// chip's scale, show up as error. Each pass first scales every voice by
+/// the ratio of input to synthesized magnitude over its main lobe, then
+/// mutes voices that only add error and restores muted ones that remove
+/// some; a change is kept only when it lowers the distance.
+pub struct LevelRefiner {
+    options: RefineOptions,
+    planner: FftPlanner<f32>,
+    fft: Option<Arc<dyn Fft<f32>>>,
+    fft_size: usize,
+    window: Vec<f32>,
+    buffer: Vec<Complex<f32>>,
+    scratch: Vec<Complex<f32>>,
+}
+
+impl LevelRefiner {
+    pub fn new(options: RefineOptions) -> Self {
+        LevelRefiner {
+            options,
+            planner: FftPlanner::new(),
+            fft: None,
+            fft_size: 0,
+            window: Vec::new(),
+            buffer: Vec::new(),
+            scratch: Vec::new(),
+        }
+    }
+
+    pub fn options(&self) -> &RefineOptions {
+        &self.options
+    }
+
+    /// Spectral distance between the frame `samples` and `voices` played
+    /// together at their magnitudes: 0 for a perfect match, 1 for silence.
+    pub fn distance(&mut self, samples: &[f32], sample_rate: usize, voices: &[Peak]) -> f32 {
+        if samples.is_empty() || sample_rate == 0 {
+            return 0.0;
+        }
+        let target = self.magnitude_spectrum(samples);
+        let spectra = self.voice_spectra(voices, samples.len(), sample_rate);
+        let levels: Vec<f32> = voices.iter().map(|v| v.magnitude.max(0.0)).collect();
+        spectral_distance(&target, &spectra, &levels)
+    }
+
+    /// Refined levels of `voices` (played pitch and initial level) for the
+    /// frame `samples`, on `scale`.
+    pub fn refine(
+        &mut self,
+        samples: &[f32],
+        sample_rate: usize,
+        voices: &[Peak],
+        scale: &LevelScale,
+    ) -> LevelRefinement {
+        let mut levels: Vec<f32> = voices.iter().map(|v| scale.quantize(v.magnitude)).collect();
+        if samples.is_empty() || sample_rate == 0 || voices.is_empty() {
+            return LevelRefinement {
+                magnitudes: levels,
+                initial_distance: 0.0,
+                distance: 0.0,
+                iterations: 0,
+            };
+        }
+        let target = self.magnitude_spectrum(samples);
+        let spectra = self.voice_spectra(voices, samples.len(), sample_rate);
+        let initial_distance = spectral_distance(&target, &spectra, &levels);
+        let mut distance = initial_distance;
+        let width = self.options.half_width_bins;
+
+        let mut iterations = 0;
+        while iterations < self.options.iterations && distance > 0.0 {
+            iterations += 1;
+            let before = distance;
+
+            // levels: match each voice's main lobe to the input's
+            let synth = synthesize(&spectra, &levels, target.len());
+            let proposed: Vec<f32> = levels
+                .iter()
+                .zip(&spectra)
+                .map(|(&level, spectrum)| {
+                    let lo = spectrum.centre.saturating_sub(width);
+                    let hi = (spectrum.centre + width).min(target.len() - 1);
+                    let have: f32 = synth[lo..=hi].iter().map(|c| c.norm()).sum();
+                    let want: f32 = target[lo..=hi].iter().sum();
+                    if level <= 0.0 || have <= 0.0 {
+                        return level;
+                    }
+                    let ratio = (want / have).clamp(1.0 / MAX_LEVEL_STEP, MAX_LEVEL_STEP);
+                    scale.quantize(level * ratio)
+                })
+                .collect();
+            let proposed_distance = spectral_distance(&target, &spectra, &proposed);
+            if proposed_distance < distance {
+                levels = proposed;
+                distance = proposed_distance;
+            }
+
+            // selection: quietest voices first, mute those that only add
+            // error and restore those that remove some
+            let mut order: Vec<usize> = (0..levels.len()).collect();
+            order.sort_by(|&a, &b| levels[a].total_cmp(&levels[b]));
+            for v in order {
+                let mut trial = levels.clone();
+                trial[v] = if levels[v] > 0.0 {
+                    0.0
+                } else {
+                    let centre = spectra[v].bins[spectra[v].centre - spectra[v].start].norm();
+                    if centre <= 0.0 {
+                        continue;
+                    }
+                    scale.quantize(target[spectra[v].centre] / centre)
+                };
+                let trial_distance = spectral_distance(&target, &spectra, &trial);
+                if trial_distance < distance {
+                    levels = trial;
+                    distance = trial_distance;
+                }
+            }
+
+            if before - distance < self.options.tolerance * before {
+                break;
+            }
+        }
+
+        LevelRefinement {
+            magnitudes: levels,
+            initial_distance,
+            distance,
+            iterations,
+        }
+    }
+
+    // Magnitudes of the windowed spectrum of `samples`, DC to Nyquist.
+    fn magnitude_spectrum(&mut self, samples: &[f32]) -> Vec<f32> {
+        self.transform(samples);
+        self.buffer[..=self.fft_size / 2]
+            .iter()
+            .map(|c| c.norm())
+            .collect()
+    }
+
+    fn voice_spectra(
+        &mut self,
+        voices: &[Peak],
+        len: usize,
+        sample_rate: usize,
+    ) -> Vec<VoiceSpectrum> {
+        voices
+            .iter()
+            .map(|voice| {
+                let unit = Peak {
+                    magnitude: 1.0,
+                    magnitude_db: 0.0,
+                    ..*voice
+                };
+                self.transform(&synthesize_sines(&[unit], sample_rate, len));
+                let half = self.fft_size / 2;
+                let bin_hz = sample_rate as f32 / self.fft_size as f32;
+                let centre = ((voice.freq_hz / bin_hz).round().max(0.0) as usize).min(half);
+                let start = centre.saturating_sub(LEAKAGE_HALF_WIDTH_BINS);
+                let end = (centre + LEAKAGE_HALF_WIDTH_BINS).min(half);
+                VoiceSpectrum {
+                    centre,
+                    start,
+                    bins: self.buffer[start..=end].to_vec(),
+                }
+            })
+            .collect()
+    }
+
+    // Windowed FFT of `samples` (zero-padded to a power of two) into
+    // `buffer`.
+    fn transform(&mut self, samples: &[f32]) {
+        let fft_size = samples.len().next_power_of_two();
+        if self.fft.is_none() || self.fft_size != fft_size {
+            let fft = self.planner.plan_fft_forward(fft_size);
+            self.scratch = vec![Complex::new(0.0, 0.0); fft.get_inplace_scratch_len()];
+            self.buffer = vec![Complex::new(0.0, 0.0); fft_size];
+            self.fft = Some(fft);
+            self.fft_size = fft_size;
+        }
+        if self.window.len() != samples.len() {
+            self.window = self.options.window.coefficients(samples.len());
+        }
+        for (idx, slot) in self.buffer.iter_mut().enumerate() {
+            *slot = match (samples.get(idx), self.window.get(idx)) {
+                (Some(&s), Some(&w)) => Complex::new(s * w, 0.0),
+                _ => Complex::new(0.0, 0.0),
+            };
+        }
+        let fft = Arc::clone(self.fft.as_ref().expect("fft planned above"));
+        fft.process_with_scratch(&mut self.buffer, &mut self.scratch);
+    }
+}
+
+// Spectrum of the voices played together at `levels`.
+fn synthesize(spectra: &[VoiceSpectrum], levels: &[f32], bins: usize) -> Vec<Complex<f32>> {
+    let mut sum = vec![Complex::new(0.0, 0.0); bins];
+    for (spectrum, &level) in spectra.iter().zip(levels) {
+        if level <= 0.0 {
+            continue;
+        }
+        for (slot, bin) in sum[spectrum.start..].iter_mut().zip(&spectrum.bins) {
+            *slot += bin * level;
+        }
+    }
+    sum
+}
+
+// Squared magnitude-spectrum error of the voices at `levels`, relative to
+// the energy of `target`.
+fn spectral_distance(target: &[f32], spectra: &[VoiceSpectrum], levels: &[f32]) -> f32 {
+    let energy: f32 = target.iter().map(|t| t * t).sum();
+    if energy <= 0.0 {
+        return 0.0;
+    }
+    let synth = synthesize(spectra, levels, target.len());
+    let error: f32 = synth
+        .iter()
+        .zip(target)
+        .map(|(s, t)| (s.norm() - t).powi(2))
+        .sum();
+    error / energy
+}
@@ -1,15 +1,30 @@
-use crate::pcm::{Peak, analyze_pcm_peaks, synthesize_sines};
+use crate::assign::{AssignmentCandidate, AssignmentMode, AssignmentOptions, min_cost_assignment};
+use crate::filter::{FilterStage, preprocess_channels};
+use crate::fnumber::{FNumberSolver, FNumberTable, fnumber_solver, fnumber_table, solve_fnumber};
+use crate::loudness::{GainOptions, apply_output_gain_channels};
+use crate::pcm::{
+    AnalysisOptions, F0Estimator, F0Options, Formant, FormantAnalyzer, FormantOptions,
+    NoiseSynthesizer, Onset, OnsetOptions, OscillatorBank, OscillatorOptions, Peak,
+    ResidualAnalyzer, ResidualOptions, SpectralAnalyzer, StereoAnalysis, StereoOptions,
+    analyze_pcm_peaks, detect_onsets, estimate_pan, merge_channel_peaks, pan_to_levels, spread_pan,
+    synthesize_sines,
+};
+use crate::quantize::{NoteQuantizer, QuantizeOptions, note_freq};
+use crate::refine::{LevelRefiner, LevelScale, RefineOptions};
+use crate::resample::{ResampleOptions, Resampler};
+use crate::track::{TrackingOptions, track_partials, tracks_to_frames};
+use crate::voice::{VoiceAllocator, VoiceEvent, VoiceOptions};
 use crate::ym::{
-    init_ym2203, init_ym2203_channel_and_op, init_ymf262, init_ymf262_channel_and_op, ym2203_keyon,
-    ymf262_keyon,
+    Opl3Output, init_ym2203, init_ym2203_channel_and_op, init_ymf262, init_ymf262_channel_and_op,
+    ym2203_frequency, ym2203_keyoff, ym2203_keyon, ym2203_level, ym2203_ssg_noise,
+    ym2203_ssg_noise_enable, ymf262_frequency, ymf262_keyoff, ymf262_keyon, ymf262_level,
+    ymf262_output,
 };
 use soundlog::chip::Chip;
-use soundlog::chip::fnumber::{
-    ChipTypeSpec, FNumber, FNumberEntry, FNumberError, Opl3Spec, OpnSpec, find_and_tune_fnumber,
-    generate_12edo_fnum_table,
-};
+use soundlog::chip::fnumber::{ChipTypeSpec, FNumber, FNumberError, Opl3Spec, OpnSpec};
 use soundlog::{EndOfData, Instance, WaitSamples};
 use soundlog::{VgmBuilder, VgmDocument};
+use std::sync::Arc;
 
 /// Extracted spectral feature representing a chip `FNumber` and the detected magnitude.
 ///
@@ -20,6 +35,392 @@ use soundlog::{VgmBuilder, VgmDocument};
 pub struct SpectralFeature {
     pub fnumber: FNumber,
     pub magnitude: f32,
+    /// Stereo position, -1 (left) to 1 (right); 0 for mono input.
+    pub pan: f32,
+    /// MIDI note `fnumber` plays when the pitch was quantized (see
+    /// `ResynthOptions::quantize`); `None` when it follows the exact pitch.
+    pub midi_note: Option<u8>,
+}
+
+impl SpectralFeature {
+    /// Left and right amplitudes of this feature as `chip` plays it. OPL3
+    /// channels route to the left, right or both speakers at one level
+    /// (see `ym::Opl3Output::from_pan`); other chips are mono.
+    pub fn output_levels(&self, chip: &Chip) -> (f32, f32) {
+        let (left, right) = pan_to_levels(self.magnitude, self.pan);
+        match chip {
+            Chip::Ymf262 => match Opl3Output::from_pan(self.pan) {
+                Opl3Output::Left => (left, 0.0),
+                Opl3Output::Right => (0.0, right),
+                Opl3Output::Both => (self.magnitude, self.magnitude),
+            },
+            _ => (self.magnitude, self.magnitude),
+        }
+    }
+}
+
+/// PCM input of the stereo-aware entry points.
+#[derive(Debug, Clone, Copy)]
+pub enum InputChannels<'a> {
+    Mono(&'a [f32]),
+    Stereo { left: &'a [f32], right: &'a [f32] },
+}
+
+impl<'a> InputChannels<'a> {
+    /// View one (mono) or two (left, right) channel buffers as input.
+    pub fn from_channels(channels: &'a [Vec<f32>]) -> Self {
+        match channels {
+            [left, right, ..] => InputChannels::Stereo { left, right },
+            [mono] => InputChannels::Mono(mono),
+            [] => InputChannels::Mono(&[]),
+        }
+    }
+
+    /// The channel buffers: one for mono, left and right for stereo.
+    pub fn channels(&self) -> Vec<&'a [f32]> {
+        match *self {
+            InputChannels::Mono(samples) => vec![samples],
+            InputChannels::Stereo { left, right } => vec![left, right],
+        }
+    }
+
+    /// Number of sample frames.
+    pub fn len(&self) -> usize {
+        match self {
+            InputChannels::Mono(samples) => samples.len(),
+            InputChannels::Stereo { left, right } => left.len().min(right.len()),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Options shared by `process_samples_resynth_multi` and
+/// `process_samples_resynth_multi_to_vgm`.
+#[derive(Debug, Clone, Default)]
+pub struct ResynthOptions {
+    /// Spectral analysis options applied to every window. An unset
+    /// frequency band defaults to what the configured chips can play. With
+    /// `resolution_bands`, frames grow to the longest band window while
+    /// staying centred on their hop.
+    pub analysis: AnalysisOptions,
+    /// Hop between successive analysis frames in input samples. `None` uses
+    /// the window size (no overlap). Output timing (WAV length, VGM waits)
+    /// follows the hop; each frame is centred on the hop it describes.
+    pub hop_size: Option<usize>,
+    /// Link peaks across frames into partial tracks before voice assignment
+    /// (see `track::PartialTracker`). Short-lived peaks are dropped, gaps
+    /// are filled, and each frame lists its partials in order of birth so a
+    /// continuing partial keeps its place instead of following the loudness
+    /// order. `None` assigns every frame's peaks independently.
+    pub tracking: Option<TrackingOptions>,
+    /// Describe what the sinusoids leave behind (breath, fricatives) as a
+    /// per-band noise envelope and render it: as filtered noise in the WAV
+    /// path, and on the SSG noise generator (channel C of the first YM2203)
+    /// in the VGM path. Chips without a noise source ignore it.
+    pub residual: Option<ResidualOptions>,
+    /// Split hops at detected onsets (see `pcm::detect_onsets`) so that a
+    /// new frame, and in the VGM path its key-on writes, starts on each
+    /// attack. Frames never reach back across an onset, and frames before
+    /// an onset are shortened to stop at it. `resynth_onsets` returns the
+    /// onsets used.
+    pub onsets: Option<OnsetOptions>,
+    /// Oscillator settings of the WAV preview. Voices keep their phase from
+    /// one hop to the next and glide to the next hop's frequency and level
+    /// (or crossfade with `overlap_samples`), as a chip holding its key
+    /// would, instead of restarting at every hop.
+    pub synthesis: OscillatorOptions,
+    /// Output gain of the WAV preview: one gain for the whole output, so
+    /// the input's dynamics carry over (see `loudness::GainOptions`).
+    pub gain: GainOptions,
+    /// Stereo analysis and placement. Used by the stereo-aware entry points
+    /// (`process_stereo_resynth_multi` and
+    /// `process_stereo_resynth_multi_to_vgm`): each voice gets a pan, which
+    /// selects the speakers of its OPL3 channel.
+    pub stereo: StereoOptions,
+    /// Resample the input to this rate before analysis, so window and hop
+    /// sizes (in samples), FFT bin spacing and onset timing mean the same
+    /// for a 22.05 kHz and a 48 kHz file. `None` analyzes at the input
+    /// rate. The WAV preview is rendered at the analysis rate.
+    pub analysis_sample_rate: Option<usize>,
+    /// Resampler used for `analysis_sample_rate`, and to bring the WAV
+    /// preview from the analysis rate to the output rate.
+    pub resample: ResampleOptions,
+    /// Pre-processing chain run in order on the input, at the analysis
+    /// rate, before anything is analyzed (see `filter::FilterStage`). The
+    /// output gain still matches the level of the unprocessed input.
+    pub preprocess: Vec<FilterStage>,
+    /// Speech mode: give each formant of the frame's LPC envelope a voice
+    /// at its centre frequency, with the formant's energy as its level
+    /// (see `pcm::FormantAnalyzer`), instead of picking spectral peaks,
+    /// which on voiced speech mostly finds the pitch harmonics. Stereo
+    /// input is analyzed on its mid signal. `None` picks peaks.
+    pub speech: Option<SpeechOptions>,
+    /// Snap each voice to a note of the 12-EDO table (optionally of a key),
+    /// with hysteresis so a pitch between two notes does not flutter (see
+    /// `quantize::NoteQuantizer`). The note is recorded in
+    /// `SpectralFeature::midi_note`. `None` plays the exact pitch, as does
+    /// speech mode, whose formants are not notes.
+    pub quantize: Option<QuantizeOptions>,
+    /// How voices are placed on chip channels in the VGM path (see
+    /// `voice::VoiceAllocator`): a continuing partial stays on its channel
+    /// and only has its changed registers rewritten, new voices key on, and
+    /// channels whose voice ended are keyed off and silenced. Voices below
+    /// the quietest level (TL 0x3F) are not placed.
+    pub voices: VoiceOptions,
+    /// How each frame's peaks are spread over the chip instances' voices:
+    /// greedily, or with the lowest total cost of tuning error, lost level,
+    /// frequency range and continuity with the previous frame (see
+    /// `assign::AssignmentOptions`).
+    pub assignment: AssignmentOptions,
+    /// Master clock (Hz) of each chip instance, in the order of
+    /// `chip_instances`; a missing or `None` entry runs the instance at its
+    /// chip's `default_master_clock`. F-numbers, playable ranges and the SSG
+    /// noise period follow the clock. A VGM header holds one clock per chip
+    /// type, so in the VGM path instances of one chip must share it.
+    pub master_clocks: Vec<Option<u32>>,
+    /// Refine the levels of each frame's voices by analysis-by-synthesis
+    /// (see `refine::LevelRefiner`): the assigned voices are synthesized at
+    /// their played pitch, compared with the input spectrum, and their
+    /// levels adjusted (on the TL scale of `mag_to_tl` in the VGM path)
+    /// and voices dropped until the spectral distance stops improving.
+    /// `None` keeps each voice at its measured level.
+    pub refine: Option<RefineOptions>,
+}
+
+/// Options of the speech (formant) mode.
+#[derive(Debug, Clone, Default)]
+pub struct SpeechOptions {
+    pub formants: FormantOptions,
+    /// Add a voice at the fundamental of voiced frames (see
+    /// `pcm::estimate_f0`) ahead of the formant voices. `None` plays the
+    /// formants only.
+    pub f0: Option<F0Options>,
+}
+
+// Speech-mode analysis state: tracked formants, the f0 estimator, and the
+// last fundamental so the f0 voice holds its pitch through unvoiced frames.
+struct SpeechAnalyzer<'a> {
+    options: &'a SpeechOptions,
+    formants: FormantAnalyzer,
+    f0: Option<F0Estimator>,
+    last_f0_hz: Option<f32>,
+}
+
+impl<'a> SpeechAnalyzer<'a> {
+    fn new(options: &'a SpeechOptions) -> Self {
+        SpeechAnalyzer {
+            options,
+            formants: FormantAnalyzer::new(options.formants.clone()),
+            f0: options.f0.clone().map(F0Estimator::new),
+            last_f0_hz: None,
+        }
+    }
+
+    // Length of the frame the fundamental is estimated on: the analysis
+    // frame, lengthened to hold two periods of the lowest f0 searched;
+    // `None` without an f0 voice.
+    fn f0_frame_len(&self, frame_len: usize, sample_rate: usize) -> Option<usize> {
+        let f0 = self.f0.as_ref()?;
+        Some(frame_len.max(f0.options().min_frame_len(sample_rate)))
+    }
+
+    // Voices of one frame: the fundamental (with `f0`) then F1.., each in
+    // a fixed position. A voiced frame whose fundamental is missing from
+    // the spectrum plays the estimated pitch at its strongest harmonic's
+    // level. A voice with nothing to play this frame keeps its frequency
+    // at zero magnitude. The fundamental is estimated on `f0_frame`, centred
+    // on `frame` (see `f0_frame_len`).
+    fn analyze(
+        &mut self,
+        frame: &[f32],
+        f0_frame: &[f32],
+        sample_rate: usize,
+        spectral: &mut SpectralAnalyzer,
+    ) -> Vec<Peak> {
+        let mut peaks = Vec::with_capacity(self.options.formants.max_formants + 1);
+        if let Some(f0) = self.f0.as_mut() {
+            let candidates = spectral.analyze(frame, sample_rate, F0_PEAKS);
+            let estimate = f0.estimate(f0_frame, sample_rate, &candidates);
+            let fundamental = estimate.harmonics.iter().find(|h| h.index == 1);
+            // strongest of the frame's harmonics, for a fundamental missing
+            // from the spectrum
+            let strongest = estimate
+                .harmonics
+                .iter()
+                .map(|h| h.peak)
+                .max_by(|a, b| a.magnitude.total_cmp(&b.magnitude));
+            match (fundamental, estimate.f0_hz, self.last_f0_hz) {
+                (Some(harmonic), _, _) => {
+                    self.last_f0_hz = Some(harmonic.peak.freq_hz);
+                    peaks.push(harmonic.peak);
+                }
+                // voiced: the estimated pitch at its harmonics' level
+                (None, Some(freq_hz), _) => {
+                    self.last_f0_hz = Some(freq_hz);
+                    let (magnitude, magnitude_db) =
+                        strongest.map_or((0.0, -200.0), |peak| (peak.magnitude, peak.magnitude_db));
+                    peaks.push(Peak {
+                        freq_hz,
+                        magnitude,
+                        magnitude_db,
+                        bin: 0,
+                    });
+                }
+                // unvoiced: hold the last pitch silently
+                (None, None, Some(freq_hz)) => peaks.push(Peak {
+                    freq_hz,
+                    magnitude: 0.0,
+                    magnitude_db: -200.0,
+                    bin: 0,
+                }),
+                (None, None, None) => {}
+            }
+        }
+        peaks.extend(
+            self.formants
+                .analyze(frame, sample_rate)
+                .iter()
+                .map(Formant::to_peak),
+        );
+        peaks
+    }
+}
+
+// Spectral peaks searched for the harmonics of the speech-mode f0 voice.
+const F0_PEAKS: usize = 16;
+
+// One analyzed hop: the input span it describes, the peaks to assign to
+// voices with their stereo positions and, when enabled, the residual noise
+// envelope (per-band RMS) left after removing them and the input window
+// voice levels are refined against.
+struct HopFrame {
+    start: usize,
+    end: usize,
+    peaks: Vec<Peak>,
+    // pan of each peak
+    pans: Vec<f32>,
+    // quantized note of each peak
+    notes: Vec<Option<u8>>,
+    noise: Vec<f32>,
+    samples: Vec<f32>,
+}
+
+// Input samples `start..end` described by one analysis frame. Frames stay
+// within `lo..hi` (set next to onsets); outside the input they are
+// zero-padded.
+#[derive(Debug, Clone, Copy)]
+struct Segment {
+    start: usize,
+    end: usize,
+    lo: Option<usize>,
+    hi: Option<usize>,
+}
+
+// Regular hops over `total` samples, split at the sorted `onsets`. A hop
+// boundary closer than `hop_size / 8` to an onset moves onto the onset
+// instead of leaving a sliver segment.
+fn segment_hops(total: usize, hop_size: usize, onsets: &[usize]) -> Vec<Segment> {
+    let end = total.div_ceil(hop_size) * hop_size;
+    let min_len = (hop_size / 8).max(1);
+    let mut kept: Vec<usize> = Vec::new();
+    for &onset in onsets {
+        if onset >= min_len
+            && onset + min_len <= end
+            && kept.last().is_none_or(|&k| onset >= k + min_len)
+        {
+            kept.push(onset);
+        }
+    }
+
+    let mut bounds: Vec<usize> = (0..end)
+        .step_by(hop_size)
+        .filter(|&b| b == 0 || kept.iter().all(|&o| o.abs_diff(b) >= min_len))
+        .chain(kept.iter().copied())
+        .collect();
+    bounds.sort_unstable();
+    bounds.push(end);
+
+    bounds
+        .windows(2)
+        .map(|w| Segment {
+            start: w[0],
+            end: w[1],
+            lo: kept.iter().rev().find(|&&o| o <= w[0]).copied(),
+            hi: kept.iter().find(|&&o| o > w[0]).copied(),
+        })
+        .collect()
+}
+
+/// Master clock (Hz) a chip runs at unless `ResynthOptions::master_clocks`
+/// sets another. Returns `None` for chips this crate does not drive.
+pub fn default_master_clock(chip: &Chip) -> Option<u32> {
+    match chip {
+        Chip::Ymf262 => Some(Opl3Spec::default_master_clock() as u32),
+        Chip::Ym2203 => Some(OpnSpec::default_master_clock() as u32),
+        _ => None,
+    }
+}
+
+// Master clock of each chip instance (see `ResynthOptions::master_clocks`);
+// 0 for chips this crate does not drive.
+fn instance_clocks(chip_instances: &[(Chip, usize)], options: &ResynthOptions) -> Vec<u32> {
+    chip_instances
+        .iter()
+        .enumerate()
+        .map(|(idx, (chip, _))| {
+            options
+                .master_clocks
+                .get(idx)
+                .copied()
+                .flatten()
+                .or_else(|| default_master_clock(chip))
+                .unwrap_or(0)
+        })
+        .collect()
+}
+
+/// Frequency band (Hz) a chip can play at its default master clock (see
+/// `chip_frequency_range_at`).
+pub fn chip_frequency_range(chip: &Chip) -> Option<(f32, f32)> {
+    chip_frequency_range_at(chip, default_master_clock(chip)?)
+}
+
+/// Frequency band (Hz) a chip can play at `master_clock`: the band its
+/// F-number solver tunes with full resolution (see
+/// `fnumber::FNumberSolver::frequency_range`). Returns `None` for chips
+/// this crate does not drive.
+pub fn chip_frequency_range_at(chip: &Chip, master_clock: u32) -> Option<(f32, f32)> {
+    let solver = match chip {
+        Chip::Ymf262 => fnumber_solver::<Opl3Spec>(master_clock as f32),
+        Chip::Ym2203 => fnumber_solver::<OpnSpec>(master_clock as f32),
+        _ => return None,
+    };
+    solver.ok().map(|solver| solver.frequency_range())
+}
+
+// Analysis options for a resynth run: unless the caller set an explicit
+// band, peaks are limited to the union of the chips' playable ranges so they
+// do not consume voice slots they can never fill.
+fn analysis_options_for(
+    options: &ResynthOptions,
+    chip_instances: &[(Chip, usize)],
+) -> AnalysisOptions {
+    let mut analysis = options.analysis.clone();
+    let ranges: Vec<(f32, f32)> = chip_instances
+        .iter()
+        .zip(instance_clocks(chip_instances, options))
+        .filter_map(|((chip, _), clock)| chip_frequency_range_at(chip, clock))
+        .collect();
+    if !ranges.is_empty() {
+        let lo = ranges.iter().map(|r| r.0).fold(f32::MAX, f32::min);
+        let hi = ranges.iter().map(|r| r.1).fold(0.0, f32::max);
+        analysis.min_freq_hz.get_or_insert(lo);
+        analysis.max_freq_hz.get_or_insert(hi);
+    }
+    analysis
 }
 
 /// Analyze a mono sample window and map dominant spectral peaks to
@@ -29,15 +430,16 @@ pub struct SpectralFeature {
 /// - `samples`: mono PCM samples (floating-point, length typically equals analysis window)
 /// - `sample_rate`: sampling rate of `samples` in Hz
 /// - `max_voices`: maximum number of peaks/voices to return
-/// - `table`: precomputed 12-EDO F-number table produced by `generate_12edo_fnum_table`.
+/// - `master_clock`: the chip's master clock in Hz (e.g. `C::default_master_clock()`)
 ///
+/// Each peak is tuned by the chip's cached exact solver (`fnumber::fnumber_solver`).
 /// Returns a vector of `SpectralFeature` containing tuned `FNumber`s and their magnitudes,
-/// or `FNumberError` if table lookup/tuning fails.
-pub fn map_samples_to_fnums<C: soundlog::chip::fnumber::ChipTypeSpec>(
+/// or `FNumberError` if the solver cannot be built for `master_clock`.
+pub fn map_samples_to_fnums<C: ChipTypeSpec + 'static>(
     samples: &[f32],
     sample_rate: usize,
     max_voices: usize,
-    table: &[[Option<FNumberEntry>; 12]; 8],
+    master_clock: f32,
 ) -> Result<Vec<SpectralFeature>, FNumberError> {
     let peaks = analyze_pcm_peaks(samples, sample_rate, max_voices);
 
@@ -47,12 +449,14 @@ pub fn map_samples_to_fnums<C: soundlog::chip::fnumber::ChipTypeSpec>(
 
     let mut out: Vec<SpectralFeature> = Vec::new();
 
-    let mclk = C::default_master_clock();
+    let solver = fnumber_solver::<C>(master_clock)?;
     for peak in peaks.into_iter().take(max_voices) {
-        if let Ok(fnum) = find_and_tune_fnumber::<C>(table, peak.freq_hz, mclk) {
+        if let Ok(fnum) = solver.solve(peak.freq_hz) {
             out.push(SpectralFeature {
                 fnumber: fnum,
                 magnitude: peak.magnitude,
+                pan: 0.0,
+                midi_note: None,
             });
         }
     }
@@ -60,85 +464,256 @@ pub fn map_samples_to_fnums<C: soundlog::chip::fnumber::ChipTypeSpec>(
     Ok(out)
 }
 
+// Tuning of one chip instance at its master clock: its cached solver and
+// 12-EDO table.
+#[derive(Debug, Clone)]
+struct ChipTuning {
+    solver: Arc<FNumberSolver>,
+    table: Arc<FNumberTable>,
+    // block of the table row holding A4
+    a4_block: u8,
+}
+
+impl ChipTuning {
+    // Tuning of `chip` at `master_clock`; `None` for chips this crate does
+    // not drive.
+    fn for_chip(chip: &Chip, master_clock: u32) -> Result<Option<Self>, FNumberError> {
+        match chip {
+            Chip::Ymf262 => Self::of::<Opl3Spec>(master_clock as f32).map(Some),
+            Chip::Ym2203 => Self::of::<OpnSpec>(master_clock as f32).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    fn of<C: ChipTypeSpec + 'static>(master_clock: f32) -> Result<Self, FNumberError> {
+        Ok(ChipTuning {
+            solver: fnumber_solver::<C>(master_clock)?,
+            table: fnumber_table::<C>(master_clock)?,
+            a4_block: C::config().a4_block,
+        })
+    }
+
+    // F-number for a peak at `freq_hz`: the 12-EDO table entry of `note`
+    // when the peak is quantized (solved for the note's frequency when the
+    // table lacks it), otherwise solved for the peak's own pitch.
+    fn fnumber(&self, freq_hz: f32, note: Option<u8>) -> Result<FNumber, FNumberError> {
+        let Some(note) = note else {
+            return self.solver.solve(freq_hz);
+        };
+        // table rows are octaves starting at C, with A4 in block `a4_block`
+        let block = note as i32 / 12 - 5 + self.a4_block as i32;
+        let entry = usize::try_from(block)
+            .ok()
+            .and_then(|block| self.table.get(block))
+            .and_then(|row| row[note as usize % 12]);
+        match entry {
+            Some((_, fnumber)) => Ok(fnumber),
+            None => self.solver.solve(note_freq(note)),
+        }
+    }
+}
+
+// Tuning of each chip instance, in the order of `chip_instances`.
+fn chip_tunings(
+    chip_instances: &[(Chip, usize)],
+    clocks: &[u32],
+) -> Result<Vec<Option<ChipTuning>>, String> {
+    chip_instances
+        .iter()
+        .zip(clocks)
+        .map(|((chip, _), &clock)| {
+            ChipTuning::for_chip(chip, clock)
+                .map_err(|e| format!("F-number table error at {} Hz: {:?}", clock, e))
+        })
+        .collect()
+}
+
 /// Assign detected spectral peaks to chip instances.
 ///
-/// For each peak this function selects at most one target instance from
-/// `chip_instances`. Candidates are instances that still have remaining
-/// voice slots; the chosen instance is the one whose tuned `FNumber`
-/// produces the smallest tuning error (in cents). The assigned feature
-/// (tuned `FNumber` + original magnitude) is appended to that instance's
-/// output list and its remaining voice count is decremented.
+/// Each peak of `frame` goes to at most one instance of `chip_instances`
+/// with a voice slot left. With `AssignmentMode::Greedy` the peaks are
+/// taken in order and each chooses the instance whose tuned `FNumber`
+/// produces the smallest tuning error (in cents). With
+/// `AssignmentMode::Optimal` all peaks and slots are matched at once for
+/// the lowest total `assignment.cost`; `previous` (each instance's features
+/// of the previous frame) prices channel continuity. The assigned feature
+/// (tuned `FNumber` + original magnitude, and the peak's pan and note) is
+/// appended to that instance's output list. A peak without a note is
+/// tuned by the instance's exact F-number solver; a peak with a note plays
+/// the 12-EDO table's F-number of that note instead of its own pitch.
 ///
 /// The function supports `YMF262Opl3` and `YM2203` chips via the provided
-/// per-chip F-number tables. The returned `Vec<Vec<SpectralFeature>>` has
+/// per-instance `tunings`. The returned `Vec<Vec<SpectralFeature>>` has
 /// the same length and ordering as `chip_instances` so callers can map
-/// features back to instances directly.
+/// features back to instances directly; each list keeps the order of the
+/// frame's peaks. With `AssignmentMode::Greedy` it comes with each
+/// instance's overflow: the peaks (index and feature) it could tune but
+/// had no free voice for, and no other instance took. The optimal solver's
+/// drops are final, so its overflow is empty.
 fn assign_peaks_to_chip_instances(
-    peaks: &[Peak],
-    _input_sample_rate: usize,
+    frame: &HopFrame,
+    previous: &[Vec<SpectralFeature>],
     chip_instances: &[(Chip, usize)],
-    fnum_table_ymf262opl3: &[[Option<FNumberEntry>; 12]; 8],
-    fnum_table_ym2203: &[[Option<FNumberEntry>; 12]; 8],
-) -> Result<Vec<Vec<SpectralFeature>>, FNumberError> {
-    let total_instances = chip_instances.len();
-    let mut remaining: Vec<usize> = chip_instances.iter().map(|(_, v)| *v).collect();
-    let mut out: Vec<Vec<SpectralFeature>> = vec![Vec::new(); total_instances];
-
-    for peak in peaks.iter() {
-        let mut best: Option<(usize, SpectralFeature)> = None;
-        for (idx, (chip, _voices)) in chip_instances.iter().enumerate() {
-            if remaining[idx] == 0 {
-                continue;
-            }
-            match chip {
-                Chip::Ymf262 => {
-                    if let Ok(fnum) = find_and_tune_fnumber::<Opl3Spec>(
-                        fnum_table_ymf262opl3,
-                        peak.freq_hz,
-                        Opl3Spec::default_master_clock(),
-                    ) {
-                        let feat = SpectralFeature {
-                            fnumber: fnum,
-                            magnitude: peak.magnitude,
-                        };
-                        let err = fnum.error_cents;
-                        if best.is_none() || err < best.as_ref().unwrap().1.fnumber.error_cents {
-                            best = Some((idx, feat));
-                        }
+    tunings: &[Option<ChipTuning>],
+    assignment: &AssignmentOptions,
+) -> Result<(Vec<Vec<SpectralFeature>>, Vec<Overflow>), FNumberError> {
+    let mut out: Vec<Vec<SpectralFeature>> = vec![Vec::new(); chip_instances.len()];
+    let mut overflow: Vec<Overflow> = vec![Vec::new(); chip_instances.len()];
+    let tune = |idx: usize, peak: &Peak, midi_note: Option<u8>| {
+        tunings
+            .get(idx)?
+            .as_ref()?
+            .fnumber(peak.freq_hz, midi_note)
+            .ok()
+    };
+
+    match assignment.mode {
+        AssignmentMode::Greedy => {
+            let mut remaining: Vec<usize> = chip_instances.iter().map(|(_, v)| *v).collect();
+            for (p, peak) in frame.peaks.iter().enumerate() {
+                let midi_note = frame.notes.get(p).copied().flatten();
+                let best = chip_instances
+                    .iter()
+                    .enumerate()
+                    .filter(|&(idx, _)| remaining[idx] > 0)
+                    .filter_map(|(idx, _)| Some((idx, tune(idx, peak, midi_note)?)))
+                    .min_by(|a, b| a.1.error_cents.total_cmp(&b.1.error_cents));
+                if let Some((idx, fnumber)) = best {
+                    remaining[idx] -= 1;
+                    out[idx].push(frame_feature(frame, p, fnumber));
+                    continue;
+                }
+                // no instance with a free voice tunes it: it overflows the
+                // full ones that do
+                for idx in (0..chip_instances.len()).filter(|&idx| remaining[idx] == 0) {
+                    if let Some(fnumber) = tune(idx, peak, midi_note) {
+                        overflow[idx].push((p, frame_feature(frame, p, fnumber)));
                     }
                 }
-                Chip::Ym2203 => {
-                    if let Ok(fnum) = find_and_tune_fnumber::<OpnSpec>(
-                        fnum_table_ym2203,
-                        peak.freq_hz,
-                        OpnSpec::default_master_clock(),
-                    ) {
-                        let feat = SpectralFeature {
-                            fnumber: fnum,
-                            magnitude: peak.magnitude,
-                        };
-                        let err = fnum.error_cents;
-                        if best.is_none() || err < best.as_ref().unwrap().1.fnumber.error_cents {
-                            best = Some((idx, feat));
+            }
+        }
+        AssignmentMode::Optimal => {
+            // one column per voice, then one per peak for leaving it out
+            let slots: Vec<usize> = chip_instances
+                .iter()
+                .enumerate()
+                .flat_map(|(idx, (_, voices))| std::iter::repeat_n(idx, *voices))
+                .collect();
+            let mut fnumbers: Vec<Vec<Option<FNumber>>> = Vec::new();
+            let costs: Vec<Vec<f32>> = frame
+                .peaks
+                .iter()
+                .enumerate()
+                .map(|(p, peak)| {
+                    let midi_note = frame.notes.get(p).copied().flatten();
+                    let tuned: Vec<Option<FNumber>> = (0..chip_instances.len())
+                        .map(|idx| tune(idx, peak, midi_note))
+                        .collect();
+                    let instance_costs: Vec<Option<f32>> = chip_instances
+                        .iter()
+                        .enumerate()
+                        .map(|(idx, (chip, _))| {
+                            let fnumber = tuned[idx].as_ref()?;
+                            let continuity_cents = previous
+                                .get(idx)
+                                .into_iter()
+                                .flatten()
+                                .map(|f| {
+                                    (1200.0 * (peak.freq_hz / f.fnumber.actual_freq_hz).log2())
+                                        .abs()
+                                })
+                                .min_by(f32::total_cmp);
+                            assignment.cost.cost(&AssignmentCandidate {
+                                peak,
+                                chip,
+                                instance: idx,
+                                fnumber,
+                                freq_range: tunings
+                                    .get(idx)
+                                    .and_then(|t| t.as_ref())
+                                    .map(|t| t.solver.frequency_range()),
+                                level_floor_db: chip_level_floor_db(chip),
+                                continuity_cents,
+                            })
+                        })
+                        .collect();
+                    let mut row = vec![FORBIDDEN_COST; slots.len() + frame.peaks.len()];
+                    for (slot, &idx) in slots.iter().enumerate() {
+                        if let Some(cost) = instance_costs[idx] {
+                            row[slot] = cost;
                         }
                     }
+                    row[slots.len() + p] = assignment.cost.drop_cost(peak);
+                    fnumbers.push(tuned);
+                    row
+                })
+                .collect();
+            for (p, &column) in min_cost_assignment(&costs).iter().enumerate() {
+                if column < slots.len() && costs[p][column] < FORBIDDEN_COST {
+                    let idx = slots[column];
+                    if let Some(fnumber) = fnumbers[p][idx] {
+                        out[idx].push(frame_feature(frame, p, fnumber));
+                    }
                 }
-                _ => {}
             }
         }
+    }
 
-        if let Some((idx, feat)) = best {
-            remaining[idx] = remaining[idx].saturating_sub(1);
-            out[idx].push(feat);
-        }
+    Ok((out, overflow))
+}
+
+// Peaks (index in the frame, and feature) an instance had no voice for.
+type Overflow = Vec<(usize, SpectralFeature)>;
+
+// Feature of the frame's peak `p` played at `fnumber`, with the peak's
+// magnitude, pan and note.
+fn frame_feature(frame: &HopFrame, p: usize, fnumber: FNumber) -> SpectralFeature {
+    SpectralFeature {
+        fnumber,
+        magnitude: frame.peaks[p].magnitude,
+        pan: frame.pans.get(p).copied().unwrap_or(0.0),
+        midi_note: frame.notes.get(p).copied().flatten(),
+    }
+}
 
-        // stop early if all assigned
-        if remaining.iter().all(|&r| r == 0) {
-            break;
+// Refine the levels of a frame's assigned voices against its input window
+// (see `refine::LevelRefiner`); voices the refinement leaves out are
+// dropped.
+fn refine_frame_levels(
+    refiner: &mut LevelRefiner,
+    frame: &HopFrame,
+    sample_rate: usize,
+    scale: &LevelScale,
+    per_instance_feats: &mut [Vec<SpectralFeature>],
+) {
+    let voices: Vec<Peak> = per_instance_feats
+        .iter()
+        .flatten()
+        .map(feature_peak)
+        .collect();
+    if voices.is_empty() {
+        return;
+    }
+    let refined = refiner.refine(&frame.samples, sample_rate, &voices, scale);
+    let mut magnitudes = refined.magnitudes.into_iter();
+    for feats in per_instance_feats.iter_mut() {
+        for feat in feats.iter_mut() {
+            feat.magnitude = magnitudes.next().unwrap_or(0.0);
         }
+        feats.retain(|feat| feat.magnitude > 0.0);
     }
+}
 
-    Ok(out)
+// Stands for a pairing the cost model rules out in the assignment matrix.
+const FORBIDDEN_COST: f32 = 1e9;
+
+// Quietest level (dBFS) a chip's voice is written at before it mutes:
+// both chips' TLs are set by `mag_to_tl`.
+fn chip_level_floor_db(chip: &Chip) -> f32 {
+    match chip {
+        Chip::Ymf262 | Chip::Ym2203 => TL_FLOOR_DB,
+        _ => 0.0,
+    }
 }
 
 /// Synthesize a mono PCM buffer from a set of `SpectralFeature` entries.
@@ -161,37 +736,42 @@ pub fn synth_from_spectral_features(
         return Ok(vec![0.0f32; sample_count]);
     }
 
-    let mut peaks: Vec<Peak> = Vec::with_capacity(features.len());
+    let peaks = features_to_peaks(features);
+    let buf = synthesize_sines(&peaks, sample_rate, sample_count);
+    Ok(buf)
+}
+
+// Sinusoids sounding the tuned frequencies of `features` at their measured
+// magnitudes.
+fn features_to_peaks(features: &[SpectralFeature]) -> Vec<Peak> {
+    features.iter().map(feature_peak).collect()
+}
 
-    for feat in features {
-        let fnum = feat.fnumber;
-        let freq = fnum.actual_freq_hz;
-        let mag = feat.magnitude;
-        let mag_db = if mag <= 0.0 {
+fn feature_peak(feat: &SpectralFeature) -> Peak {
+    Peak {
+        freq_hz: feat.fnumber.actual_freq_hz,
+        magnitude: feat.magnitude,
+        magnitude_db: if feat.magnitude <= 0.0 {
             -200.0
         } else {
-            20.0 * mag.log10()
-        };
-        peaks.push(Peak {
-            freq_hz: freq,
-            magnitude: mag,
-            magnitude_db: mag_db,
-            bin: 0,
-        });
+            20.0 * feat.magnitude.log10()
+        },
+        bin: 0,
     }
-
-    let buf = synthesize_sines(&peaks, sample_rate, sample_count);
-    Ok(buf)
 }
 
-/// Process an entire PCM buffer in fixed-size windows, analyze spectral
-/// content per window for multiple chip instances, and resynthesize audio.
+/// Process an entire PCM buffer in overlapping analysis frames, analyze
+/// spectral content per frame for multiple chip instances, and resynthesize
+/// audio.
 ///
-/// This function coordinates per-window analysis (using `map_samples_to_fnums`)
-/// across the provided `chip_instances` and synthesizes output windows at
-/// `output_sample_rate`. It precomputes per-chip 12-EDO tables and preserves
-/// the time duration of each input window by scaling the synthesized sample
-/// count according to the input/output sample rates.
+/// This function coordinates per-frame analysis (using `map_samples_to_fnums`)
+/// across the provided `chip_instances` and synthesizes one output segment
+/// per hop at the analysis rate (the input rate, or
+/// `ResynthOptions::analysis_sample_rate`). Voices are tuned with the
+/// cached per-chip F-number solvers and tables of `crate::fnumber`. All
+/// segments are rendered by one `pcm::OscillatorBank`, so voices run
+/// continuously across hop boundaries. The result is resampled to
+/// `output_sample_rate` when it differs, keeping the input's duration.
 ///
 /// - `samples`: input mono PCM buffer (f32)
 /// - `input_sample_rate`: sample rate of `samples` in Hz
@@ -199,6 +779,7 @@ pub fn synth_from_spectral_features(
 /// - `output_sample_rate`: desired sample rate for synthesized output
 /// - `chip_instances`: list of `(Chip, voices)` tuples describing which chips
 ///   to emulate and how many voices to allocate per instance
+/// - `options`: analysis settings, hop size and output gain (see `ResynthOptions`)
 ///
 /// Returns the synthesized mono buffer or an error message if analysis
 /// or synthesis fails.
@@ -208,64 +789,497 @@ pub fn process_samples_resynth_multi(
     window_size: usize,
     output_sample_rate: usize,
     chip_instances: &[(Chip, usize)],
+    options: &ResynthOptions,
 ) -> Result<Vec<f32>, String> {
+    let mut channels = render_preview(
+        InputChannels::Mono(samples),
+        input_sample_rate,
+        window_size,
+        output_sample_rate,
+        chip_instances,
+        options,
+        1,
+    )?;
+    Ok(channels.remove(0))
+}
+
+/// Stereo counterpart of `process_samples_resynth_multi`.
+///
+/// Stereo input is analyzed as set by `ResynthOptions::stereo`; mono input
+/// is spread by frequency when `StereoOptions::mono_spread` is set. Each
+/// voice is rendered on the speakers its chip would use: OPL3 voices on
+/// the left, right or both sides, YM2203 voices on both.
+///
+/// Returns the synthesized `(left, right)` buffers.
+pub fn process_stereo_resynth_multi(
+    input: InputChannels,
+    input_sample_rate: usize,
+    window_size: usize,
+    output_sample_rate: usize,
+    chip_instances: &[(Chip, usize)],
+    options: &ResynthOptions,
+) -> Result<(Vec<f32>, Vec<f32>), String> {
+    let mut channels = render_preview(
+        input,
+        input_sample_rate,
+        window_size,
+        output_sample_rate,
+        chip_instances,
+        options,
+        2,
+    )?;
+    let right = channels.pop().unwrap_or_default();
+    let left = channels.pop().unwrap_or_default();
+    Ok((left, right))
+}
+
+// Render the WAV preview of `input` into `output_channels` (1: mono, 2:
+// left and right) buffers.
+fn render_preview(
+    input: InputChannels,
+    input_sample_rate: usize,
+    window_size: usize,
+    output_sample_rate: usize,
+    chip_instances: &[(Chip, usize)],
+    options: &ResynthOptions,
+    output_channels: usize,
+) -> Result<Vec<Vec<f32>>, String> {
     if window_size == 0 {
         return Err("window_size must be > 0".to_string());
     }
+    let hop_size = options.hop_size.unwrap_or(window_size);
+    if hop_size == 0 {
+        return Err("hop_size must be > 0".to_string());
+    }
+    let references = input.channels();
+    let reference_rate = input_sample_rate;
+    let prepared = prepare_input(input, input_sample_rate, options);
+    let (input, input_sample_rate) = match &prepared {
+        Some((channels, rate)) => (InputChannels::from_channels(channels), *rate),
+        None => (input, input_sample_rate),
+    };
+    // the preview is rendered at the analysis rate, then resampled
+    let synth_sample_rate = input_sample_rate;
 
-    let fnum_table_ymf262opl3 =
-        generate_12edo_fnum_table::<Opl3Spec>(Opl3Spec::default_master_clock())
-            .map_err(|e| format!("table gen 262 error: {:?}", e))?;
-    let fnum_table_ym2203 = generate_12edo_fnum_table::<OpnSpec>(OpnSpec::default_master_clock())
-        .map_err(|e| format!("table gen 2203 error: {:?}", e))?;
-
-    let total_samples = samples.len();
-    let mut out: Vec<f32> = Vec::with_capacity(total_samples);
-
-    let mut offset = 0usize;
-    while offset < total_samples {
-        let end = (offset + window_size).min(total_samples);
+    let clocks = instance_clocks(chip_instances, options);
+    let tunings = chip_tunings(chip_instances, &clocks)?;
 
-        let mut window: Vec<f32> = samples[offset..end].to_vec();
-        if window.len() < window_size {
-            window.resize(window_size, 0.0);
-        }
+    let total_samples = input.len();
+    let mut out: Vec<Vec<f32>> = vec![Vec::with_capacity(total_samples); output_channels];
 
-        // analyze peaks once per window and assign them to chip instances
-        let total_voices_needed: usize = chip_instances.iter().map(|(_, v)| *v).sum();
-        let peaks = analyze_pcm_peaks(&window, input_sample_rate, total_voices_needed.max(1));
-        let per_instance_feats = assign_peaks_to_chip_instances(
-            &peaks,
-            input_sample_rate,
+    // analyze peaks once per hop and assign them to chip instances
+    let frames = analyze_hops(
+        input,
+        input_sample_rate,
+        window_size,
+        hop_size,
+        chip_instances,
+        options,
+    );
+    let mut banks: Vec<OscillatorBank> = (0..output_channels)
+        .map(|_| OscillatorBank::new(options.synthesis.clone(), synth_sample_rate))
+        .collect();
+    let mut noise = options
+        .residual
+        .as_ref()
+        .map(|residual| NoiseSynthesizer::new(&residual.band_edges_hz, synth_sample_rate));
+    let mut channel_peaks: Vec<Vec<Peak>> = vec![Vec::new(); output_channels];
+    let mut refiner = options.refine.clone().map(LevelRefiner::new);
+    let mut previous: Vec<Vec<SpectralFeature>> = vec![Vec::new(); chip_instances.len()];
+    for frame in frames.iter() {
+        let (mut per_instance_feats, _) = assign_peaks_to_chip_instances(
+            frame,
+            &previous,
             chip_instances,
-            &fnum_table_ymf262opl3,
-            &fnum_table_ym2203,
+            &tunings,
+            &options.assignment,
         )
         .map_err(|e| format!("FNumber mapping error: {:?}", e))?;
-        let mut all_features: Vec<SpectralFeature> = Vec::new();
-        for mut v in per_instance_feats.into_iter() {
-            all_features.append(&mut v);
+        if let Some(refiner) = refiner.as_mut() {
+            let scale = LevelScale::default();
+            refine_frame_levels(
+                refiner,
+                frame,
+                input_sample_rate,
+                &scale,
+                &mut per_instance_feats,
+            );
         }
-
-        let input_count = end - offset;
-        let mut output_count = ((input_count as f64) * (output_sample_rate as f64)
-            / (input_sample_rate as f64))
-            .round() as usize;
-        if output_count == 0 {
-            output_count = 1;
+        for peaks in channel_peaks.iter_mut() {
+            peaks.clear();
+        }
+        for ((chip, _voices), feats) in chip_instances.iter().zip(&per_instance_feats) {
+            for (feat, peak) in feats.iter().zip(features_to_peaks(feats)) {
+                if output_channels == 1 {
+                    channel_peaks[0].push(peak);
+                    continue;
+                }
+                let (left, right) = feat.output_levels(chip);
+                for (peaks, magnitude) in channel_peaks.iter_mut().zip([left, right]) {
+                    peaks.push(Peak { magnitude, ..peak });
+                }
+            }
         }
 
-        let synth = synth_from_spectral_features(&all_features, output_sample_rate, output_count)
-            .map_err(|e| format!("synthesis error: {:?}", e))?;
-        out.extend_from_slice(&synth[..]);
+        let output_count = frame.end.min(total_samples) - frame.start;
 
-        offset += window_size;
+        let mut residual = vec![0.0f32; output_count];
+        if let Some(noise) = noise.as_mut() {
+            noise.render(&frame.noise, &mut residual);
+        }
+        for ((channel, bank), peaks) in out.iter_mut().zip(&mut banks).zip(&channel_peaks) {
+            let mut synth = residual.clone();
+            bank.render(peaks, &mut synth);
+            channel.extend_from_slice(&synth[..]);
+        }
+        previous = per_instance_feats;
+    }
+
+    if output_sample_rate != synth_sample_rate {
+        let resampler = Resampler::new(synth_sample_rate, output_sample_rate, &options.resample);
+        for channel in out.iter_mut() {
+            *channel = resampler.process(channel);
+        }
     }
 
+    let mut outputs: Vec<&mut [f32]> = out.iter_mut().map(|c| c.as_mut_slice()).collect();
+    apply_output_gain_channels(
+        &mut outputs,
+        output_sample_rate,
+        &references,
+        reference_rate,
+        &options.gain,
+    );
     Ok(out)
 }
 
-// helper: map FFT magnitude to TL (0 = loud, larger value = quieter)
+// Copy of `input` resampled to `options.analysis_sample_rate` and run
+// through `options.preprocess`, with its rate; `None` when neither applies.
+fn prepare_input(
+    input: InputChannels,
+    input_sample_rate: usize,
+    options: &ResynthOptions,
+) -> Option<(Vec<Vec<f32>>, usize)> {
+    let resample_to = options
+        .analysis_sample_rate
+        .filter(|&rate| rate > 0 && rate != input_sample_rate);
+    if resample_to.is_none() && options.preprocess.is_empty() {
+        return None;
+    }
+    let rate = resample_to.unwrap_or(input_sample_rate);
+    let resampler = Resampler::new(input_sample_rate, rate, &options.resample);
+    let mut channels: Vec<Vec<f32>> = input
+        .channels()
+        .iter()
+        .map(|channel| resampler.process(channel))
+        .collect();
+    let mut views: Vec<&mut [f32]> = channels.iter_mut().map(|c| c.as_mut_slice()).collect();
+    preprocess_channels(&mut views, rate, &options.preprocess);
+    Some((channels, rate))
+}
+
+/// Onsets the resynthesis splits its hops at for `input` (see
+/// `ResynthOptions::onsets`): detected on the mid signal after
+/// `ResynthOptions::analysis_sample_rate` and `ResynthOptions::preprocess`
+/// are applied, as the `process_*` entry points do. `Onset::sample` counts
+/// samples at the analysis rate; `Onset::time_s` is the same at any rate.
+/// Empty when `options.onsets` is `None`.
+pub fn resynth_onsets(
+    input: InputChannels,
+    input_sample_rate: usize,
+    options: &ResynthOptions,
+) -> Vec<Onset> {
+    if options.onsets.is_none() {
+        return Vec::new();
+    }
+    let prepared = prepare_input(input, input_sample_rate, options);
+    let (input, input_sample_rate) = match &prepared {
+        Some((channels, rate)) => (InputChannels::from_channels(channels), *rate),
+        None => (input, input_sample_rate),
+    };
+    hop_onsets(&mid_signal(input), input_sample_rate, options)
+}
+
+// Average of the left and right channels; mono input as is.
+fn mid_signal(input: InputChannels) -> Vec<f32> {
+    match input {
+        InputChannels::Mono(samples) => samples.to_vec(),
+        InputChannels::Stereo { left, right } => {
+            left.iter().zip(right).map(|(l, r)| (l + r) / 2.0).collect()
+        }
+    }
+}
+
+// Onsets of the (prepared) mid signal set by `options.onsets`.
+fn hop_onsets(mid: &[f32], sample_rate: usize, options: &ResynthOptions) -> Vec<Onset> {
+    options
+        .onsets
+        .as_ref()
+        .map(|onsets| detect_onsets(mid, sample_rate, onsets))
+        .unwrap_or_default()
+}
+
+// Analysis of every hop of `input`: hop `i` covers input samples
+// `i * hop_size..(i + 1) * hop_size` (split at onsets with `options.onsets`)
+// and is analyzed on a `window_size` frame centred on it. Stereo input is
+// analyzed as set by `options.stereo`; onsets and the residual use the mid
+// signal. With `options.tracking` the peaks are linked into partial tracks
+// and laid back out per hop; the residual is measured against the final
+// per-hop peaks. Each frame holds up to one peak per voice plus
+// `options.voices.spare_peaks`.
+fn analyze_hops(
+    input: InputChannels,
+    input_sample_rate: usize,
+    window_size: usize,
+    hop_size: usize,
+    chip_instances: &[(Chip, usize)],
+    options: &ResynthOptions,
+) -> Vec<HopFrame> {
+    let total_voices_needed: usize = chip_instances.iter().map(|(_, v)| *v).sum();
+    let voiced_peaks = total_voices_needed.max(1);
+    let max_peaks = voiced_peaks + options.voices.spare_peaks;
+    let analysis = analysis_options_for(options, chip_instances);
+    let window_function = analysis.window;
+    let spread_range = (
+        analysis.min_freq_hz.unwrap_or(20.0),
+        analysis.max_freq_hz.unwrap_or(20000.0),
+    );
+    // multi-resolution bands may need a longer frame than `window_size`
+    let frame_len = analysis.frame_len(window_size);
+    let mut analyzer = SpectralAnalyzer::new(analysis);
+
+    let mid = mid_signal(input);
+    let onsets: Vec<usize> = hop_onsets(&mid, input_sample_rate, options)
+        .iter()
+        .map(|onset| onset.sample)
+        .collect();
+    let segments = segment_hops(mid.len(), hop_size, &onsets);
+
+    let mut window: Vec<f32> = Vec::with_capacity(frame_len);
+    let mut f0_window: Vec<f32> = Vec::new();
+    let mut left_window: Vec<f32> = Vec::with_capacity(frame_len);
+    let mut right_window: Vec<f32> = Vec::with_capacity(frame_len);
+    let mut frames: Vec<Vec<Peak>> = Vec::with_capacity(segments.len());
+    let mut frame_pans: Vec<Vec<f32>> = Vec::with_capacity(segments.len());
+    let mut speech = options.speech.as_ref().map(SpeechAnalyzer::new);
+    for segment in &segments {
+        let (peaks, pans) = match (input, options.stereo.analysis, &mut speech) {
+            (InputChannels::Stereo { left, right }, StereoAnalysis::PerChannel, None) => {
+                fill_analysis_frame(&mut left_window, left, segment, frame_len);
+                fill_analysis_frame(&mut right_window, right, segment, frame_len);
+                let left_peaks = analyzer.analyze(&left_window, input_sample_rate, max_peaks);
+                let right_peaks = analyzer.analyze(&right_window, input_sample_rate, max_peaks);
+                let (mut peaks, mut pans) = merge_channel_peaks(
+                    &left_peaks,
+                    &right_peaks,
+                    options.stereo.merge_tolerance_cents,
+                );
+                peaks.truncate(max_peaks);
+                pans.truncate(max_peaks);
+                (peaks, pans)
+            }
+            (_, _, speech) => {
+                fill_analysis_frame(&mut window, &mid, segment, frame_len);
+                let mut peaks = match speech {
+                    Some(speech) => {
+                        if let Some(len) = speech.f0_frame_len(frame_len, input_sample_rate) {
+                            fill_analysis_frame(&mut f0_window, &mid, segment, len);
+                        }
+                        speech.analyze(&window, &f0_window, input_sample_rate, &mut analyzer)
+                    }
+                    None => analyzer.analyze(&window, input_sample_rate, max_peaks),
+                };
+                peaks.truncate(max_peaks);
+                let pans = match input {
+                    InputChannels::Mono(_) => {
+                        let spread = options.stereo.mono_spread;
+                        peaks
+                            .iter()
+                            .map(|p| spread_pan(p.freq_hz, spread_range.0, spread_range.1, spread))
+                            .collect()
+                    }
+                    InputChannels::Stereo { left, right } => {
+                        fill_analysis_frame(&mut left_window, left, segment, frame_len);
+                        fill_analysis_frame(&mut right_window, right, segment, frame_len);
+                        estimate_pan(
+                            &left_window,
+                            &right_window,
+                            input_sample_rate,
+                            &peaks,
+                            window_function,
+                        )
+                    }
+                };
+                (peaks, pans)
+            }
+        };
+        frames.push(peaks);
+        frame_pans.push(pans);
+    }
+
+    if let Some(tracking) = &options.tracking {
+        let tracks = track_partials(&frames, tracking);
+        let tracked: Vec<Vec<Peak>> = tracks_to_frames(&tracks, frames.len())
+            .into_iter()
+            .map(|frame| frame.into_iter().map(|tracked| tracked.peak).collect())
+            .collect();
+        frame_pans = tracked
+            .iter()
+            .zip(frames.iter().zip(&frame_pans))
+            .map(|(peaks, (detected, pans))| carry_pans(peaks, detected, pans))
+            .collect();
+        frames = tracked;
+    }
+
+    let mut residual = options.residual.clone().map(ResidualAnalyzer::new);
+    // speech partials are not notes and keep their exact pitch
+    let mut quantizer = options
+        .quantize
+        .clone()
+        .filter(|_| options.speech.is_none())
+        .map(NoteQuantizer::new);
+    let mut hops: Vec<HopFrame> = Vec::with_capacity(frames.len());
+    for ((segment, peaks), pans) in segments.iter().zip(frames).zip(frame_pans) {
+        let noise = match residual.as_mut() {
+            Some(residual) => {
+                fill_analysis_frame(&mut window, &mid, segment, window_size);
+                let voiced = &peaks[..peaks.len().min(voiced_peaks)];
+                residual.analyze(&window, input_sample_rate, voiced)
+            }
+            None => Vec::new(),
+        };
+        let samples = match options.refine {
+            Some(_) => {
+                fill_analysis_frame(&mut window, &mid, segment, window_size);
+                window.clone()
+            }
+            None => Vec::new(),
+        };
+        let notes = match quantizer.as_mut() {
+            Some(quantizer) => {
+                let freqs: Vec<f32> = peaks.iter().map(|p| p.freq_hz).collect();
+                quantizer.quantize(&freqs)
+            }
+            None => vec![None; peaks.len()],
+        };
+        hops.push(HopFrame {
+            start: segment.start,
+            end: segment.end,
+            peaks,
+            pans,
+            notes,
+            noise,
+            samples,
+        });
+    }
+    hops
+}
+
+// Pans for `peaks` (laid out from partial tracks) taken from the closest
+// of the frame's `detected` peaks; detected peaks come through tracking
+// unchanged, points filling a gap take the pan of the peak nearest in
+// frequency.
+fn carry_pans(peaks: &[Peak], detected: &[Peak], pans: &[f32]) -> Vec<f32> {
+    peaks
+        .iter()
+        .map(|peak| {
+            detected
+                .iter()
+                .zip(pans)
+                .min_by(|a, b| {
+                    let da = (a.0.freq_hz - peak.freq_hz).abs();
+                    let db = (b.0.freq_hz - peak.freq_hz).abs();
+                    da.total_cmp(&db)
+                })
+                .map_or(0.0, |(_, &pan)| pan)
+        })
+        .collect()
+}
+
+// Map a residual noise envelope to the YM2203 SSG noise generator:
+// (noise period, channel level). The noise clock (master / 64 / period) is
+// placed at twice the envelope's spectral centroid; the level follows the
+// total residual RMS at roughly 3 dB per step.
+fn residual_to_ssg_noise(levels: &[f32], band_edges_hz: &[f32], master_clock: f32) -> (u8, u8) {
+    let mut power = 0.0f32;
+    let mut weighted = 0.0f32;
+    for (level, edge) in levels.iter().zip(band_edges_hz.windows(2)) {
+        let p = level * level;
+        power += p;
+        weighted += p * (edge[0] * edge[1]).sqrt();
+    }
+    if power <= 0.0 {
+        return (0x1F, 0);
+    }
+    let centroid = weighted / power;
+    let period = (master_clock / 64.0 / (2.0 * centroid))
+        .round()
+        .clamp(1.0, 31.0) as u8;
+    // peak-equivalent level, on the same scale as `Peak::magnitude`
+    let db = 20.0 * (power.sqrt() * std::f32::consts::SQRT_2).log10();
+    let level = (15.0 + db / 3.0).round().clamp(0.0, 15.0) as u8;
+    (period, level)
+}
+
+// Fill `frame` with `window_size` samples centred on `segment`,
+// zero-padding outside `samples`. When the segment is a full hop of
+// `window_size` samples this is exactly `samples[start..start + window_size]`.
+// A frame is moved forward to start no earlier than `segment.lo` and cut
+// short at `segment.hi`.
+fn fill_analysis_frame(
+    frame: &mut Vec<f32>,
+    samples: &[f32],
+    segment: &Segment,
+    window_size: usize,
+) {
+    let centre = (segment.start + segment.end) / 2;
+    let mut start = centre as isize - (window_size / 2) as isize;
+    if let Some(lo) = segment.lo {
+        start = start.max(lo as isize);
+    }
+    let len = match segment.hi {
+        Some(hi) => window_size.min((hi as isize - start).max(1) as usize),
+        None => window_size,
+    };
+    frame.clear();
+    frame.extend((0..len).map(|i| {
+        let idx = start + i as isize;
+        if idx < 0 {
+            0.0
+        } else {
+            samples.get(idx as usize).copied().unwrap_or(0.0)
+        }
+    }));
+}
+
+// Number of output samples covering input samples `start..end`. Positions are
+// rounded on the absolute timeline so per-hop rounding does not drift.
+fn output_span(
+    start: usize,
+    end: usize,
+    input_sample_rate: usize,
+    output_sample_rate: usize,
+) -> usize {
+    let ratio = (output_sample_rate as f64) / (input_sample_rate as f64);
+    let out_start = ((start as f64) * ratio).round() as usize;
+    let out_end = ((end as f64) * ratio).round() as usize;
+    out_end.saturating_sub(out_start).max(1)
+}
+
+// Append `WaitSamples` commands for `count` samples, split into u16 chunks.
+fn add_wait_samples(builder: &mut VgmBuilder, mut count: usize) {
+    while count > 0 {
+        let chunk = count.min(u16::MAX as usize);
+        builder.add_vgm_command(WaitSamples(chunk as u16));
+        count -= chunk;
+    }
+}
+
+// Level (dBFS) at and below which `mag_to_tl` writes full attenuation.
+const TL_FLOOR_DB: f32 = -60.0;
+
+// helper: map a dBFS-calibrated peak magnitude (`Peak::magnitude`) to TL
+// (0 = loud, larger value = quieter). -60..0 dBFS spans the TL range.
 // `max_tl` is the maximum TL value to use (e.g. 0x24). note: 0x00 == loudest.
 pub fn mag_to_tl(mag: f32, max_tl: u8) -> u8 {
     // For non-finite or silence, return full attenuation (0x3f)
@@ -273,7 +1287,7 @@ pub fn mag_to_tl(mag: f32, max_tl: u8) -> u8 {
         return 0x3f;
     }
     let mag_db = 20.0 * mag.log10();
-    let db_min = -60.0;
+    let db_min = TL_FLOOR_DB;
     let db_max = 0.0;
     let t = ((mag_db - db_min) / (db_max - db_min)).clamp(0.0, 1.0);
     // Map t==1.0 -> max_tl (loud), t==0.0 -> 0x3f (silent)
@@ -282,13 +1296,31 @@ pub fn mag_to_tl(mag: f32, max_tl: u8) -> u8 {
     tl_f.round().clamp(max_tl as f32, 0x3f as f32) as u8
 }
 
+/// Levels `mag_to_tl` tells apart with `max_tl` as the loudest TL: whole
+/// steps of 60 dB / (0x3F - `max_tl`) at or below 0 dBFS, muted (TL 0x3F)
+/// at -60 dBFS.
+pub fn tl_level_scale(max_tl: u8) -> LevelScale {
+    let steps = 0x3F_u8.saturating_sub(max_tl).max(1);
+    LevelScale {
+        step_db: Some(-TL_FLOOR_DB / steps as f32),
+        floor_db: TL_FLOOR_DB,
+    }
+}
+
 /// Similar to `process_samples_resynth_multi`, but instead of synthesizing
 /// audio it builds a `VgmDocument` that reproduces the analysis using chip
 /// register writes. For each analysis window this function:
 /// - maps spectral peaks to per-chip FNumbers
-/// - programs operator parameters and frequencies for assigned channels
-/// - issues key-on writes for each active voice
-/// - inserts a `WaitSamples` corresponding to the synthesized window length
+/// - places them on channels with a `voice::VoiceAllocator`, so a
+///   continuing partial keeps its channel
+/// - keys on new voices, retunes and re-levels continuing ones only where
+///   their registers change, and keys off released channels
+/// - inserts a `WaitSamples` corresponding to the hop length
+///
+/// `options` carries the same analysis settings as in
+/// `process_samples_resynth_multi`. Each chip is registered at the master
+/// clock of its instances (`ResynthOptions::master_clocks`), which must
+/// agree for all instances of one chip.
 ///
 /// Returns a built `VgmDocument` on success.
 pub fn process_samples_resynth_multi_to_vgm(
@@ -297,66 +1329,92 @@ pub fn process_samples_resynth_multi_to_vgm(
     window_size: usize,
     max_tl: u8,
     chip_instances: &[(Chip, usize)],
+    options: &ResynthOptions,
+) -> Result<VgmDocument, String> {
+    process_stereo_resynth_multi_to_vgm(
+        InputChannels::Mono(samples),
+        input_sample_rate,
+        window_size,
+        max_tl,
+        chip_instances,
+        options,
+    )
+}
+
+/// Stereo counterpart of `process_samples_resynth_multi_to_vgm`.
+///
+/// Voices are placed as in `process_stereo_resynth_multi`: each OPL3
+/// channel's output-select bits (registers C0-C8) follow the pan of the
+/// voice it plays, written whenever they change, and its level is the
+/// level on the speakers it plays on.
+pub fn process_stereo_resynth_multi_to_vgm(
+    input: InputChannels,
+    input_sample_rate: usize,
+    window_size: usize,
+    max_tl: u8,
+    chip_instances: &[(Chip, usize)],
+    options: &ResynthOptions,
 ) -> Result<VgmDocument, String> {
     if window_size == 0 {
         return Err("window_size must be > 0".to_string());
     }
+    let hop_size = options.hop_size.unwrap_or(window_size);
+    if hop_size == 0 {
+        return Err("hop_size must be > 0".to_string());
+    }
+    let prepared = prepare_input(input, input_sample_rate, options);
+    let (input, input_sample_rate) = match &prepared {
+        Some((channels, rate)) => (InputChannels::from_channels(channels), *rate),
+        None => (input, input_sample_rate),
+    };
     // VGM sample rate
     let output_sample_rate = 44100;
 
-    let fnum_table_ymf262opl3 =
-        generate_12edo_fnum_table::<Opl3Spec>(Opl3Spec::default_master_clock())
-            .map_err(|e| format!("table gen 262 error: {:?}", e))?;
-    let fnum_table_ym2203 = generate_12edo_fnum_table::<OpnSpec>(OpnSpec::default_master_clock())
-        .map_err(|e| format!("table gen 2203 error: {:?}", e))?;
+    let clocks = instance_clocks(chip_instances, options);
+    let tunings = chip_tunings(chip_instances, &clocks)?;
 
-    let total_samples = samples.len();
+    let total_samples = input.len();
     let mut builder = VgmBuilder::new();
 
-    let mut seen_ymf262 = false;
-    let mut seen_ym2203 = false;
+    // the header holds one clock per chip type
+    let shared_clock = |wanted: Chip| -> Result<Option<u32>, String> {
+        let mut shared: Option<u32> = None;
+        for ((chip, _), &clock) in chip_instances.iter().zip(&clocks) {
+            if *chip != wanted {
+                continue;
+            }
+            match shared {
+                Some(first) if first != clock => {
+                    return Err(format!(
+                        "{:?} instances must share one master clock in a VGM file ({} Hz and {} Hz)",
+                        wanted, first, clock
+                    ));
+                }
+                _ => shared = Some(clock),
+            }
+        }
+        Ok(shared)
+    };
+    let ymf262_clock = shared_clock(Chip::Ymf262)?;
+    let ym2203_clock = shared_clock(Chip::Ym2203)?;
     let ym2203_instances = chip_instances
         .iter()
         .filter(|(c, _)| matches!(c, Chip::Ym2203))
         .count();
-    for (chip, _voices) in chip_instances.iter() {
-        match chip {
-            Chip::Ymf262 if !seen_ymf262 => {
-                builder.register_chip(
-                    Chip::Ymf262,
-                    Instance::Primary,
-                    Opl3Spec::default_master_clock() as u32,
-                );
-                seen_ymf262 = true;
-            }
-            Chip::Ym2203 if !seen_ym2203 => {
-                builder.register_chip(
-                    Chip::Ym2203,
-                    Instance::Primary,
-                    OpnSpec::default_master_clock() as u32,
-                );
-                seen_ym2203 = true;
-            }
-            _ => {}
-        }
+    if let Some(clock) = ymf262_clock {
+        builder.register_chip(Chip::Ymf262, Instance::Primary, clock);
     }
-
-    if ym2203_instances >= 2 {
-        builder.register_chip(
-            Chip::Ym2203,
-            Instance::Secondary,
-            OpnSpec::default_master_clock() as u32,
-        );
+    if let Some(clock) = ym2203_clock {
+        builder.register_chip(Chip::Ym2203, Instance::Primary, clock);
+        if ym2203_instances >= 2 {
+            builder.register_chip(Chip::Ym2203, Instance::Secondary, clock);
+        }
     }
 
-    if seen_ymf262 {
+    if let Some(clock) = ymf262_clock {
         init_ymf262(&mut builder);
-        let base_262 = find_and_tune_fnumber::<Opl3Spec>(
-            &fnum_table_ymf262opl3,
-            440.0,
-            Opl3Spec::default_master_clock(),
-        )
-        .map_err(|e| format!("fnum tune error 262: {:?}", e))?;
+        let base_262 = solve_fnumber::<Opl3Spec>(440.0, clock as f32)
+            .map_err(|e| format!("fnum tune error 262: {:?}", e))?;
         for ch in 0u8..18u8 {
             init_ymf262_channel_and_op(
                 &mut builder,
@@ -367,19 +1425,15 @@ pub fn process_samples_resynth_multi_to_vgm(
             );
         }
     }
-    if seen_ym2203 {
+    if let Some(clock) = ym2203_clock {
         init_ym2203(&mut builder, 0);
         let chip_count = if ym2203_instances >= 2 {
             ym2203_instances
         } else {
             1usize
         };
-        let base_2203 = find_and_tune_fnumber::<OpnSpec>(
-            &fnum_table_ym2203,
-            440.0,
-            OpnSpec::default_master_clock(),
-        )
-        .map_err(|e| format!("fnum tune error 2203: {:?}", e))?;
+        let base_2203 = solve_fnumber::<OpnSpec>(440.0, clock as f32)
+            .map_err(|e| format!("fnum tune error 2203: {:?}", e))?;
         for port in 0..chip_count {
             for ch in 0u8..3u8 {
                 init_ym2203_channel_and_op(
@@ -393,74 +1447,174 @@ pub fn process_samples_resynth_multi_to_vgm(
             }
         }
     }
+    // residual noise goes to the SSG of the first YM2203
+    let noise_bands = options
+        .residual
+        .as_ref()
+        .filter(|_| ym2203_clock.is_some())
+        .map(|residual| residual.band_edges_hz.as_slice());
+    if noise_bands.is_some() {
+        ym2203_ssg_noise_enable(&mut builder, 0);
+    }
+    let mut last_noise: Option<(u8, u8)> = None;
+    // output-select bits of each OPL3 channel, as set by
+    // `init_ymf262_channel_and_op`
+    let mut opl3_outputs = [Opl3Output::Both; 18];
+    // one allocator per chip instance, over the channels its voices use,
+    // and the (F-number, block, TL) last written to each of its channels
+    let mut allocators: Vec<VoiceAllocator> = chip_instances
+        .iter()
+        .map(|(chip, voices)| {
+            let channels = match chip {
+                Chip::Ymf262 => 18,
+                Chip::Ym2203 => 3,
+                _ => 0,
+            };
+            VoiceAllocator::new((*voices).min(channels), options.voices.clone())
+        })
+        .collect();
+    let mut written: Vec<Vec<Option<(u16, u8, u8)>>> = allocators
+        .iter()
+        .map(|allocator| vec![None; allocator.channel_count()])
+        .collect();
 
-    let mut offset = 0usize;
-    while offset < total_samples {
-        let end = (offset + window_size).min(total_samples);
-
-        let mut window: Vec<f32> = samples[offset..end].to_vec();
-        if window.len() < window_size {
-            window.resize(window_size, 0.0);
-        }
-
-        let total_voices_needed: usize = chip_instances.iter().map(|(_, v)| *v).sum();
-        let peaks = analyze_pcm_peaks(&window, input_sample_rate, total_voices_needed.max(1));
-        let per_instance_feats = assign_peaks_to_chip_instances(
-            &peaks,
-            input_sample_rate,
+    let frames = analyze_hops(
+        input,
+        input_sample_rate,
+        window_size,
+        hop_size,
+        chip_instances,
+        options,
+    );
+    let mut refiner = options.refine.clone().map(LevelRefiner::new);
+    let mut previous: Vec<Vec<SpectralFeature>> = vec![Vec::new(); chip_instances.len()];
+    for frame in frames.iter() {
+        let (mut per_instance_feats, overflow) = assign_peaks_to_chip_instances(
+            frame,
+            &previous,
             chip_instances,
-            &fnum_table_ymf262opl3,
-            &fnum_table_ym2203,
+            &tunings,
+            &options.assignment,
         )
         .map_err(|e| format!("FNumber mapping error: {:?}", e))?;
+        if let Some(refiner) = refiner.as_mut() {
+            let scale = tl_level_scale(max_tl);
+            refine_frame_levels(
+                refiner,
+                frame,
+                input_sample_rate,
+                &scale,
+                &mut per_instance_feats,
+            );
+        }
+
+        if let Some(band_edges) = noise_bands {
+            let clock = ym2203_clock.unwrap_or(0) as f32;
+            let noise = residual_to_ssg_noise(&frame.noise, band_edges, clock);
+            if last_noise != Some(noise) {
+                ym2203_ssg_noise(&mut builder, 0, noise.0, noise.1);
+                last_noise = Some(noise);
+            }
+        }
 
+        let mut placed: Vec<usize> = Vec::new();
         for (idx, (chip, _voices)) in chip_instances.iter().enumerate() {
             let feats = &per_instance_feats[idx];
-            if feats.is_empty() {
-                continue;
+            let port_num = chip_instances[..idx]
+                .iter()
+                .filter(|(c, _)| matches!(c, Chip::Ym2203))
+                .count() as u8;
+            // voices too quiet to hear are not placed, so noise-floor peaks
+            // do not key channels on
+            let level = |feat: &SpectralFeature| {
+                let (left, right) = feat.output_levels(chip);
+                mag_to_tl(left.max(right), max_tl)
+            };
+            let mut audible: Vec<(Option<usize>, &SpectralFeature, u8)> = feats
+                .iter()
+                .map(|feat| (None, feat, level(feat)))
+                .filter(|&(_, _, tl)| tl < 0x3F)
+                .collect();
+            // once the assigned voices fill the channels, the peaks the
+            // instance had no voice for compete with them, so the steal
+            // policy decides; a channel left free by a dropped or muted
+            // voice is not refilled
+            if audible.len() >= allocators[idx].channel_count() {
+                audible.extend(
+                    overflow[idx]
+                        .iter()
+                        .filter(|(p, _)| !placed.contains(p))
+                        .map(|(p, feat)| (Some(*p), feat, level(feat)))
+                        .filter(|&(_, _, tl)| tl < 0x3F),
+                );
             }
-
-            match chip {
-                Chip::Ymf262 => {
-                    let max_ch = 18usize;
-                    for (i, feat) in feats.iter().enumerate() {
-                        let ch_idx = (i % max_ch) as u8;
-                        let fnum = feat.fnumber;
-                        let fnum_val = fnum.f_num as u16;
-                        let block_val = fnum.block;
-                        let tl = mag_to_tl(feat.magnitude, max_tl);
-                        ymf262_keyon(&mut builder, ch_idx, fnum_val, block_val, tl);
+            let peaks: Vec<Peak> = audible
+                .iter()
+                .map(|(_, feat, _)| feature_peak(feat))
+                .collect();
+            let updates = allocators[idx].allocate(&peaks);
+            // an overflow peak placed here is not offered to the next instance
+            placed.extend(updates.iter().filter_map(|update| audible[update.peak?].0));
+            for update in updates {
+                let ch = update.channel as u8;
+                let last = &mut written[idx][update.channel];
+                let Some((_, feat, tl)) = update.peak.map(|p| audible[p]) else {
+                    if let Some((fnum_val, block_val, _)) = last.take() {
+                        match chip {
+                            Chip::Ymf262 => ymf262_keyoff(&mut builder, ch, fnum_val, block_val),
+                            Chip::Ym2203 => ym2203_keyoff(&mut builder, port_num, ch),
+                            _ => {}
+                        }
+                    }
+                    continue;
+                };
+                let fnum_val = feat.fnumber.f_num as u16;
+                let block_val = feat.fnumber.block;
+                if matches!(chip, Chip::Ymf262) {
+                    let output = Opl3Output::from_pan(feat.pan);
+                    if opl3_outputs[ch as usize] != output {
+                        ymf262_output(&mut builder, ch, output);
+                        opl3_outputs[ch as usize] = output;
                     }
                 }
-                Chip::Ym2203 => {
-                    let port_num = chip_instances[..=idx]
-                        .iter()
-                        .filter(|(c, _)| matches!(c, Chip::Ym2203))
-                        .count()
-                        - 1;
-                    for (i, feat) in feats.iter().enumerate() {
-                        let ch = (i % 3) as u8; // YM2203 channels per chip = 3
-                        let fnum = feat.fnumber;
-                        let fnum_val = fnum.f_num as u16;
-                        let block_val = fnum.block;
-                        let tl = mag_to_tl(feat.magnitude, max_tl);
-                        ym2203_keyon(&mut builder, port_num as u8, ch, fnum_val, block_val, tl);
+                // a continuing voice only rewrites what changed
+                let previous = match update.event {
+                    VoiceEvent::Continued => *last,
+                    _ => None,
+                };
+                match (chip, previous) {
+                    (Chip::Ymf262, Some((f, b, t))) => {
+                        if (f, b) != (fnum_val, block_val) {
+                            ymf262_frequency(&mut builder, ch, fnum_val, block_val);
+                        }
+                        if t != tl {
+                            ymf262_level(&mut builder, ch, tl);
+                        }
+                    }
+                    (Chip::Ymf262, None) => {
+                        ymf262_keyon(&mut builder, ch, fnum_val, block_val, tl);
+                    }
+                    (Chip::Ym2203, Some((f, b, t))) => {
+                        if (f, b) != (fnum_val, block_val) {
+                            ym2203_frequency(&mut builder, port_num, ch, fnum_val, block_val);
+                        }
+                        if t != tl {
+                            ym2203_level(&mut builder, port_num, ch, tl);
+                        }
+                    }
+                    (Chip::Ym2203, None) => {
+                        ym2203_keyon(&mut builder, port_num, ch, fnum_val, block_val, tl);
                     }
+                    _ => {}
                 }
-                _ => {}
+                *last = Some((fnum_val, block_val, tl));
             }
         }
 
-        let input_count = end - offset;
-        let mut output_count = ((input_count as f64) * (output_sample_rate as f64)
-            / (input_sample_rate as f64))
-            .round() as usize;
-        if output_count == 0 {
-            output_count = 1;
-        }
-        builder.add_vgm_command(WaitSamples(output_count as u16));
-
-        offset += window_size;
+        let end = frame.end.min(total_samples);
+        let output_count = output_span(frame.start, end, input_sample_rate, output_sample_rate);
+        add_wait_samples(&mut builder, output_count);
+        previous = per_instance_feats;
     }
 
     builder.add_vgm_command(EndOfData);
@@ -0,0 +1,297 @@
+use crate::pcm::Peak;
+
+/// Matching limits for `PartialTracker`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrackingOptions {
+    /// Largest frequency jump (cents) a track may make between two
+    /// consecutive points.
+    pub max_freq_deviation_cents: f32,
+    /// Largest level jump (dB) a track may make between two consecutive
+    /// points.
+    pub max_magnitude_deviation_db: f32,
+    /// Number of consecutive frames a track may go unmatched and still be
+    /// continued. Missing frames are filled by interpolation.
+    pub max_gap_frames: usize,
+    /// Tracks with fewer points (including filled gaps) are discarded.
+    pub min_track_frames: usize,
+}
+
+impl Default for TrackingOptions {
+    fn default() -> Self {
+        TrackingOptions {
+            max_freq_deviation_cents: 100.0,
+            max_magnitude_deviation_db: 24.0,
+            max_gap_frames: 2,
+            min_track_frames: 3,
+        }
+    }
+}
+
+/// One frame of a `Track`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TrackPoint {
+    pub frame: usize,
+    pub peak: Peak,
+    /// `true` when the point fills a gap rather than coming from a detected
+    /// peak.
+    pub interpolated: bool,
+}
+
+/// A sinusoidal partial followed across consecutive frames.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Track {
+    /// Unique id, increasing in order of birth.
+    pub id: usize,
+    /// One point per frame from birth to death, without holes.
+    pub points: Vec<TrackPoint>,
+}
+
+impl Track {
+    /// Frame of the first point.
+    pub fn birth_frame(&self) -> usize {
+        self.points.first().map_or(0, |p| p.frame)
+    }
+
+    /// Frame of the last point.
+    pub fn death_frame(&self) -> usize {
+        self.points.last().map_or(0, |p| p.frame)
+    }
+
+    /// The point at `frame`, if the track is alive there.
+    pub fn point_at(&self, frame: usize) -> Option<&TrackPoint> {
+        frame
+            .checked_sub(self.birth_frame())
+            .and_then(|i| self.points.get(i))
+    }
+}
+
+/// Lifecycle of a track at a given frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrackEvent {
+    /// First frame of the track.
+    Birth,
+    /// Neither first nor last frame.
+    Continuation,
+    /// Last frame of the track.
+    Death,
+}
+
+/// A track's point in one frame, as returned by `tracks_to_frames`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TrackedPeak {
+    pub track_id: usize,
+    pub peak: Peak,
+    pub event: TrackEvent,
+    pub interpolated: bool,
+}
+
+// A track that can still be continued.
+#[derive(Debug)]
+struct ActiveTrack {
+    track: Track,
+    // frames since the last detected peak
+    missed: usize,
+}
+
+/// McAulay–Quatieri style partial tracker.
+///
+/// Feed the peaks of each analysis frame in order with `push_frame`; each
+/// peak either continues the closest compatible active track (within the
+/// `TrackingOptions` deviation limits) or gives birth to a new one. Tracks
+/// that stay unmatched for more than `max_gap_frames` frames die. Call
+/// `finish` after the last frame to collect all tracks.
+#[derive(Debug)]
+pub struct PartialTracker {
+    options: TrackingOptions,
+    frame: usize,
+    next_id: usize,
+    active: Vec<ActiveTrack>,
+    finished: Vec<Track>,
+}
+
+impl PartialTracker {
+    pub fn new(options: TrackingOptions) -> Self {
+        PartialTracker {
+            options,
+            frame: 0,
+            next_id: 0,
+            active: Vec::new(),
+            finished: Vec::new(),
+        }
+    }
+
+    pub fn options(&self) -> &TrackingOptions {
+        &self.options
+    }
+
+    /// Number of frames pushed so far.
+    pub fn frame_count(&self) -> usize {
+        self.frame
+    }
+
+    /// Link the peaks of the next frame to the active tracks.
+    pub fn push_frame(&mut self, peaks: &[Peak]) {
+        let frame = self.frame;
+
+        // all compatible (track, peak) pairs, closest in pitch first
+        let mut pairs: Vec<(f32, usize, usize)> = Vec::new();
+        for (t, active) in self.active.iter().enumerate() {
+            let last = active.track.points.last().expect("tracks are never empty");
+            for (p, peak) in peaks.iter().enumerate() {
+                if let Some(distance) = self.distance_cents(&last.peak, peak) {
+                    pairs.push((distance, t, p));
+                }
+            }
+        }
+        pairs.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let mut track_taken = vec![false; self.active.len()];
+        let mut peak_taken = vec![false; peaks.len()];
+        for &(_, t, p) in &pairs {
+            if track_taken[t] || peak_taken[p] {
+                continue;
+            }
+            track_taken[t] = true;
+            peak_taken[p] = true;
+            let active = &mut self.active[t];
+            fill_gap(&mut active.track, frame, &peaks[p]);
+            active.track.points.push(TrackPoint {
+                frame,
+                peak: peaks[p],
+                interpolated: false,
+            });
+            active.missed = 0;
+        }
+
+        // unmatched tracks age and eventually die
+        let max_gap = self.options.max_gap_frames;
+        let (alive, dead): (Vec<ActiveTrack>, Vec<ActiveTrack>) = std::mem::take(&mut self.active)
+            .into_iter()
+            .zip(track_taken)
+            .map(|(mut active, taken)| {
+                if !taken {
+                    active.missed += 1;
+                }
+                active
+            })
+            .partition(|active| active.missed <= max_gap);
+        self.active = alive;
+        for active in dead {
+            self.retire(active.track);
+        }
+
+        // unmatched peaks are born as new tracks
+        for (p, peak) in peaks.iter().enumerate() {
+            if peak_taken[p] {
+                continue;
+            }
+            self.active.push(ActiveTrack {
+                track: Track {
+                    id: self.next_id,
+                    points: vec![TrackPoint {
+                        frame,
+                        peak: *peak,
+                        interpolated: false,
+                    }],
+                },
+                missed: 0,
+            });
+            self.next_id += 1;
+        }
+
+        self.frame += 1;
+    }
+
+    /// End all active tracks and return every track that meets
+    /// `min_track_frames`, ordered by id.
+    pub fn finish(mut self) -> Vec<Track> {
+        for active in std::mem::take(&mut self.active) {
+            self.retire(active.track);
+        }
+        self.finished.sort_by_key(|t| t.id);
+        self.finished
+    }
+
+    fn retire(&mut self, track: Track) {
+        if track.points.len() >= self.options.min_track_frames {
+            self.finished.push(track);
+        }
+    }
+
+    // Pitch distance in cents, or `None` when `next` cannot continue `prev`.
+    fn distance_cents(&self, prev: &Peak, next: &Peak) -> Option<f32> {
+        if prev.freq_hz <= 0.0 || next.freq_hz <= 0.0 {
+            return None;
+        }
+        let cents = (1200.0 * (next.freq_hz / prev.freq_hz).log2()).abs();
+        let db = (next.magnitude_db - prev.magnitude_db).abs();
+        (cents <= self.options.max_freq_deviation_cents
+            && db <= self.options.max_magnitude_deviation_db)
+            .then_some(cents)
+    }
+}
+
+// Interpolate the frames between the track's last point and `next` at
+// `frame`: frequency linearly in pitch, level linearly in dB.
+fn fill_gap(track: &mut Track, frame: usize, next: &Peak) {
+    let last = *track.points.last().expect("tracks are never empty");
+    let span = frame - last.frame;
+    for step in 1..span {
+        let t = step as f32 / span as f32;
+        let freq_hz = last.peak.freq_hz * (next.freq_hz / last.peak.freq_hz).powf(t);
+        let magnitude_db =
+            last.peak.magnitude_db + (next.magnitude_db - last.peak.magnitude_db) * t;
+        track.points.push(TrackPoint {
+            frame: last.frame + step,
+            peak: Peak {
+                freq_hz,
+                magnitude: 10f32.powf(magnitude_db / 20.0),
+                magnitude_db,
+                bin: last.peak.bin,
+            },
+            interpolated: true,
+        });
+    }
+}
+
+/// Track partials over a whole sequence of frames (see `PartialTracker`).
+pub fn track_partials(frames: &[Vec<Peak>], options: &TrackingOptions) -> Vec<Track> {
+    let mut tracker = PartialTracker::new(options.clone());
+    for peaks in frames {
+        tracker.push_frame(peaks);
+    }
+    tracker.finish()
+}
+
+/// Lay `tracks` back out as `frame_count` per-frame peak lists.
+///
+/// Each frame lists the tracks alive in it ordered by track id, so a
+/// continuing partial keeps its position relative to the other partials
+/// instead of moving with the loudness order.
+pub fn tracks_to_frames(tracks: &[Track], frame_count: usize) -> Vec<Vec<TrackedPeak>> {
+    let mut frames: Vec<Vec<TrackedPeak>> = vec![Vec::new(); frame_count];
+    let mut ordered: Vec<&Track> = tracks.iter().collect();
+    ordered.sort_by_key(|t| t.id);
+    for track in ordered {
+        let last = track.points.len().saturating_sub(1);
+        for (i, point) in track.points.iter().enumerate() {
+            let Some(frame) = frames.get_mut(point.frame) else {
+                break;
+            };
+            let event = if i == 0 {
+                TrackEvent::Birth
+            } else if i == last {
+                TrackEvent::Death
+            } else {
+                TrackEvent::Continuation
+            };
+            frame.push(TrackedPeak {
+                track_id: track.id,
+                peak: point.peak,
+                event,
+                interpolated: point.interpolated,
+            });
+        }
+    }
+    frames
+}
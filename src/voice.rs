@@ -0,0 +1,229 @@
+use crate::pcm::Peak;
+
+/// Which sounding voice `VoiceAllocator` gives up when a new voice finds
+/// every channel taken.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StealPolicy {
+    /// The quietest voice, and only when the new voice is louder; a quieter
+    /// new voice is dropped instead.
+    #[default]
+    Quietest,
+    /// The voice that has held its channel longest.
+    Oldest,
+    /// The voice furthest in pitch from the new one.
+    FurthestPitch,
+}
+
+/// Options for `VoiceAllocator`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VoiceOptions {
+    /// Largest pitch change (cents) between frames for a voice to be taken
+    /// as the same partial and stay on its channel.
+    pub max_freq_deviation_cents: f32,
+    pub steal: StealPolicy,
+    /// Peaks analyzed per frame beyond one per voice. In the VGM output, a
+    /// peak a full chip instance had no voice for competes for its
+    /// channels with the assigned voices, so that `steal` decides whether
+    /// it takes one.
+    pub spare_peaks: usize,
+}
+
+impl Default for VoiceOptions {
+    fn default() -> Self {
+        VoiceOptions {
+            max_freq_deviation_cents: 100.0,
+            steal: StealPolicy::default(),
+            spare_peaks: 4,
+        }
+    }
+}
+
+/// What happens on a channel in one frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VoiceEvent {
+    /// A voice starts on the channel: it was free, its voice ended this
+    /// frame, or it was stolen.
+    New,
+    /// The channel's voice continues, possibly at a new pitch or level.
+    Continued,
+    /// The channel's voice ended and no other took its place.
+    Released,
+}
+
+/// A channel's event in one frame, as returned by `VoiceAllocator::allocate`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VoiceUpdate {
+    pub channel: usize,
+    pub event: VoiceEvent,
+    /// Index of the peak the channel plays; `None` when released.
+    pub peak: Option<usize>,
+}
+
+// The voice a channel plays.
+#[derive(Debug, Clone, Copy)]
+struct ActiveVoice {
+    freq_hz: f32,
+    // frame the voice started on the channel
+    since: usize,
+}
+
+/// Keeps each partial on the channel it started on across frames.
+///
+/// Each call to `allocate` describes the next frame by its peaks. A peak
+/// close in pitch to a sounding voice (within `max_freq_deviation_cents`)
+/// continues it on the same channel, closest pairs first. Other peaks, in
+/// the order given, start on a free channel, preferring one whose voice
+/// just ended nearest in pitch, then the lowest idle one. When none is
+/// free, a continuing voice is stolen as set by `StealPolicy`. Voices that
+/// no peak continues are released.
+#[derive(Debug, Clone)]
+pub struct VoiceAllocator {
+    options: VoiceOptions,
+    frame: usize,
+    channels: Vec<Option<ActiveVoice>>,
+}
+
+impl VoiceAllocator {
+    /// Allocator over `channels` channels, all idle.
+    pub fn new(channels: usize, options: VoiceOptions) -> Self {
+        VoiceAllocator {
+            options,
+            frame: 0,
+            channels: vec![None; channels],
+        }
+    }
+
+    pub fn options(&self) -> &VoiceOptions {
+        &self.options
+    }
+
+    /// Number of channels.
+    pub fn channel_count(&self) -> usize {
+        self.channels.len()
+    }
+
+    /// Number of channels playing a voice after the last frame.
+    pub fn active_count(&self) -> usize {
+        self.channels.iter().flatten().count()
+    }
+
+    /// Place the next frame's `peaks` on channels. Returns the events of
+    /// every channel that starts, continues or releases a voice, by channel;
+    /// channels that stay idle are left out.
+    pub fn allocate(&mut self, peaks: &[Peak]) -> Vec<VoiceUpdate> {
+        let frame = self.frame;
+        self.frame += 1;
+        let cents = |a: f32, b: f32| (1200.0 * (a / b).log2()).abs();
+
+        // continuing voices, closest in pitch first
+        let mut pairs: Vec<(f32, usize, usize)> = Vec::new();
+        for (c, voice) in self.channels.iter().enumerate() {
+            let Some(voice) = voice else { continue };
+            for (p, peak) in peaks.iter().enumerate() {
+                let distance = cents(peak.freq_hz, voice.freq_hz);
+                if distance <= self.options.max_freq_deviation_cents {
+                    pairs.push((distance, c, p));
+                }
+            }
+        }
+        pairs.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let mut taken: Vec<Option<(VoiceEvent, usize)>> = vec![None; self.channels.len()];
+        let mut placed = vec![false; peaks.len()];
+        for &(_, c, p) in &pairs {
+            if taken[c].is_some() || placed[p] {
+                continue;
+            }
+            taken[c] = Some((VoiceEvent::Continued, p));
+            placed[p] = true;
+        }
+
+        for (p, peak) in peaks.iter().enumerate() {
+            if placed[p] {
+                continue;
+            }
+            let ended = (0..self.channels.len())
+                .filter(|&c| taken[c].is_none())
+                .filter_map(|c| self.channels[c].map(|voice| (c, voice)))
+                .min_by(|a, b| {
+                    let da = cents(peak.freq_hz, a.1.freq_hz);
+                    let db = cents(peak.freq_hz, b.1.freq_hz);
+                    da.total_cmp(&db)
+                })
+                .map(|(c, _)| c);
+            let idle = || (0..self.channels.len()).find(|&c| taken[c].is_none());
+            let Some(c) = ended
+                .or_else(idle)
+                .or_else(|| self.victim(&taken, peaks, peak))
+            else {
+                continue;
+            };
+            if let Some((VoiceEvent::Continued, stolen)) = taken[c] {
+                placed[stolen] = false;
+            }
+            taken[c] = Some((VoiceEvent::New, p));
+            placed[p] = true;
+        }
+
+        let mut updates = Vec::new();
+        for (c, slot) in taken.into_iter().enumerate() {
+            match slot {
+                Some((event, p)) => {
+                    let since = match (event, self.channels[c]) {
+                        (VoiceEvent::Continued, Some(voice)) => voice.since,
+                        _ => frame,
+                    };
+                    self.channels[c] = Some(ActiveVoice {
+                        freq_hz: peaks[p].freq_hz,
+                        since,
+                    });
+                    updates.push(VoiceUpdate {
+                        channel: c,
+                        event,
+                        peak: Some(p),
+                    });
+                }
+                None => {
+                    if self.channels[c].take().is_some() {
+                        updates.push(VoiceUpdate {
+                            channel: c,
+                            event: VoiceEvent::Released,
+                            peak: None,
+                        });
+                    }
+                }
+            }
+        }
+        updates
+    }
+
+    // Channel to steal for `peak` among those continuing a voice this frame
+    // (voices started this frame are kept).
+    fn victim(
+        &self,
+        taken: &[Option<(VoiceEvent, usize)>],
+        peaks: &[Peak],
+        peak: &Peak,
+    ) -> Option<usize> {
+        let continued = taken.iter().enumerate().filter_map(|(c, slot)| match slot {
+            Some((VoiceEvent::Continued, p)) => Some((c, &peaks[*p])),
+            _ => None,
+        });
+        match self.options.steal {
+            StealPolicy::Quietest => continued
+                .min_by(|a, b| a.1.magnitude.total_cmp(&b.1.magnitude))
+                .filter(|(_, quietest)| quietest.magnitude < peak.magnitude)
+                .map(|(c, _)| c),
+            StealPolicy::Oldest => continued
+                .filter_map(|(c, _)| self.channels[c].map(|voice| (c, voice.since)))
+                .min_by_key(|&(_, since)| since)
+                .map(|(c, _)| c),
+            StealPolicy::FurthestPitch => continued
+                .max_by(|a, b| {
+                    let da = (a.1.freq_hz / peak.freq_hz).log2().abs();
+                    let db = (b.1.freq_hz / peak.freq_hz).log2().abs();
+                    da.total_cmp(&db)
+                })
+                .map(|(c, _)| c),
+        }
+    }
+}
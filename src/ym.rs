@@ -244,6 +244,16 @@ pub fn init_ymf262_channel_and_op(
         );
     }
 
+    // two-operator FM, output to both speakers
+    b.add_chip_write(
+        Instance::Primary,
+        Ymf262Spec {
+            port: freq_port,
+            register: 0xC0 + freq_idx,
+            value: Opl3Output::Both.bits(),
+        },
+    );
+
     // rewrite frequency after operator setup
     b.add_chip_write(
         Instance::Primary,
@@ -354,3 +364,182 @@ pub fn ymf262_keyon(b: &mut VgmBuilder, ch: u8, fnum_val: u16, block_val: u8, tl
         },
     );
 }
+
+/// Retune channel `ch` of a YM2203 without keying it on again. The high
+/// byte (A4) is latched until the low byte (A0) is written, so it goes first.
+pub fn ym2203_frequency(b: &mut VgmBuilder, instance: u8, ch: u8, fnum_val: u16, block_val: u8) {
+    let instance: Instance = (instance as usize).into();
+    let low = (fnum_val & 0xFF) as u8;
+    let high = (((fnum_val >> 8) & 0x07) as u8) | ((block_val & 0x07) << 3);
+    b.add_chip_write(
+        instance,
+        Ym2203Spec {
+            register: 0xA4 + ch,
+            value: high,
+        },
+    );
+    b.add_chip_write(
+        instance,
+        Ym2203Spec {
+            register: 0xA0 + ch,
+            value: low,
+        },
+    );
+}
+
+/// Set the level (TL of the sounding operator) of YM2203 channel `ch`.
+pub fn ym2203_level(b: &mut VgmBuilder, instance: u8, ch: u8, tl: u8) {
+    let instance: Instance = (instance as usize).into();
+    b.add_chip_write(
+        instance,
+        Ym2203Spec {
+            register: 0x40 + ch,
+            value: tl,
+        },
+    );
+}
+
+/// Key off YM2203 channel `ch` and silence it: channels are set up with no
+/// release, so the key-off alone would hold the note.
+pub fn ym2203_keyoff(b: &mut VgmBuilder, instance: u8, ch: u8) {
+    let chip: Instance = (instance as usize).into();
+    b.add_chip_write(
+        chip,
+        Ym2203Spec {
+            register: 0x28,
+            value: ch & 0x0F,
+        },
+    );
+    ym2203_level(b, instance, ch, 0x7F);
+}
+
+/// Retune OPL3 channel `ch` (0..18), leaving it keyed on.
+pub fn ymf262_frequency(b: &mut VgmBuilder, ch: u8, fnum_val: u16, block_val: u8) {
+    ymf262_fnum_block(b, ch, fnum_val, block_val, true);
+}
+
+/// Set the level (carrier TL) of OPL3 channel `ch`.
+pub fn ymf262_level(b: &mut VgmBuilder, ch: u8, tl: u8) {
+    let op_car = OPL3_OPS_BY_CH.get(ch as usize).map_or(3, |ops| ops.1);
+    let (port, off) = OPL3_OP_MAP[op_car as usize];
+    b.add_chip_write(
+        Instance::Primary,
+        Ymf262Spec {
+            port,
+            register: 0x40 + off,
+            value: tl,
+        },
+    );
+}
+
+/// Key off OPL3 channel `ch` (keeping its frequency) and silence it:
+/// channels are set up with no release, so the key-off alone would hold
+/// the note.
+pub fn ymf262_keyoff(b: &mut VgmBuilder, ch: u8, fnum_val: u16, block_val: u8) {
+    ymf262_fnum_block(b, ch, fnum_val, block_val, false);
+    ymf262_level(b, ch, 0x3F);
+}
+
+// Frequency registers A0/B0 of OPL3 channel `ch`; B0 also holds the key-on
+// bit.
+fn ymf262_fnum_block(b: &mut VgmBuilder, ch: u8, fnum_val: u16, block_val: u8, key_on: bool) {
+    let low = (fnum_val & 0xFF) as u8;
+    let high = (((fnum_val >> 8) & 0x03) as u8) | ((block_val & 0x07) << 2);
+    let port: u8 = if ch >= 9 { 1 } else { 0 };
+    let reg_ch = ch % 9;
+    b.add_chip_write(
+        Instance::Primary,
+        Ymf262Spec {
+            port,
+            register: 0xA0 + reg_ch,
+            value: low,
+        },
+    );
+    b.add_chip_write(
+        Instance::Primary,
+        Ymf262Spec {
+            port,
+            register: 0xB0 + reg_ch,
+            value: if key_on { high | 0x20 } else { high },
+        },
+    );
+}
+
+/// Speakers an OPL3 channel plays on (register C0-C8 bits 4-5).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Opl3Output {
+    Left,
+    Right,
+    Both,
+}
+
+impl Opl3Output {
+    /// Routing for a voice at `pan` (-1 left .. 1 right): one side only
+    /// when it is about 6 dB louder there (`|pan| > 0.6`), otherwise both.
+    pub fn from_pan(pan: f32) -> Self {
+        if pan < -0.6 {
+            Opl3Output::Left
+        } else if pan > 0.6 {
+            Opl3Output::Right
+        } else {
+            Opl3Output::Both
+        }
+    }
+
+    /// Output-select bits (CHA = left, CHB = right).
+    pub fn bits(self) -> u8 {
+        match self {
+            Opl3Output::Left => 0x10,
+            Opl3Output::Right => 0x20,
+            Opl3Output::Both => 0x30,
+        }
+    }
+}
+
+/// Route channel `ch` (0..18) to `output`, keeping two-operator FM with no
+/// feedback.
+pub fn ymf262_output(b: &mut VgmBuilder, ch: u8, output: Opl3Output) {
+    let port: u8 = if ch >= 9 { 1 } else { 0 };
+    b.add_chip_write(
+        Instance::Primary,
+        Ymf262Spec {
+            port,
+            register: 0xC0 + ch % 9,
+            value: output.bits(),
+        },
+    );
+}
+
+// SSG mixer (register 0x07): tone off on A/B/C, noise on C only.
+const YM2203_SSG_MIXER_NOISE_C: u8 = 0x1F;
+
+pub fn ym2203_ssg_noise_enable(b: &mut VgmBuilder, instance: u8) {
+    let instance: Instance = (instance as usize).into();
+    b.add_chip_write(
+        instance,
+        Ym2203Spec {
+            register: 0x07,
+            value: YM2203_SSG_MIXER_NOISE_C,
+        },
+    );
+}
+
+pub fn ym2203_ssg_noise(b: &mut VgmBuilder, instance: u8, period: u8, level: u8) {
+    let instance: Instance = (instance as usize).into();
+    // noise period (5 bits)
+    b.add_chip_write(
+        instance,
+        Ym2203Spec {
+            register: 0x06,
+            value: period & 0x1F,
+        },
+    );
+    // channel C level (4 bits, fixed level mode)
+    b.add_chip_write(
+        instance,
+        Ym2203Spec {
+            register: 0x0A,
+            value: level & 0x0F,
+        },
+    );
+}
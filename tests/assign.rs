@@ -0,0 +1,112 @@
+use nanonanoda::assign::{AssignmentCandidate, AssignmentCost, WeightedCost, min_cost_assignment};
+use nanonanoda::pcm::Peak;
+use soundlog::chip::Chip;
+use soundlog::chip::fnumber::FNumber;
+
+fn total(costs: &[Vec<f32>], columns: &[usize]) -> f32 {
+    columns.iter().enumerate().map(|(r, &c)| costs[r][c]).sum()
+}
+
+// every assignment of rows to distinct columns
+fn brute_force(costs: &[Vec<f32>], row: usize, used: &mut Vec<bool>) -> f32 {
+    if row == costs.len() {
+        return 0.0;
+    }
+    let mut best = f32::INFINITY;
+    for c in 0..used.len() {
+        if !used[c] {
+            used[c] = true;
+            best = best.min(costs[row][c] + brute_force(costs, row + 1, used));
+            used[c] = false;
+        }
+    }
+    best
+}
+
+#[test]
+fn test_min_cost_assignment_is_optimal() {
+    // taking the cheapest column for row 0 first costs 1 + 10
+    let costs = vec![vec![1.0, 2.0], vec![1.0, 10.0]];
+    assert_eq!(min_cost_assignment(&costs), vec![1, 0]);
+
+    // pseudo-random rectangular matrices against exhaustive search
+    let mut seed = 12345u32;
+    let mut next = || {
+        seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        (seed >> 16) as f32 / 65536.0 * 20.0 - 5.0
+    };
+    for (rows, columns) in [(1, 1), (3, 3), (3, 5), (4, 6), (5, 5)] {
+        for _ in 0..20 {
+            let costs: Vec<Vec<f32>> = (0..rows)
+                .map(|_| (0..columns).map(|_| next()).collect())
+                .collect();
+            let assignment = min_cost_assignment(&costs);
+            let mut seen = assignment.clone();
+            seen.sort_unstable();
+            seen.dedup();
+            assert_eq!(seen.len(), rows, "columns reused: {assignment:?}");
+            let expected = brute_force(&costs, 0, &mut vec![false; columns]);
+            assert!((total(&costs, &assignment) - expected).abs() < 1e-3);
+        }
+    }
+    assert!(min_cost_assignment(&[]).is_empty());
+}
+
+#[test]
+fn test_weighted_cost_terms() {
+    let peak = |freq_hz: f32, magnitude_db: f32| Peak {
+        freq_hz,
+        magnitude: 10f32.powf(magnitude_db / 20.0),
+        magnitude_db,
+        bin: 0,
+    };
+    let fnumber = FNumber {
+        f_num: 0,
+        block: 0,
+        actual_freq_hz: 440.0,
+        error_hz: 0.0,
+        error_cents: 10.0,
+    };
+    let cost = WeightedCost::default();
+    let loud = peak(440.0, -6.0);
+    let candidate = AssignmentCandidate {
+        peak: &loud,
+        chip: &Chip::Ymf262,
+        instance: 0,
+        fnumber: &fnumber,
+        freq_range: Some((25.0, 6000.0)),
+        level_floor_db: -47.25,
+        continuity_cents: Some(5.0),
+    };
+    // 10 cents of tuning error on a continuing voice
+    assert!((cost.cost(&candidate).unwrap() - 1.0).abs() < 1e-4);
+    // a new voice pays for the discontinuity
+    let fresh = AssignmentCandidate {
+        continuity_cents: None,
+        ..candidate
+    };
+    assert!((cost.cost(&fresh).unwrap() - 4.0).abs() < 1e-4);
+    // level the chip cannot reach
+    let quiet = peak(440.0, -57.25);
+    let low = AssignmentCandidate {
+        peak: &quiet,
+        ..candidate
+    };
+    assert!((cost.cost(&low).unwrap() - 11.0).abs() < 1e-4);
+    // out of range: penalized, or forbidden
+    let high = peak(6500.0, -6.0);
+    let out = AssignmentCandidate {
+        peak: &high,
+        ..candidate
+    };
+    assert!((cost.cost(&out).unwrap() - 21.0).abs() < 1e-4);
+    let strict = WeightedCost {
+        out_of_range: None,
+        ..Default::default()
+    };
+    assert_eq!(strict.cost(&out), None);
+
+    // dropping a peak costs its level above -60 dBFS
+    assert!((cost.drop_cost(&loud) - 54.0).abs() < 1e-4);
+    assert_eq!(cost.drop_cost(&peak(440.0, -80.0)), 0.0);
+}
@@ -0,0 +1,29 @@
+// Signal generators shared by the integration tests.
+
+// Pulse train at `f0` through two-pole resonators `(freq, bandwidth)`,
+// normalized to a 0.5 peak.
+pub fn synthetic_vowel(
+    f0: f32,
+    formants: &[(f32, f32)],
+    sample_rate: usize,
+    len: usize,
+) -> Vec<f32> {
+    let period = sample_rate as f32 / f0;
+    let mut x: Vec<f32> = (0..len)
+        .map(|i| if (i as f32 % period) < 1.0 { 1.0 } else { 0.0 })
+        .collect();
+    for &(freq, bandwidth) in formants {
+        let r = (-std::f32::consts::PI * bandwidth / sample_rate as f32).exp();
+        let theta = 2.0 * std::f32::consts::PI * freq / sample_rate as f32;
+        let (a1, a2) = (-2.0 * r * theta.cos(), r * r);
+        let (mut y1, mut y2) = (0.0f32, 0.0f32);
+        for v in x.iter_mut() {
+            let y = (1.0 + a1 + a2) * *v - a1 * y1 - a2 * y2;
+            y2 = y1;
+            y1 = y;
+            *v = y;
+        }
+    }
+    let peak = x.iter().fold(0.0f32, |m, v| m.max(v.abs()));
+    x.iter().map(|v| 0.5 * v / peak).collect()
+}
@@ -0,0 +1,120 @@
+use nanonanoda::filter::{FilterStage, preprocess, preprocess_channels};
+use nanonanoda::loudness::{peak_dbfs, rms_dbfs};
+use nanonanoda::pcm::analyze_pcm_peaks;
+use nanonanoda::resynth::{ResynthOptions, process_samples_resynth_multi};
+use soundlog::chip::Chip;
+
+fn sine(freq_hz: f32, amp: f32, sample_rate: usize, len: usize) -> Vec<f32> {
+    (0..len)
+        .map(|i| amp * (2.0 * std::f32::consts::PI * freq_hz * i as f32 / sample_rate as f32).sin())
+        .collect()
+}
+
+// Level change (dB) of a sine at `freq_hz` through `stage`, after settling.
+fn response_db(stage: FilterStage, freq_hz: f32) -> f32 {
+    let sample_rate = 44100usize;
+    let input = sine(freq_hz, 0.5, sample_rate, sample_rate);
+    let mut out = input.clone();
+    preprocess(&mut out, sample_rate, &[stage]);
+    let settled = sample_rate / 2..;
+    rms_dbfs(&out[settled.clone()]) - rms_dbfs(&input[settled])
+}
+
+#[test]
+fn test_filters_shape_the_spectrum() {
+    // DC offset is removed, the tone passes
+    let sample_rate = 44100usize;
+    let mut offset: Vec<f32> = sine(440.0, 0.3, sample_rate, sample_rate)
+        .iter()
+        .map(|x| x + 0.2)
+        .collect();
+    preprocess(&mut offset, sample_rate, &[FilterStage::dc_removal()]);
+    let tail = &offset[sample_rate / 2..];
+    let mean = tail.iter().sum::<f32>() / tail.len() as f32;
+    assert!(mean.abs() < 1e-3, "{mean}");
+    assert!(response_db(FilterStage::dc_removal(), 440.0).abs() < 0.05);
+
+    let high_pass = FilterStage::high_pass(100.0);
+    assert!(response_db(high_pass, 25.0) < -20.0);
+    assert!((response_db(high_pass, 100.0) + 3.01).abs() < 0.1);
+    assert!(response_db(high_pass, 1000.0).abs() < 0.05);
+
+    let low_pass = FilterStage::low_pass(2000.0);
+    assert!(response_db(low_pass, 8000.0) < -20.0);
+    assert!((response_db(low_pass, 2000.0) + 3.01).abs() < 0.1);
+    assert!(response_db(low_pass, 200.0).abs() < 0.05);
+
+    // pre-emphasis lifts the treble against the bass
+    let emphasis = FilterStage::PreEmphasis { coefficient: 0.95 };
+    assert!(response_db(emphasis, 8000.0) - response_db(emphasis, 100.0) > 20.0);
+}
+
+#[test]
+fn test_dynamics_stages() {
+    let sample_rate = 44100usize;
+    let half = sample_rate / 2;
+    // a loud passage followed by one 30 dB quieter
+    let mut input = sine(440.0, 0.5, sample_rate, half);
+    input.extend(sine(440.0, 0.5 * 10f32.powf(-1.5), sample_rate, half));
+    let level_drop = |v: &[f32]| rms_dbfs(&v[half / 2..half]) - rms_dbfs(&v[half + half / 2..]);
+
+    // 4:1 above -30 dB: the 30 dB drop shrinks towards 30 - 0.75 * 21
+    let mut compressed = input.clone();
+    preprocess(
+        &mut compressed,
+        sample_rate,
+        &[FilterStage::compressor(-30.0, 4.0)],
+    );
+    let drop = level_drop(&compressed);
+    assert!((drop - 14.3).abs() < 1.5, "{drop}");
+
+    let mut normalized = input.clone();
+    preprocess(
+        &mut normalized,
+        sample_rate,
+        &[FilterStage::Normalize { peak_dbfs: -3.0 }],
+    );
+    assert!((peak_dbfs(&normalized) + 3.0).abs() < 0.01);
+    assert!((level_drop(&normalized) - 30.0).abs() < 0.1);
+
+    // the gate mutes the quiet passage and keeps the loud one
+    let mut gated = input.clone();
+    preprocess(&mut gated, sample_rate, &[FilterStage::gate(-20.0)]);
+    assert!((rms_dbfs(&gated[half / 2..half]) - rms_dbfs(&input[half / 2..half])).abs() < 0.1);
+    assert!(gated[half + sample_rate / 4..].iter().all(|&x| x == 0.0));
+
+    // stereo dynamics are linked: the quiet channel follows the loud one
+    let mut left = input.clone();
+    let mut right: Vec<f32> = input.iter().map(|x| x * 0.01).collect();
+    preprocess_channels(
+        &mut [&mut left, &mut right],
+        sample_rate,
+        &[FilterStage::gate(-20.0)],
+    );
+    assert!(rms_dbfs(&right[half / 2..half]) > -60.0);
+    assert!(right[half + sample_rate / 4..].iter().all(|&x| x == 0.0));
+}
+
+#[test]
+fn test_preprocess_runs_before_analysis() {
+    let sample_rate = 44100usize;
+    // loud rumble under a quieter tone
+    let input: Vec<f32> = sine(40.0, 0.6, sample_rate, sample_rate / 2)
+        .iter()
+        .zip(sine(440.0, 0.2, sample_rate, sample_rate / 2))
+        .map(|(a, b)| a + b)
+        .collect();
+    let chips = vec![(Chip::Ymf262, 1usize)];
+    let loudest = |options: &ResynthOptions| {
+        let out =
+            process_samples_resynth_multi(&input, sample_rate, 2048, sample_rate, &chips, options)
+                .expect("resynth");
+        analyze_pcm_peaks(&out[4096..4096 + 8192], sample_rate, 1)[0].freq_hz
+    };
+    assert!(loudest(&ResynthOptions::default()) < 100.0);
+    let filtered = ResynthOptions {
+        preprocess: vec![FilterStage::high_pass(200.0)],
+        ..Default::default()
+    };
+    assert!((loudest(&filtered) - 440.0).abs() < 10.0);
+}
@@ -0,0 +1,108 @@
+use nanonanoda::fnumber::{FNumberSolver, fnumber_solver, fnumber_table, solve_fnumber};
+use soundlog::chip::fnumber::{
+    ChipTypeSpec, Opl3Spec, OpnSpec, find_and_tune_fnumber, generate_12edo_fnum_table,
+};
+use std::sync::Arc;
+
+fn cents(a: f32, b: f32) -> f32 {
+    (1200.0 * (a / b).log2()).abs()
+}
+
+// Least error (cents) of any block and F-number of chip `C` for `freq`.
+fn brute_force_cents<C: ChipTypeSpec>(freq: f32, clock: f32) -> f32 {
+    let config = C::config();
+    let mut best = f32::MAX;
+    for block in 0..(1u8 << config.block_bits) {
+        for f_num in 1..(1u32 << config.fnum_bits) {
+            let actual = C::fnum_block_to_freq(f_num, block, clock).unwrap();
+            best = best.min(cents(actual, freq));
+        }
+    }
+    best
+}
+
+fn check_solver<C: ChipTypeSpec + 'static>(clock: f32) {
+    let solver = FNumberSolver::new::<C>(clock).unwrap();
+    let (lo, hi) = solver.frequency_range();
+    // the band of full resolution: the upper half of the F-numbers in block 0
+    // up to the largest one in the highest block
+    let config = C::config();
+    let half = C::fnum_block_to_freq(1 << (config.fnum_bits - 1), 0, clock).unwrap();
+    let top_block = (1u8 << config.block_bits) - 1;
+    let top = C::fnum_block_to_freq((1 << config.fnum_bits) - 1, top_block, clock).unwrap();
+    assert!(
+        cents(lo, half) < 1e-3 && cents(hi, top) < 1e-3,
+        "{lo}..{hi}"
+    );
+    // log-spaced over the range, plus a speech formant off the note grid
+    let freqs = (0..40)
+        .map(|i| lo * 1.5 * (hi / lo / 1.5).powf(i as f32 / 39.0))
+        .chain([1913.7]);
+    for freq in freqs {
+        let fnumber = solver.solve(freq).unwrap();
+        let actual = C::fnum_block_to_freq(fnumber.f_num, fnumber.block, clock).unwrap();
+        assert!(cents(actual, fnumber.actual_freq_hz) < 1e-3, "{freq}");
+        assert!((fnumber.error_cents - cents(actual, freq)).abs() < 1e-2);
+        assert!(
+            fnumber.error_cents <= brute_force_cents::<C>(freq, clock) + 1e-2,
+            "{freq}: {fnumber:?}"
+        );
+    }
+}
+
+#[test]
+fn test_solver_matches_exhaustive_search() {
+    check_solver::<Opl3Spec>(Opl3Spec::default_master_clock());
+    check_solver::<OpnSpec>(OpnSpec::default_master_clock());
+    // boards run the chips off other crystals
+    check_solver::<OpnSpec>(3_993_600.0);
+    check_solver::<OpnSpec>(3_579_545.0);
+    check_solver::<Opl3Spec>(14_000_000.0);
+
+    // out-of-range targets get the nearest end of the range
+    let solver = FNumberSolver::new::<Opl3Spec>(Opl3Spec::default_master_clock()).unwrap();
+    let top = solver.solve(20000.0).unwrap();
+    assert_eq!((top.f_num, top.block), (1023, 7));
+    assert!(solver.solve(0.0).is_err());
+    assert!(solver.solve(f32::NAN).is_err());
+}
+
+#[test]
+fn test_solver_is_never_worse_than_table_search() {
+    let clock = OpnSpec::default_master_clock();
+    let table = generate_12edo_fnum_table::<OpnSpec>(clock).unwrap();
+    for i in 0..60 {
+        let freq = 55.0 * 2f32.powf(i as f32 / 10.3);
+        let solved = solve_fnumber::<OpnSpec>(freq, clock).unwrap();
+        let searched = find_and_tune_fnumber::<OpnSpec>(&table, freq, clock).unwrap();
+        assert!(
+            solved.error_cents <= searched.error_cents + 1e-3,
+            "{freq}: {solved:?} vs {searched:?}"
+        );
+    }
+}
+
+#[test]
+fn test_tables_and_solvers_are_cached() {
+    let clock = Opl3Spec::default_master_clock();
+    let a = fnumber_solver::<Opl3Spec>(clock).unwrap();
+    let b = fnumber_solver::<Opl3Spec>(clock).unwrap();
+    assert!(Arc::ptr_eq(&a, &b));
+    assert_eq!(a.master_clock(), clock);
+    // another clock or chip type is another entry
+    let other = fnumber_solver::<Opl3Spec>(clock / 2.0).unwrap();
+    assert!(!Arc::ptr_eq(&a, &other));
+    assert_eq!(other.master_clock(), clock / 2.0);
+
+    let table = fnumber_table::<OpnSpec>(OpnSpec::default_master_clock()).unwrap();
+    assert!(Arc::ptr_eq(
+        &table,
+        &fnumber_table::<OpnSpec>(OpnSpec::default_master_clock()).unwrap()
+    ));
+    let fresh = generate_12edo_fnum_table::<OpnSpec>(OpnSpec::default_master_clock()).unwrap();
+    for (row, fresh_row) in table.iter().zip(&fresh) {
+        for (entry, fresh_entry) in row.iter().zip(fresh_row) {
+            assert_eq!(entry.map(|e| e.1), fresh_entry.map(|e| e.1));
+        }
+    }
+}
@@ -0,0 +1,157 @@
+use nanonanoda::loudness::{
+    GainOptions, LimiterOptions, LoudnessTarget, apply_output_gain, apply_output_gain_channels,
+    integrated_loudness, limit, peak_dbfs, rms_dbfs,
+};
+use nanonanoda::resynth::{ResynthOptions, process_samples_resynth_multi};
+use soundlog::chip::Chip;
+
+fn sine(freq_hz: f32, amp: f32, sample_rate: usize, len: usize) -> Vec<f32> {
+    (0..len)
+        .map(|i| amp * (2.0 * std::f32::consts::PI * freq_hz * i as f32 / sample_rate as f32).sin())
+        .collect()
+}
+
+#[test]
+fn test_levels_of_a_full_scale_sine() {
+    // BS.1770 calibrates a 0 dBFS 997 Hz sine to -3.01 LUFS
+    for sample_rate in [44100usize, 48000] {
+        let samples = sine(997.0, 1.0, sample_rate, sample_rate * 2);
+        let lufs = integrated_loudness(&samples, sample_rate);
+        assert!((lufs + 3.01).abs() < 0.1, "{sample_rate}: {lufs}");
+        assert!((rms_dbfs(&samples) + 3.01).abs() < 0.05);
+        assert!(peak_dbfs(&samples).abs() < 0.01);
+    }
+
+    // gating ignores silence: a tone followed by twice as much silence
+    // reads close to the tone alone instead of 4.8 dB below it
+    let mut gated = sine(997.0, 0.1, 48000, 48000);
+    let tone_only = integrated_loudness(&gated, 48000);
+    gated.extend(vec![0.0; 96000]);
+    let with_silence = integrated_loudness(&gated, 48000);
+    assert!(
+        (with_silence - tone_only).abs() < 1.0,
+        "{with_silence} vs {tone_only}"
+    );
+    assert_eq!(integrated_loudness(&[0.0; 1000], 48000), f32::NEG_INFINITY);
+}
+
+#[test]
+fn test_gain_targets_and_limiter() {
+    let sample_rate = 44100usize;
+    let reference = sine(440.0, 0.5, sample_rate, sample_rate);
+    let output = sine(440.0, 0.1, sample_rate, sample_rate);
+
+    let targets = [
+        (
+            LoudnessTarget::Peak { dbfs: -6.0 },
+            -6.0 - peak_dbfs(&output),
+        ),
+        (
+            LoudnessTarget::MatchRms,
+            rms_dbfs(&reference) - rms_dbfs(&output),
+        ),
+        (
+            LoudnessTarget::MatchLoudness,
+            integrated_loudness(&reference, sample_rate)
+                - integrated_loudness(&output, sample_rate),
+        ),
+        (LoudnessTarget::Fixed { gain_db: 3.0 }, 3.0),
+    ];
+    for (target, expected_db) in targets {
+        let mut out = output.clone();
+        let options = GainOptions {
+            target,
+            limiter: None,
+        };
+        let gain_db = apply_output_gain(&mut out, sample_rate, &reference, sample_rate, &options);
+        assert!(
+            (gain_db - expected_db).abs() < 0.01,
+            "{target:?}: {gain_db}"
+        );
+        assert!((rms_dbfs(&out) - rms_dbfs(&output) - gain_db).abs() < 0.01);
+    }
+
+    // silence is left alone
+    let mut silent = vec![0.0f32; 1000];
+    assert_eq!(
+        apply_output_gain(
+            &mut silent,
+            sample_rate,
+            &reference,
+            sample_rate,
+            &Default::default()
+        ),
+        0.0
+    );
+
+    // the limiter holds an overshooting burst under its ceiling, leaves the
+    // quiet part before it untouched, and recovers afterwards
+    let options = LimiterOptions::default();
+    let ceiling = 10f32.powf(options.ceiling_dbfs / 20.0);
+    let mut signal = sine(440.0, 0.3, sample_rate, sample_rate);
+    for v in &mut signal[20000..22000] {
+        *v *= 6.0;
+    }
+    let original = signal.clone();
+    limit(&mut signal, sample_rate, &options);
+    assert!(signal.iter().all(|v| v.abs() <= ceiling));
+    assert_eq!(&signal[..19000], &original[..19000]);
+    let last = signal.len() - 1000..signal.len();
+    assert!((rms_dbfs(&signal[last.clone()]) - rms_dbfs(&original[last])).abs() < 0.1);
+}
+
+#[test]
+fn test_mono_reference_for_stereo_output() {
+    let sample_rate = 48000usize;
+    let reference = sine(997.0, 0.5, sample_rate, sample_rate);
+    // both targets bring each output channel to the mono reference's level
+    for target in [LoudnessTarget::MatchLoudness, LoudnessTarget::MatchRms] {
+        let mut left = sine(997.0, 0.25, sample_rate, sample_rate);
+        let mut right = left.clone();
+        let options = GainOptions {
+            target,
+            limiter: None,
+        };
+        let gain_db = apply_output_gain_channels(
+            &mut [&mut left, &mut right],
+            sample_rate,
+            &[&reference],
+            sample_rate,
+            &options,
+        );
+        assert!((gain_db - 6.02).abs() < 0.05, "{target:?}: {gain_db}");
+        assert!((peak_dbfs(&left) - peak_dbfs(&reference)).abs() < 0.05);
+    }
+}
+
+#[test]
+fn test_resynth_keeps_dynamics() {
+    let sample_rate = 44100usize;
+    // a loud passage followed by one 20 dB quieter
+    let mut samples = sine(440.0, 0.5, sample_rate, sample_rate / 2);
+    samples.extend(sine(440.0, 0.05, sample_rate, sample_rate / 2));
+    let chips = vec![(Chip::Ym2203, 3usize)];
+    let loud = 4096..sample_rate / 2 - 4096;
+    let quiet = sample_rate / 2 + 4096..sample_rate - 4096;
+
+    let out = process_samples_resynth_multi(
+        &samples,
+        sample_rate,
+        1024,
+        sample_rate,
+        &chips,
+        &ResynthOptions::default(),
+    )
+    .expect("resynth");
+    let drop_db = rms_dbfs(&out[loud.clone()]) - rms_dbfs(&out[quiet.clone()]);
+    assert!((drop_db - 20.0).abs() < 1.0, "{drop_db}");
+    assert!((peak_dbfs(&out) + 1.0).abs() < 0.1, "{}", peak_dbfs(&out));
+
+    // matching the input RMS reproduces the input level
+    let mut options = ResynthOptions::default();
+    options.gain.target = LoudnessTarget::MatchRms;
+    let out =
+        process_samples_resynth_multi(&samples, sample_rate, 1024, sample_rate, &chips, &options)
+            .expect("resynth matching rms");
+    assert!((rms_dbfs(&out[loud.clone()]) - rms_dbfs(&samples[loud])).abs() < 1.0);
+}
@@ -1,4 +1,39 @@
-use nanonanoda::pcm::{Peak, analyze_pcm_peaks, synthesize_sines};
+mod common;
+
+use common::synthetic_vowel;
+use nanonanoda::pcm::{
+    AnalysisOptions, F0Estimator, F0Method, F0Options, Formant, FormantAnalyzer, FormantOptions,
+    FrequencyEstimator, NoiseSynthesizer, OnsetMethod, OnsetOptions, OscillatorBank,
+    OscillatorOptions, Peak, PeakPicking, PeakSelection, PeakSpacing, RejectReason,
+    ResidualAnalyzer, ResidualOptions, SpectralAnalyzer, WindowFunction, analyze_pcm_peaks,
+    analyze_pcm_peaks_with_options, default_resolution_bands, detect_onsets, estimate_f0,
+    estimate_formants, estimate_pan, interleaved_to_stereo, merge_channel_peaks, pan_from_levels,
+    pan_to_levels, synthesize_sines,
+};
+
+// Deterministic uniform noise in -amp..amp (LCG).
+fn lcg_noise(len: usize, amp: f32) -> Vec<f32> {
+    let mut state: u32 = 12345;
+    (0..len)
+        .map(|_| {
+            state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+            ((state >> 8) as f32 / (1u32 << 24) as f32 * 2.0 - 1.0) * amp
+        })
+        .collect()
+}
+
+// Harmonics `first..=last` of `f0` with 1/h amplitudes.
+fn harmonic_tone(f0: f32, first: usize, last: usize, sample_rate: usize, len: usize) -> Vec<f32> {
+    (0..len)
+        .map(|i| {
+            let t = i as f32 / sample_rate as f32;
+            (first..=last)
+                .map(|h| (2.0 * std::f32::consts::PI * f0 * h as f32 * t).sin() / h as f32)
+                .sum::<f32>()
+                * 0.3
+        })
+        .collect()
+}
 
 #[test]
 fn test_analyze_single_tone() {
@@ -125,3 +160,696 @@ fn test_synthesize_multi_peaks_low_magnitude() {
         );
     }
 }
+
+#[test]
+fn test_frequency_estimators_sub_bin() {
+    let sample_rate: usize = 44100;
+    let window: usize = 512;
+    // deliberately between bins (bin width ~86 Hz)
+    let freq: f32 = 1000.0;
+    let samples: Vec<f32> = (0..window)
+        .map(|i| (2.0 * std::f32::consts::PI * freq * i as f32 / sample_rate as f32).sin())
+        .collect();
+    let resolution = (sample_rate as f32) / (window as f32);
+
+    let cases = [
+        (FrequencyEstimator::Bin, 0.5),
+        (FrequencyEstimator::Parabolic, 0.05),
+        (FrequencyEstimator::Jain, 0.25),
+        (FrequencyEstimator::PhaseVocoder, 0.05),
+    ];
+    let bin_peak = analyze_pcm_peaks_with_options(
+        &samples,
+        sample_rate,
+        1,
+        &AnalysisOptions {
+            estimator: FrequencyEstimator::Bin,
+            ..Default::default()
+        },
+    )[0];
+    for (estimator, tolerance_bins) in cases {
+        let options = AnalysisOptions {
+            estimator,
+            ..Default::default()
+        };
+        let peaks = analyze_pcm_peaks_with_options(&samples, sample_rate, 1, &options);
+        assert_eq!(peaks.len(), 1);
+        let p = peaks[0];
+        assert!(
+            (p.freq_hz - freq).abs() <= resolution * tolerance_bins,
+            "{:?}: {} Hz not within {} bins of {}",
+            estimator,
+            p.freq_hz,
+            tolerance_bins,
+            freq
+        );
+        // refined magnitudes never fall below the raw bin magnitude
+        assert!(p.magnitude >= bin_peak.magnitude);
+    }
+}
+
+#[test]
+fn test_spectral_analyzer_reuse_matches_one_shot() {
+    let sample_rate: usize = 44100;
+    let options = AnalysisOptions {
+        estimator: FrequencyEstimator::PhaseVocoder,
+        ..Default::default()
+    };
+    let mut analyzer = SpectralAnalyzer::new(options.clone());
+
+    // alternate window lengths so plans and windows get rebuilt and reused
+    for (i, &len) in [512usize, 512, 1000, 2048, 512].iter().enumerate() {
+        let freq = 300.0 + 250.0 * i as f32;
+        let samples: Vec<f32> = (0..len)
+            .map(|n| (2.0 * std::f32::consts::PI * freq * n as f32 / sample_rate as f32).sin())
+            .collect();
+        let reused = analyzer.analyze(&samples, sample_rate, 4);
+        let one_shot = analyze_pcm_peaks_with_options(&samples, sample_rate, 4, &options);
+        assert_eq!(reused, one_shot, "window length {}", len);
+    }
+}
+
+#[test]
+fn test_window_functions_calibrated_magnitude() {
+    let sample_rate: usize = 44100;
+    let window: usize = 2048;
+    let windows = [
+        WindowFunction::Hann,
+        WindowFunction::Hamming,
+        WindowFunction::Blackman,
+        WindowFunction::BlackmanHarris,
+        WindowFunction::Kaiser { beta: 8.6 },
+        WindowFunction::Gaussian { sigma: 0.4 },
+        WindowFunction::FlatTop,
+    ];
+
+    // one bin-centred tone and one between bins
+    for freq in [100.0 * sample_rate as f32 / window as f32, 1000.0] {
+        let samples: Vec<f32> = (0..window)
+            .map(|i| (2.0 * std::f32::consts::PI * freq * i as f32 / sample_rate as f32).sin())
+            .collect();
+        let reference = analyze_pcm_peaks(&samples, sample_rate, 1)[0];
+        for function in windows {
+            let options = AnalysisOptions {
+                window: function,
+                ..Default::default()
+            };
+            let p = analyze_pcm_peaks_with_options(&samples, sample_rate, 1, &options)[0];
+            assert!(
+                (p.magnitude_db - reference.magnitude_db).abs() < 0.5,
+                "{:?} at {} Hz: {} dB vs Hann {} dB",
+                function,
+                freq,
+                p.magnitude_db,
+                reference.magnitude_db
+            );
+        }
+    }
+}
+
+#[test]
+fn test_window_function_coherent_gain() {
+    let len = 4096;
+    assert!((WindowFunction::Hann.coherent_gain(len) - 0.5).abs() < 1e-3);
+    assert!((WindowFunction::Hamming.coherent_gain(len) - 0.54).abs() < 1e-3);
+    assert!((WindowFunction::Blackman.coherent_gain(len) - 0.42).abs() < 1e-3);
+    let kaiser = WindowFunction::Kaiser { beta: 0.0 }.coefficients(16);
+    assert!(kaiser.iter().all(|&w| (w - 1.0).abs() < 1e-6));
+}
+
+#[test]
+fn test_full_scale_sine_reads_0_dbfs() {
+    let sample_rate: usize = 44100;
+    let windows = [
+        WindowFunction::Hann,
+        WindowFunction::Hamming,
+        WindowFunction::BlackmanHarris,
+        WindowFunction::Kaiser { beta: 8.6 },
+        WindowFunction::FlatTop,
+    ];
+
+    // powers of two and zero-padded (non power of two) sizes
+    for window in [256usize, 500, 512, 1024, 2048, 3000, 4096, 8192] {
+        for freq in [1000.0_f32, 5000.0] {
+            let samples: Vec<f32> = (0..window)
+                .map(|i| (2.0 * std::f32::consts::PI * freq * i as f32 / sample_rate as f32).sin())
+                .collect();
+            for function in windows {
+                let options = AnalysisOptions {
+                    window: function,
+                    ..Default::default()
+                };
+                let p = analyze_pcm_peaks_with_options(&samples, sample_rate, 1, &options)[0];
+                assert!(
+                    p.magnitude_db.abs() < 0.5,
+                    "{:?} window={} freq={}: {} dBFS",
+                    function,
+                    window,
+                    freq,
+                    p.magnitude_db
+                );
+            }
+
+            // half-scale sine reads -6 dBFS; flat-top is accurate even without
+            // interpolation
+            let half: Vec<f32> = samples.iter().map(|s| s * 0.5).collect();
+            let options = AnalysisOptions {
+                estimator: FrequencyEstimator::Bin,
+                window: WindowFunction::FlatTop,
+                ..Default::default()
+            };
+            let p = analyze_pcm_peaks_with_options(&half, sample_rate, 1, &options)[0];
+            assert!(
+                (p.magnitude_db + 6.0206).abs() < 0.1,
+                "flat-top window={} freq={}: {} dBFS",
+                window,
+                freq,
+                p.magnitude_db
+            );
+        }
+    }
+}
+
+#[test]
+fn test_peak_picking_spacing_and_prominence() {
+    let sample_rate: usize = 44100;
+    let window: usize = 4096;
+    let two_pi = 2.0 * std::f32::consts::PI;
+
+    // two tones ~51 cents apart: a 100 cent spacing keeps only the louder one
+    let samples: Vec<f32> = (0..window)
+        .map(|i| {
+            let t = i as f32 / sample_rate as f32;
+            0.5 * (two_pi * 1000.0 * t).sin() + 0.25 * (two_pi * 1030.0 * t).sin()
+        })
+        .collect();
+    let mut options = AnalysisOptions::default();
+    options.picking.min_spacing = Some(PeakSpacing::Cents(100.0));
+    options.picking.debug = true;
+    let result = SpectralAnalyzer::new(options).analyze_detailed(&samples, sample_rate, 2);
+    assert!((result.peaks[0].freq_hz - 1000.0).abs() < 2.0);
+    assert!(
+        result
+            .peaks
+            .iter()
+            .all(|p| (p.freq_hz - 1030.0).abs() > 10.0),
+        "spaced-out tone returned: {:?}",
+        result.peaks
+    );
+    assert!(
+        result
+            .rejected
+            .iter()
+            .any(|r| r.reason == RejectReason::Spacing && (r.peak.freq_hz - 1030.0).abs() < 2.0)
+    );
+
+    // slight amplitude modulation leaves low sidebands on the carrier's skirt
+    let samples: Vec<f32> = (0..window)
+        .map(|i| {
+            let t = i as f32 / sample_rate as f32;
+            (two_pi * 1000.0 * t).sin() * (1.0 + 0.02 * (two_pi * 40.0 * t).sin())
+        })
+        .collect();
+    let near_carrier = |peaks: &[Peak]| {
+        peaks
+            .iter()
+            .filter(|p| (p.freq_hz - 1000.0).abs() < 100.0)
+            .count()
+    };
+    let plain = analyze_pcm_peaks(&samples, sample_rate, 3);
+    assert!(near_carrier(&plain) > 1, "sidebands expected: {:?}", plain);
+    let mut options = AnalysisOptions::default();
+    options.picking.min_prominence_db = Some(6.0);
+    let picked = analyze_pcm_peaks_with_options(&samples, sample_rate, 3, &options);
+    assert_eq!(near_carrier(&picked), 1, "sidebands kept: {:?}", picked);
+}
+
+#[test]
+fn test_peak_picking_noise_rejection() {
+    let sample_rate: usize = 44100;
+    let window: usize = 4096;
+    let noise = lcg_noise(window, 0.05);
+    let samples: Vec<f32> = (0..window)
+        .map(|i| {
+            let t = i as f32 / sample_rate as f32;
+            0.5 * (2.0 * std::f32::consts::PI * 1000.0 * t).sin() + noise[i]
+        })
+        .collect();
+
+    // noise-floor margin leaves only the tone
+    let mut options = AnalysisOptions::default();
+    options.picking.min_above_noise_floor_db = Some(20.0);
+    options.picking.debug = true;
+    let result = SpectralAnalyzer::new(options).analyze_detailed(&samples, sample_rate, 8);
+    assert_eq!(result.peaks.len(), 1, "peaks: {:?}", result.peaks);
+    assert!((result.peaks[0].freq_hz - 1000.0).abs() < 2.0);
+    assert!(
+        result
+            .rejected
+            .iter()
+            .all(|r| r.reason == RejectReason::NoiseFloor)
+    );
+
+    // pure noise has a flat spectrum everywhere
+    let mut options = AnalysisOptions::default();
+    options.picking.max_flatness = Some(0.2);
+    let peaks = analyze_pcm_peaks_with_options(&noise, sample_rate, 8, &options);
+    assert!(peaks.is_empty(), "tonal peaks found in noise: {:?}", peaks);
+}
+
+#[test]
+fn test_frequency_band_limits_peak_search() {
+    let sample_rate: usize = 44100;
+    let window: usize = 2048;
+    let two_pi = 2.0 * std::f32::consts::PI;
+    let samples: Vec<f32> = (0..window)
+        .map(|i| {
+            let t = i as f32 / sample_rate as f32;
+            0.8 * (two_pi * 9000.0 * t).sin() + 0.2 * (two_pi * 440.0 * t).sin()
+        })
+        .collect();
+
+    let options = AnalysisOptions {
+        max_freq_hz: Some(6000.0),
+        ..Default::default()
+    };
+    // the out-of-band tone does not take the only slot
+    let peaks = analyze_pcm_peaks_with_options(&samples, sample_rate, 1, &options);
+    assert_eq!(peaks.len(), 1);
+    assert!((peaks[0].freq_hz - 440.0).abs() < 5.0, "{:?}", peaks);
+
+    let options = AnalysisOptions {
+        min_freq_hz: Some(1000.0),
+        ..Default::default()
+    };
+    let peaks = analyze_pcm_peaks_with_options(&samples, sample_rate, 4, &options);
+    assert!(peaks.iter().all(|p| p.freq_hz >= 1000.0));
+}
+
+#[test]
+fn test_f0_estimation_groups_harmonics() {
+    let sample_rate: usize = 44100;
+    let samples = harmonic_tone(220.0, 1, 6, sample_rate, 2048);
+    let peaks = analyze_pcm_peaks(&samples, sample_rate, 8);
+
+    for method in [F0Method::Yin, F0Method::HarmonicProductSpectrum] {
+        let opts = F0Options {
+            method,
+            ..Default::default()
+        };
+        let est = estimate_f0(&samples, sample_rate, &peaks, &opts);
+        let f0 = est.f0_hz.expect("harmonic tone should be voiced");
+        assert!((f0 - 220.0).abs() < 2.0, "{method:?} estimated {f0}");
+        assert!(est.confidence >= opts.voicing_threshold);
+
+        let indices: Vec<usize> = est.harmonics.iter().map(|h| h.index).collect();
+        for h in 1..=6 {
+            assert!(indices.contains(&h), "{method:?} missing harmonic {h}");
+        }
+        assert!(indices.windows(2).all(|w| w[0] < w[1]));
+    }
+}
+
+#[test]
+fn test_f0_missing_fundamental_and_unvoiced() {
+    let sample_rate: usize = 44100;
+
+    // no energy at 220 Hz itself; the pitch is implied by the harmonic series
+    let samples = harmonic_tone(220.0, 2, 6, sample_rate, 2048);
+    let peaks = analyze_pcm_peaks(&samples, sample_rate, 8);
+    for method in [F0Method::Yin, F0Method::HarmonicProductSpectrum] {
+        let opts = F0Options {
+            method,
+            ..Default::default()
+        };
+        let f0 = estimate_f0(&samples, sample_rate, &peaks, &opts)
+            .f0_hz
+            .expect("missing-fundamental tone should be voiced");
+        assert!((f0 - 220.0).abs() < 3.0, "{method:?} estimated {f0}");
+    }
+
+    let noise = lcg_noise(2048, 0.5);
+    for method in [F0Method::Yin, F0Method::HarmonicProductSpectrum] {
+        let opts = F0Options {
+            method,
+            ..Default::default()
+        };
+        let est = estimate_f0(&noise, sample_rate, &[], &opts);
+        assert_eq!(est.f0_hz, None, "{method:?} voiced noise");
+        assert!(est.harmonics.is_empty());
+
+        // frames too short to hold a period
+        for tiny in [&[0.5f32][..], &[0.5, -0.5]] {
+            let est = estimate_f0(tiny, sample_rate, &[], &opts);
+            assert_eq!(est.f0_hz, None, "{method:?} on {} samples", tiny.len());
+        }
+    }
+}
+
+#[test]
+fn test_f0_estimator_reuse_and_min_frame_len() {
+    let sample_rate: usize = 44100;
+    let opts = F0Options {
+        method: F0Method::HarmonicProductSpectrum,
+        ..Default::default()
+    };
+    // one estimator over frames of changing length matches one-off estimates
+    let mut estimator = F0Estimator::new(opts.clone());
+    for (f0, len) in [(220.0, 2048), (330.0, 1024), (220.0, 2048), (150.0, 3000)] {
+        let samples = harmonic_tone(f0, 1, 5, sample_rate, len);
+        let peaks = analyze_pcm_peaks(&samples, sample_rate, 8);
+        assert_eq!(
+            estimator.estimate(&samples, sample_rate, &peaks),
+            estimate_f0(&samples, sample_rate, &peaks, &opts),
+            "{f0} Hz over {len} samples"
+        );
+    }
+
+    // YIN finds a low fundamental once the frame holds two periods of
+    // `min_f0_hz`, not in a short analysis window
+    let opts = F0Options::default();
+    let len = opts.min_frame_len(sample_rate);
+    assert_eq!(len, 1764);
+    let samples = harmonic_tone(60.0, 1, 5, sample_rate, len);
+    let f0 = estimate_f0(&samples, sample_rate, &[], &opts).f0_hz;
+    assert!(f0.is_some_and(|f0| (f0 - 60.0).abs() < 1.0), "{f0:?}");
+    let f0 = estimate_f0(&samples[..512], sample_rate, &[], &opts).f0_hz;
+    assert!(f0.is_none_or(|f0| (f0 - 60.0).abs() > 10.0), "{f0:?}");
+}
+
+#[test]
+fn test_masking_selection_prefers_audible_peaks() {
+    let sample_rate: usize = 44100;
+    let window: usize = 4096;
+    // the loud 1 kHz tone masks its 1.1 kHz neighbour, while the even weaker
+    // 5 kHz tone sits in an unmasked region
+    let tones = [(1000.0f32, 0.5f32), (1100.0, 0.004), (5000.0, 0.003)];
+    let samples: Vec<f32> = (0..window)
+        .map(|i| {
+            let t = i as f32 / sample_rate as f32;
+            tones
+                .iter()
+                .map(|&(f, a)| a * (2.0 * std::f32::consts::PI * f * t).sin())
+                .sum()
+        })
+        .collect();
+    let near = |peaks: &[Peak], hz: f32| peaks.iter().any(|p| (p.freq_hz - hz).abs() < 5.0);
+
+    let loudest = analyze_pcm_peaks(&samples, sample_rate, 2);
+    assert!(near(&loudest, 1000.0) && near(&loudest, 1100.0));
+
+    let opts = AnalysisOptions {
+        selection: PeakSelection::Masking,
+        picking: PeakPicking {
+            debug: true,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let analysis = SpectralAnalyzer::new(opts).analyze_detailed(&samples, sample_rate, 2);
+    assert!(near(&analysis.peaks, 1000.0), "{:?}", analysis.peaks);
+    assert!(near(&analysis.peaks, 5000.0), "{:?}", analysis.peaks);
+    assert!(
+        analysis
+            .rejected
+            .iter()
+            .any(|r| r.reason == RejectReason::Masked && (r.peak.freq_hz - 1100.0).abs() < 5.0)
+    );
+}
+
+#[test]
+fn test_residual_envelope_removes_sinusoids() {
+    let sample_rate: usize = 44100;
+    let window: usize = 2048;
+    let tone: Vec<f32> = (0..window)
+        .map(|i| 0.5 * (2.0 * std::f32::consts::PI * 1500.0 * i as f32 / sample_rate as f32).sin())
+        .collect();
+    let noise = lcg_noise(window, 0.1);
+    let mixed: Vec<f32> = tone.iter().zip(&noise).map(|(t, n)| t + n).collect();
+    let peaks = analyze_pcm_peaks(&mixed, sample_rate, 1);
+
+    let options = ResidualOptions::default();
+    let band_1k = 3; // 1000..2000 Hz
+    let mut analyzer = ResidualAnalyzer::new(options.clone());
+
+    // a pure tone leaves (almost) nothing behind
+    let tone_peaks = analyze_pcm_peaks(&tone, sample_rate, 1);
+    let residual = analyzer.analyze(&tone, sample_rate, &tone_peaks);
+    assert_eq!(residual.len(), options.band_count());
+    assert!(residual.iter().all(|&l| l < 0.005), "{residual:?}");
+
+    // with noise, the tone's band drops to the noise level once the tone is
+    // removed; uniform noise of amplitude a has RMS a / sqrt(3)
+    let kept = analyzer.analyze(&mixed, sample_rate, &[]);
+    let removed = analyzer.analyze(&mixed, sample_rate, &peaks);
+    assert!(kept[band_1k] > 0.3);
+    let noise_only = analyzer.analyze(&noise, sample_rate, &[]);
+    let ratio_db = 20.0 * (removed[band_1k] / noise_only[band_1k]).log10();
+    assert!(ratio_db.abs() < 2.0, "{ratio_db} dB");
+    let total = noise_only.iter().map(|l| l * l).sum::<f32>().sqrt();
+    let expected = 0.1 / 3f32.sqrt();
+    // the bands cover 125 Hz..16 kHz of the 22 kHz noise bandwidth
+    assert!(total > expected * 0.7 && total < expected, "{total}");
+
+    // rendering the envelope reproduces its level
+    let mut synth = NoiseSynthesizer::new(&options.band_edges_hz, sample_rate);
+    let mut out = vec![0.0f32; sample_rate];
+    synth.render(&noise_only, &mut out[..1]);
+    synth.render(&noise_only, &mut out);
+    let rms = (out.iter().map(|v| v * v).sum::<f32>() / out.len() as f32).sqrt();
+    assert!(
+        (20.0 * (rms / total).log10()).abs() < 1.5,
+        "{rms} vs {total}"
+    );
+}
+
+#[test]
+fn test_multi_resolution_resolves_bass_and_treble() {
+    let sample_rate: usize = 44100;
+    let tones = [(100.0f32, 0.5f32), (112.0, 0.4), (3000.0, 0.3)];
+    let samples: Vec<f32> = (0..8192)
+        .map(|i| {
+            let t = i as f32 / sample_rate as f32;
+            tones
+                .iter()
+                .map(|&(f, a)| a * (2.0 * std::f32::consts::PI * f * t).sin())
+                .sum()
+        })
+        .collect();
+    let near = |peaks: &[Peak], hz: f32| peaks.iter().any(|p| (p.freq_hz - hz).abs() < 3.0);
+
+    // a short window cannot separate the two bass tones
+    let short = analyze_pcm_peaks(&samples[3840..4352], sample_rate, 3);
+    assert!(!(near(&short, 100.0) && near(&short, 112.0)), "{short:?}");
+
+    let opts = AnalysisOptions {
+        resolution_bands: default_resolution_bands(),
+        ..Default::default()
+    };
+    assert_eq!(opts.frame_len(512), 8192);
+    let mut analyzer = SpectralAnalyzer::new(opts);
+    let peaks = analyzer.analyze(&samples, sample_rate, 3);
+    assert_eq!(peaks.len(), 3, "{peaks:?}");
+    for (hz, _) in tones {
+        assert!(near(&peaks, hz), "missing {hz} Hz in {peaks:?}");
+    }
+    // merged strongest first, calibrated regardless of band window
+    assert!(peaks.windows(2).all(|w| w[0].magnitude >= w[1].magnitude));
+    assert!((peaks[0].magnitude - 0.5).abs() < 0.05);
+    assert!((peaks[2].magnitude - 0.3).abs() < 0.05);
+}
+
+#[test]
+fn test_onset_detection_finds_attacks() {
+    let sample_rate: usize = 44100;
+    let mut samples = lcg_noise(sample_rate, 0.001);
+    for (i, v) in samples.iter_mut().enumerate() {
+        let t = i as f32 / sample_rate as f32;
+        if i >= 10000 {
+            *v += 0.5 * (2.0 * std::f32::consts::PI * 440.0 * t).sin();
+        }
+        if i >= 30000 {
+            *v += 0.5 * (2.0 * std::f32::consts::PI * 1234.0 * t).sin();
+        }
+    }
+
+    for method in [OnsetMethod::SpectralFlux, OnsetMethod::ComplexDomain] {
+        let options = OnsetOptions {
+            method,
+            ..Default::default()
+        };
+        let onsets = detect_onsets(&samples, sample_rate, &options);
+        let positions: Vec<usize> = onsets.iter().map(|o| o.sample).collect();
+        assert_eq!(positions.len(), 2, "{method:?}: {positions:?}");
+        assert!(positions[0].abs_diff(10000) <= 2 * options.hop_size);
+        assert!(positions[1].abs_diff(30000) <= 2 * options.hop_size);
+        assert!((onsets[0].time_s - 10000.0 / sample_rate as f32).abs() < 0.01);
+    }
+
+    // a steady tone has no onsets past its start
+    let steady = synthesize_sines(
+        &[Peak {
+            freq_hz: 440.0,
+            magnitude: 1.0,
+            magnitude_db: 0.0,
+            bin: 0,
+        }],
+        sample_rate,
+        sample_rate,
+    );
+    let onsets = detect_onsets(&steady, sample_rate, &OnsetOptions::default());
+    assert!(onsets.iter().all(|o| o.sample < 1024), "{onsets:?}");
+}
+
+#[test]
+fn test_oscillator_bank_keeps_phase_across_blocks() {
+    let sample_rate: usize = 44100;
+    let block = 512usize;
+    let tone = |freq_hz: f32, magnitude: f32| Peak {
+        freq_hz,
+        magnitude,
+        magnitude_db: 20.0 * magnitude.log10(),
+        bin: 0,
+    };
+    let reference = |i: usize| {
+        0.5 * (2.0 * std::f32::consts::PI * 440.0 * (i + 1) as f32 / sample_rate as f32).sin()
+    };
+
+    for overlap_samples in [0, 128] {
+        let mut bank = OscillatorBank::new(
+            OscillatorOptions {
+                overlap_samples,
+                ..Default::default()
+            },
+            sample_rate,
+        );
+        let mut out = vec![0.0f32; 12 * block];
+        for chunk in out[..8 * block].chunks_mut(block) {
+            bank.render(&[tone(440.0, 0.5)], chunk);
+        }
+        // once faded in, a held tone is one unbroken sine
+        for (i, &v) in out.iter().enumerate().take(8 * block).skip(block) {
+            assert!((v - reference(i)).abs() < 2e-3, "{overlap_samples}: {i}");
+        }
+
+        // glides and releases stay free of jumps
+        bank.render(&[tone(445.0, 0.25)], &mut out[8 * block..9 * block]);
+        bank.render(
+            &[tone(445.0, 0.25), tone(880.0, 0.25)],
+            &mut out[9 * block..10 * block],
+        );
+        bank.render(&[], &mut out[10 * block..11 * block]);
+        bank.render(&[], &mut out[11 * block..]);
+        assert_eq!(bank.active_count(), 0);
+        let max_step = out
+            .windows(2)
+            .map(|w| (w[1] - w[0]).abs())
+            .fold(0.0f32, f32::max);
+        // a 0.5 sine at 440 Hz moves by at most 0.031 per sample
+        assert!(max_step < 0.1, "{overlap_samples}: {max_step}");
+        assert!(out[11 * block..].iter().all(|v| v.abs() < 1e-6));
+    }
+}
+
+#[test]
+fn test_stereo_pan_estimation_and_merge() {
+    for pan in [-1.0f32, -0.4, 0.0, 0.7, 1.0] {
+        let (l, r) = pan_to_levels(0.5, pan);
+        assert!(((l + r) / 2.0 - 0.5).abs() < 1e-5);
+        assert!((pan_from_levels(l, r) - pan).abs() < 1e-4, "{pan}");
+    }
+
+    // 440 Hz mostly in the left channel, 1000 Hz only in the right
+    let sample_rate = 44100usize;
+    let len = 4096usize;
+    let tone = |freq: f32, amp: f32| -> Vec<f32> {
+        (0..len)
+            .map(|i| {
+                amp * (2.0 * std::f32::consts::PI * freq * i as f32 / sample_rate as f32).sin()
+            })
+            .collect()
+    };
+    let (a, b) = (tone(440.0, 0.8), tone(1000.0, 0.5));
+    let left: Vec<f32> = a.clone();
+    let right: Vec<f32> = a.iter().zip(&b).map(|(x, y)| 0.25 * x + y).collect();
+    let peaks = [440.0f32, 1000.0].map(|freq_hz| Peak {
+        freq_hz,
+        magnitude: 0.0,
+        magnitude_db: -200.0,
+        bin: 0,
+    });
+    let pans = estimate_pan(&left, &right, sample_rate, &peaks, WindowFunction::Hann);
+    assert!(
+        (pans[0] - pan_from_levels(1.0, 0.25)).abs() < 0.02,
+        "{pans:?}"
+    );
+    assert!(pans[1] > 0.98, "{pans:?}");
+
+    // the shared partial merges, the other one keeps its side
+    let left_peaks = analyze_pcm_peaks(&left, sample_rate, 4);
+    let right_peaks = analyze_pcm_peaks(&right, sample_rate, 4);
+    let (merged, merged_pans) = merge_channel_peaks(&left_peaks, &right_peaks, 25.0);
+    let near = |f: f32| {
+        merged
+            .iter()
+            .position(|p| (p.freq_hz - f).abs() < 10.0)
+            .expect("merged peak")
+    };
+    assert!(near(440.0) < 2 && near(1000.0) < 2, "{merged:?}");
+    assert!((merged[near(440.0)].magnitude - 0.5).abs() < 0.05);
+    assert!(merged_pans[near(440.0)] < -0.8);
+    assert_eq!(merged_pans[near(1000.0)], 1.0);
+
+    let (l, r) = interleaved_to_stereo(&[1i16, 2, 3, 4], 2);
+    assert_eq!(l.len(), 2);
+    assert!(l[1] > l[0] && r[0] > l[0] && r[1] > l[1]);
+    let (ml, mr) = interleaved_to_stereo(&[0.5f32, -0.5], 1);
+    assert_eq!(ml, mr);
+}
+
+#[test]
+fn test_formants_of_a_synthetic_vowel() {
+    let sample_rate = 44100usize;
+    let targets = [
+        (700.0f32, 80.0f32),
+        (1220.0, 90.0),
+        (2600.0, 120.0),
+        (3500.0, 150.0),
+    ];
+    let vowel = synthetic_vowel(130.0, &targets, sample_rate, sample_rate / 2);
+
+    let frame = &vowel[8192..8192 + 2048];
+    let formants = estimate_formants(frame, sample_rate, &FormantOptions::default());
+    assert_eq!(formants.len(), 4, "{formants:?}");
+    for (formant, &(freq, _)) in formants.iter().zip(&targets) {
+        assert!((formant.freq_hz / freq - 1.0).abs() < 0.05, "{formants:?}");
+        assert!(formant.bandwidth_hz > 0.0 && formant.bandwidth_hz < 400.0);
+    }
+    // the envelope falls with frequency, and so do the formant levels
+    assert!(formants.windows(2).all(|w| w[0].magnitude > w[1].magnitude));
+
+    // tracked slots stay on their formant from frame to frame, and an
+    // unvoiced (silent) frame keeps them in place at zero level
+    let mut analyzer = FormantAnalyzer::new(FormantOptions::default());
+    let mut frames: Vec<Vec<Formant>> = vowel
+        .chunks_exact(2048)
+        .map(|chunk| analyzer.analyze(chunk, sample_rate))
+        .collect();
+    frames.push(analyzer.analyze(&[0.0; 2048], sample_rate));
+    for frame in &frames[..frames.len() - 1] {
+        for (formant, &(freq, _)) in frame.iter().zip(&targets) {
+            assert!((formant.freq_hz / freq - 1.0).abs() < 0.05, "{frame:?}");
+        }
+    }
+    let silent = frames.last().unwrap();
+    assert!(silent.iter().all(|f| f.magnitude == 0.0));
+    assert!((silent[0].freq_hz / 700.0 - 1.0).abs() < 0.05);
+
+    // a few samples at 96 kHz leave nothing at the LPC rate
+    for len in [4, 8, 16] {
+        let short = synthetic_vowel(130.0, &targets, 96000, len);
+        assert!(estimate_formants(&short, 96000, &FormantOptions::default()).is_empty());
+        let frame = analyzer.analyze(&short, 96000);
+        assert!(frame.iter().all(|f| f.magnitude == 0.0), "{frame:?}");
+    }
+}
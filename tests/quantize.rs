@@ -0,0 +1,70 @@
+use nanonanoda::quantize::{Key, NoteQuantizer, QuantizeOptions, Scale, midi_note, note_freq};
+
+#[test]
+fn test_note_numbers_round_trip() {
+    assert!((midi_note(440.0) - 69.0).abs() < 1e-4);
+    assert!((midi_note(261.6256) - 60.0).abs() < 1e-3);
+    for note in [21u8, 60, 69, 108] {
+        assert!(
+            (midi_note(note_freq(note)) - note as f32).abs() < 1e-3,
+            "{note}"
+        );
+    }
+}
+
+#[test]
+fn test_key_snaps_to_scale_notes() {
+    let c_major = Key {
+        tonic: 0,
+        scale: Scale::Major,
+    };
+    // C#4 is not in C major: C4 or D4 are equally near, a nudge decides
+    assert_eq!(c_major.nearest(61.1), 62);
+    assert_eq!(c_major.nearest(60.9), 60);
+    // E4 and F4 are both in the key
+    assert_eq!(c_major.nearest(64.6), 65);
+
+    let a_minor_pentatonic = Key {
+        tonic: 9,
+        scale: Scale::MinorPentatonic,
+    };
+    // A C D E G: between A4 and C5 the midpoint is 70.5
+    assert_eq!(a_minor_pentatonic.nearest(70.4), 69);
+    assert_eq!(a_minor_pentatonic.nearest(70.6), 72);
+    assert!(a_minor_pentatonic.contains(57));
+    assert!(!a_minor_pentatonic.contains(59));
+
+    // chromatic rounds
+    assert_eq!(Key::default().nearest(69.49), 69);
+}
+
+#[test]
+fn test_hysteresis_holds_notes_between_semitones() {
+    // a pitch wobbling around the midpoint of A4 and A#4
+    let wobble: Vec<f32> = (0..20)
+        .map(|i| midi_note(440.0) + 0.5 + if i % 2 == 0 { 0.1 } else { -0.1 })
+        .map(|pitch| note_freq(69) * 2f32.powf((pitch - 69.0) / 12.0))
+        .collect();
+    let run = |hysteresis_cents: f32| {
+        let mut quantizer = NoteQuantizer::new(QuantizeOptions {
+            hysteresis_cents,
+            ..Default::default()
+        });
+        wobble
+            .iter()
+            .map(|&f| quantizer.quantize(&[f])[0].expect("note"))
+            .collect::<Vec<u8>>()
+    };
+    let held = run(30.0);
+    assert!(held.iter().all(|&n| n == held[0]), "{held:?}");
+    let flipping = run(0.0);
+    assert_eq!(&flipping[..4], &[70, 69, 70, 69]);
+
+    // a clear move still changes the note, and silent slots give None
+    let mut quantizer = NoteQuantizer::new(QuantizeOptions::default());
+    assert_eq!(quantizer.quantize(&[440.0, 0.0]), vec![Some(69), None]);
+    assert_eq!(
+        quantizer.quantize(&[note_freq(71), 0.0]),
+        vec![Some(71), None]
+    );
+}
@@ -0,0 +1,94 @@
+use nanonanoda::pcm::{Peak, synthesize_sines};
+use nanonanoda::refine::{LevelRefiner, LevelScale, RefineOptions};
+
+fn voice(freq_hz: f32, magnitude: f32) -> Peak {
+    Peak {
+        freq_hz,
+        magnitude,
+        magnitude_db: 20.0 * magnitude.log10(),
+        bin: 0,
+    }
+}
+
+fn db(magnitude: f32) -> f32 {
+    20.0 * magnitude.log10()
+}
+
+#[test]
+fn test_refinement_matches_input_levels() {
+    let sample_rate = 44100usize;
+    let frame = synthesize_sines(&[voice(440.0, 0.5), voice(880.0, 0.1)], sample_rate, 2048);
+    let mut refiner = LevelRefiner::new(RefineOptions::default());
+    let voices = [voice(440.0, 0.2), voice(880.0, 0.3)];
+    let refined = refiner.refine(&frame, sample_rate, &voices, &LevelScale::default());
+    assert!(refined.iterations <= RefineOptions::default().iterations);
+    assert!(
+        refined.distance < refined.initial_distance * 0.05,
+        "{refined:?}"
+    );
+    assert!(
+        (db(refined.magnitudes[0]) - db(0.5)).abs() < 1.0,
+        "{refined:?}"
+    );
+    assert!(
+        (db(refined.magnitudes[1]) - db(0.1)).abs() < 1.0,
+        "{refined:?}"
+    );
+
+    let played: Vec<Peak> = refined
+        .magnitudes
+        .iter()
+        .zip(&voices)
+        .map(|(&magnitude, v)| Peak { magnitude, ..*v })
+        .collect();
+    let distance = refiner.distance(&frame, sample_rate, &played);
+    assert!((distance - refined.distance).abs() < 1e-4);
+    assert!((refiner.distance(&frame, sample_rate, &[]) - 1.0).abs() < 1e-4);
+}
+
+#[test]
+fn test_refinement_resolves_overlapping_voices() {
+    let sample_rate = 44100usize;
+    // one partial at 1000 Hz, claimed by two voices a few hertz apart that
+    // each measured its full level
+    let frame = synthesize_sines(&[voice(1000.0, 0.4)], sample_rate, 2048);
+    let voices = [voice(1000.0, 0.4), voice(1004.0, 0.4)];
+    let mut refiner = LevelRefiner::new(RefineOptions::default());
+    let refined = refiner.refine(&frame, sample_rate, &voices, &LevelScale::default());
+    assert!(
+        refined.distance < refined.initial_distance * 0.1,
+        "{refined:?}"
+    );
+    let total: f32 = refined.magnitudes.iter().sum();
+    assert!((db(total) - db(0.4)).abs() < 1.0, "{refined:?}");
+}
+
+#[test]
+fn test_refinement_stays_on_the_level_scale() {
+    let sample_rate = 44100usize;
+    let frame = synthesize_sines(&[voice(300.0, 0.3)], sample_rate, 2048);
+    // a chip scale of 1.5 dB steps muting at -60 dBFS; the 2500 Hz voice
+    // has nothing to play
+    let scale = LevelScale {
+        step_db: Some(1.5),
+        floor_db: -60.0,
+    };
+    let voices = [voice(300.0, 0.05), voice(2500.0, 0.2)];
+    let options = RefineOptions {
+        iterations: 3,
+        ..Default::default()
+    };
+    let mut refiner = LevelRefiner::new(options);
+    let refined = refiner.refine(&frame, sample_rate, &voices, &scale);
+    assert!(refined.iterations <= 3);
+    assert_eq!(refined.magnitudes[1], 0.0, "{refined:?}");
+    let steps = db(refined.magnitudes[0]) / 1.5;
+    assert!((steps - steps.round()).abs() < 1e-3, "{refined:?}");
+    assert!(
+        (db(refined.magnitudes[0]) - db(0.3)).abs() <= 1.0,
+        "{refined:?}"
+    );
+
+    assert_eq!(scale.quantize(0.0005), 0.0);
+    assert_eq!(scale.quantize(2.0), 1.0);
+}
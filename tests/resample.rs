@@ -0,0 +1,64 @@
+use nanonanoda::pcm::analyze_pcm_peaks;
+use nanonanoda::resample::{ResampleOptions, Resampler, resample};
+
+// Computed in f64: f32 phase error at long positions is itself a noise
+// floor around -60 dB.
+fn sine(freq_hz: f64, amp: f32, sample_rate: usize, len: usize) -> Vec<f32> {
+    (0..len)
+        .map(|i| {
+            let phase = 2.0 * std::f64::consts::PI * freq_hz * i as f64 / sample_rate as f64;
+            amp * phase.sin() as f32
+        })
+        .collect()
+}
+
+fn rms(samples: &[f32]) -> f32 {
+    (samples.iter().map(|x| x * x).sum::<f32>() / samples.len() as f32).sqrt()
+}
+
+#[test]
+fn test_resample_keeps_pitch_level_and_duration() {
+    let options = ResampleOptions::default();
+    for (from, to) in [(48000usize, 44100usize), (22050, 44100), (44100, 8000)] {
+        let input = sine(1000.0, 0.5, from, from / 2);
+        let out = resample(&input, from, to, &options);
+        assert_eq!(out.len(), to / 2, "{from} -> {to}");
+
+        // away from the edges, the output is the same sine at the new rate
+        let body = &out[256..out.len() - 256];
+        let peaks = analyze_pcm_peaks(&body[..2048], to, 1);
+        assert!((peaks[0].freq_hz - 1000.0).abs() < 2.0, "{peaks:?}");
+        let expected = sine(1000.0, 0.5, to, to / 2);
+        let error = rms(&body
+            .iter()
+            .zip(&expected[256..])
+            .map(|(a, b)| a - b)
+            .collect::<Vec<f32>>());
+        assert!(error < 1e-3, "{from} -> {to}: {error}");
+    }
+
+    // equal rates pass samples through
+    let input = sine(440.0, 0.3, 44100, 1000);
+    assert_eq!(resample(&input, 44100, 44100, &options), input);
+    assert_eq!(
+        Resampler::new(44100, 44100, &options).process(&input),
+        input
+    );
+}
+
+#[test]
+fn test_resample_rejects_aliases() {
+    // 15 kHz cannot exist at 22.05 kHz; it must not fold back to 7.05 kHz
+    let input = sine(15000.0, 0.5, 44100, 44100 / 2);
+    let out = resample(&input, 44100, 22050, &ResampleOptions::default());
+    let body = &out[256..out.len() - 256];
+    let level_db = 20.0 * (rms(body) / rms(&input)).log10();
+    assert!(level_db < -80.0, "{level_db}");
+
+    // the passband is flat up to the rolloff
+    let input = sine(9000.0, 0.5, 44100, 44100 / 2);
+    let out = resample(&input, 44100, 22050, &ResampleOptions::default());
+    let body = &out[256..out.len() - 256];
+    let level_db = 20.0 * (rms(body) / rms(&input)).log10();
+    assert!(level_db.abs() < 0.05, "{level_db}");
+}
@@ -1,9 +1,25 @@
-use nanonanoda::pcm::{Peak, analyze_pcm_peaks, synthesize_sines};
+mod common;
+
+use common::synthetic_vowel;
+use nanonanoda::assign::AssignmentMode;
+use nanonanoda::loudness::{GainOptions, LoudnessTarget};
+use nanonanoda::pcm::{
+    F0Options, FormantOptions, OnsetOptions, Peak, ResidualOptions, StereoAnalysis, StereoOptions,
+    analyze_pcm_peaks, default_resolution_bands, synthesize_sines,
+};
+use nanonanoda::quantize::QuantizeOptions;
+use nanonanoda::refine::RefineOptions;
 use nanonanoda::resynth::{
-    mag_to_tl, map_samples_to_fnums, process_samples_resynth_multi, synth_from_spectral_features,
+    InputChannels, ResynthOptions, SpeechOptions, chip_frequency_range, chip_frequency_range_at,
+    default_master_clock, mag_to_tl, map_samples_to_fnums, process_samples_resynth_multi,
+    process_samples_resynth_multi_to_vgm, process_stereo_resynth_multi,
+    process_stereo_resynth_multi_to_vgm, resynth_onsets, synth_from_spectral_features,
+    tl_level_scale,
 };
+use nanonanoda::voice::StealPolicy;
 use soundlog::chip::Chip;
-use soundlog::chip::fnumber::{ChipTypeSpec, Opl3Spec, OpnSpec, generate_12edo_fnum_table};
+use soundlog::chip::fnumber::{ChipTypeSpec, Opl3Spec, OpnSpec};
+use soundlog::vgm::command::VgmCommand;
 
 fn generate_test_sine(freq: f32, sample_rate: usize, sample_count: usize, mag: f32) -> Vec<f32> {
     let peak = Peak {
@@ -33,6 +49,7 @@ fn test_process_samples_resynth_multi_44100() {
         window_size,
         sample_rate,
         &chip_instances,
+        &ResynthOptions::default(),
     )
     .expect("process_samples_resynth_multi failed");
 
@@ -48,14 +65,10 @@ fn test_map_samples_to_fnums_single_tone() {
 
     let buf = generate_test_sine(freq, sample_rate, window, 1.0);
 
-    let fnum_table_ymf262 = generate_12edo_fnum_table::<Opl3Spec>(Opl3Spec::default_master_clock())
-        .expect("table gen 262");
-    let fnum_table_ym2203 = generate_12edo_fnum_table::<OpnSpec>(OpnSpec::default_master_clock())
-        .expect("table gen 2203");
-
     // YMF262
-    let features_262 = map_samples_to_fnums::<Opl3Spec>(&buf, sample_rate, 4, &fnum_table_ymf262)
-        .expect("mapping failed 262");
+    let features_262 =
+        map_samples_to_fnums::<Opl3Spec>(&buf, sample_rate, 4, Opl3Spec::default_master_clock())
+            .expect("mapping failed 262");
     assert!(!features_262.is_empty(), "no features returned for 262");
     let f = &features_262[0];
     assert!(f.magnitude > 0.0, "magnitude is zero or negative");
@@ -68,8 +81,9 @@ fn test_map_samples_to_fnums_single_tone() {
     );
 
     // YM2203
-    let features_2203 = map_samples_to_fnums::<OpnSpec>(&buf, sample_rate, 4, &fnum_table_ym2203)
-        .expect("mapping failed 2203");
+    let features_2203 =
+        map_samples_to_fnums::<OpnSpec>(&buf, sample_rate, 4, OpnSpec::default_master_clock())
+            .expect("mapping failed 2203");
     assert!(!features_2203.is_empty(), "no features returned for 2203");
 }
 
@@ -81,14 +95,10 @@ fn test_synth_from_spectral_features_roundtrip() {
 
     let src = generate_test_sine(freq, sample_rate, window, 1.0);
 
-    let fnum_table_ymf262 = generate_12edo_fnum_table::<Opl3Spec>(Opl3Spec::default_master_clock())
-        .expect("table gen 262");
-    let fnum_table_ym2203 = generate_12edo_fnum_table::<OpnSpec>(OpnSpec::default_master_clock())
-        .expect("table gen 2203");
-
     // YMF262 roundtrip
-    let features = map_samples_to_fnums::<Opl3Spec>(&src, sample_rate, 4, &fnum_table_ymf262)
-        .expect("mapping failed 262");
+    let features =
+        map_samples_to_fnums::<Opl3Spec>(&src, sample_rate, 4, Opl3Spec::default_master_clock())
+            .expect("mapping failed 262");
     assert!(!features.is_empty());
     let synth = synth_from_spectral_features(&features, sample_rate, window).expect("synth failed");
     assert_eq!(synth.len(), window);
@@ -105,8 +115,9 @@ fn test_synth_from_spectral_features_roundtrip() {
     );
 
     // YM2203 roundtrip
-    let features = map_samples_to_fnums::<OpnSpec>(&src, sample_rate, 4, &fnum_table_ym2203)
-        .expect("mapping failed 2203");
+    let features =
+        map_samples_to_fnums::<OpnSpec>(&src, sample_rate, 4, OpnSpec::default_master_clock())
+            .expect("mapping failed 2203");
     assert!(!features.is_empty());
     let synth = synth_from_spectral_features(&features, sample_rate, window).expect("synth failed");
     assert_eq!(synth.len(), window);
@@ -150,14 +161,10 @@ fn test_multi_tone_varied_magnitudes() {
 
     let src = synthesize_sines(&peaks, sample_rate, window);
 
-    let fnum_table_ymf262 = generate_12edo_fnum_table::<Opl3Spec>(Opl3Spec::default_master_clock())
-        .expect("table gen 262");
-    let fnum_table_ym2203 = generate_12edo_fnum_table::<OpnSpec>(OpnSpec::default_master_clock())
-        .expect("table gen 2203");
-
     // YMF262
-    let features = map_samples_to_fnums::<Opl3Spec>(&src, sample_rate, 6, &fnum_table_ymf262)
-        .expect("mapping failed 262");
+    let features =
+        map_samples_to_fnums::<Opl3Spec>(&src, sample_rate, 6, Opl3Spec::default_master_clock())
+            .expect("mapping failed 262");
     assert!(
         features.len() >= 3,
         "expected at least 3 features, got {}",
@@ -178,8 +185,9 @@ fn test_multi_tone_varied_magnitudes() {
     }
 
     // YM2203
-    let features = map_samples_to_fnums::<OpnSpec>(&src, sample_rate, 6, &fnum_table_ym2203)
-        .expect("mapping failed 2203");
+    let features =
+        map_samples_to_fnums::<OpnSpec>(&src, sample_rate, 6, OpnSpec::default_master_clock())
+            .expect("mapping failed 2203");
     assert!(
         features.len() >= 3,
         "expected at least 3 features, got {}",
@@ -219,3 +227,857 @@ fn test_mag_to_tl_mapping() {
     let tl_mid = mag_to_tl(mag_neg30, max_tl);
     assert_eq!(tl_mid, 0x32);
 }
+
+#[test]
+fn test_hop_size_controls_output_timing() {
+    let input_rate = 48000usize;
+    let samples = generate_test_sine(440.0, input_rate, input_rate, 1.0);
+    let chip_instances = vec![(Chip::Ymf262, 4usize), (Chip::Ym2203, 3usize)];
+    let options = ResynthOptions {
+        hop_size: Some(256),
+        ..Default::default()
+    };
+
+    // WAV: output covers the input duration exactly, independent of window
+    let out =
+        process_samples_resynth_multi(&samples, input_rate, 2048, 44100, &chip_instances, &options)
+            .expect("process_samples_resynth_multi failed");
+    assert_eq!(out.len(), 44100);
+
+    // VGM: one wait per hop, summing to the input duration at 44.1 kHz
+    let doc = process_samples_resynth_multi_to_vgm(
+        &samples,
+        input_rate,
+        2048,
+        0x16,
+        &chip_instances,
+        &options,
+    )
+    .expect("process_samples_resynth_multi_to_vgm failed");
+    let waits: Vec<u16> = doc
+        .commands
+        .iter()
+        .filter_map(|c| match c {
+            VgmCommand::WaitSamples(w) => Some(w.0),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(waits.len(), input_rate.div_ceil(256));
+    assert_eq!(waits.iter().map(|&w| w as usize).sum::<usize>(), 44100);
+}
+
+#[test]
+fn test_chip_frequency_range_limits_analysis() {
+    for chip in [Chip::Ymf262, Chip::Ym2203] {
+        let (lo, hi) = chip_frequency_range(&chip).expect("supported chip");
+        assert!(lo > 10.0 && lo < 55.0, "{:?} low bound {}", chip, lo);
+        assert!(hi > 5000.0 && hi < 8000.0, "{:?} high bound {}", chip, hi);
+    }
+
+    // a loud tone above every chip's range must not steal the single voice
+    let sample_rate = 44100usize;
+    let window = 2048usize;
+    let high = generate_test_sine(15000.0, sample_rate, window, 1.0);
+    let low = generate_test_sine(440.0, sample_rate, window, 1.0);
+    let samples: Vec<f32> = high
+        .iter()
+        .zip(low.iter())
+        .map(|(h, l)| h + 0.2 * l)
+        .collect();
+    let out = process_samples_resynth_multi(
+        &samples,
+        sample_rate,
+        window,
+        sample_rate,
+        &[(Chip::Ymf262, 1usize)],
+        &ResynthOptions::default(),
+    )
+    .expect("process_samples_resynth_multi failed");
+    let peaks = analyze_pcm_peaks(&out, sample_rate, 1);
+    assert!((peaks[0].freq_hz - 440.0).abs() < 5.0, "{:?}", peaks);
+}
+
+#[test]
+fn test_residual_noise_rendering() {
+    let sample_rate = 44100usize;
+    // tone for the first half, breath-like noise for the second half
+    let mut samples = generate_test_sine(440.0, sample_rate, sample_rate / 2, 0.2);
+    let mut state: u32 = 7;
+    samples.extend((0..sample_rate / 2).map(|_| {
+        state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+        ((state >> 8) as f32 / (1u32 << 24) as f32 * 2.0 - 1.0) * 0.3
+    }));
+    // unity gain, so both renderings compare as rendered
+    let gain = GainOptions {
+        target: LoudnessTarget::Fixed { gain_db: 0.0 },
+        limiter: None,
+    };
+    let options = ResynthOptions {
+        residual: Some(ResidualOptions::default()),
+        gain: gain.clone(),
+        ..Default::default()
+    };
+
+    // WAV: the noisy half is rendered louder than without the residual
+    let chips = vec![(Chip::Ym2203, 3usize)];
+    let rms = |v: &[f32]| (v.iter().map(|x| x * x).sum::<f32>() / v.len() as f32).sqrt();
+    let with =
+        process_samples_resynth_multi(&samples, sample_rate, 1024, sample_rate, &chips, &options)
+            .expect("resynth with residual");
+    let without = process_samples_resynth_multi(
+        &samples,
+        sample_rate,
+        1024,
+        sample_rate,
+        &chips,
+        &ResynthOptions {
+            gain,
+            ..Default::default()
+        },
+    )
+    .expect("resynth without residual");
+    assert_eq!(with.len(), without.len());
+    let tail = sample_rate / 2 + 2048..with.len();
+    assert!(rms(&with[tail.clone()]) > rms(&without[tail]));
+
+    // VGM: the SSG noise level follows the residual, from quiet to loud
+    let doc =
+        process_samples_resynth_multi_to_vgm(&samples, sample_rate, 1024, 0x10, &chips, &options)
+            .expect("vgm with residual");
+    let ssg_levels: Vec<u8> = doc
+        .commands
+        .iter()
+        .filter_map(|c| match c {
+            VgmCommand::Ym2203Write(_, w) if w.register == 0x0A => Some(w.value),
+            _ => None,
+        })
+        .collect();
+    assert!(doc.commands.iter().any(|c| matches!(
+        c,
+        VgmCommand::Ym2203Write(_, w) if w.register == 0x07 && w.value == 0x1F
+    )));
+    assert_eq!(ssg_levels.first(), Some(&0), "{ssg_levels:?}");
+    assert!(ssg_levels.iter().any(|&l| l >= 8), "{ssg_levels:?}");
+
+    // chips without a noise source ignore the residual
+    let doc = process_samples_resynth_multi_to_vgm(
+        &samples,
+        sample_rate,
+        1024,
+        0x10,
+        &[(Chip::Ymf262, 4usize)],
+        &options,
+    )
+    .expect("vgm on OPL3 with residual");
+    assert!(
+        doc.commands
+            .iter()
+            .all(|c| !matches!(c, VgmCommand::Ym2203Write(..)))
+    );
+}
+
+#[test]
+fn test_multi_resolution_resynth_keeps_timing() {
+    let sample_rate = 44100usize;
+    let samples = generate_test_sine(110.0, sample_rate, sample_rate / 2, 1.0);
+    let mut options = ResynthOptions::default();
+    options.analysis.resolution_bands = default_resolution_bands();
+
+    let chips = vec![(Chip::Ym2203, 3usize)];
+    let out =
+        process_samples_resynth_multi(&samples, sample_rate, 512, sample_rate, &chips, &options)
+            .expect("multi-resolution resynth");
+    assert_eq!(out.len(), samples.len());
+
+    let doc =
+        process_samples_resynth_multi_to_vgm(&samples, sample_rate, 512, 0x10, &chips, &options)
+            .expect("multi-resolution vgm");
+    let waits: usize = doc
+        .commands
+        .iter()
+        .filter_map(|c| match c {
+            VgmCommand::WaitSamples(w) => Some(w.0 as usize),
+            _ => None,
+        })
+        .sum();
+    assert_eq!(waits, samples.len());
+}
+
+#[test]
+fn test_onset_segmentation_aligns_key_on() {
+    let sample_rate = 44100usize;
+    let attack = 10496usize; // halfway through a 512-sample hop
+    let tone = generate_test_sine(440.0, sample_rate, sample_rate / 2, 1.0);
+    let samples: Vec<f32> = (0..sample_rate / 2)
+        .map(|i| if i >= attack { tone[i] } else { 0.0 })
+        .collect();
+    let chips = vec![(Chip::Ym2203, 3usize)];
+
+    // output position (in samples) of the first key-on
+    let first_key_on = |options: &ResynthOptions| {
+        let doc =
+            process_samples_resynth_multi_to_vgm(&samples, sample_rate, 512, 0x10, &chips, options)
+                .expect("vgm");
+        let mut pos = 0usize;
+        let mut first = None;
+        for cmd in &doc.commands {
+            match cmd {
+                VgmCommand::WaitSamples(w) => pos += w.0 as usize,
+                VgmCommand::Ym2203Write(_, w) if w.register == 0x28 && first.is_none() => {
+                    first = Some(pos)
+                }
+                _ => {}
+            }
+        }
+        assert_eq!(pos, samples.len());
+        first.expect("key-on")
+    };
+
+    let regular = first_key_on(&ResynthOptions::default());
+    assert_eq!(regular % 512, 0);
+    let segmented = first_key_on(&ResynthOptions {
+        onsets: Some(OnsetOptions::default()),
+        ..Default::default()
+    });
+    assert!(
+        segmented.abs_diff(attack) < regular.abs_diff(attack).min(256),
+        "segmented {segmented}, regular {regular}"
+    );
+
+    let out = process_samples_resynth_multi(
+        &samples,
+        sample_rate,
+        512,
+        sample_rate,
+        &chips,
+        &ResynthOptions {
+            onsets: Some(OnsetOptions::default()),
+            ..Default::default()
+        },
+    )
+    .expect("wav with onsets");
+    assert_eq!(out.len(), samples.len());
+}
+
+#[test]
+fn test_resynth_onsets_follow_analysis_rate() {
+    let sample_rate = 44100usize;
+    let attack = sample_rate / 4;
+    let tone = generate_test_sine(440.0, sample_rate, sample_rate / 2, 1.0);
+    let samples: Vec<f32> = (0..sample_rate / 2)
+        .map(|i| if i >= attack { tone[i] } else { 0.0 })
+        .collect();
+    let input = InputChannels::Mono(&samples);
+    assert!(resynth_onsets(input, sample_rate, &ResynthOptions::default()).is_empty());
+
+    // the onsets are those of the resampled input the hops are split on
+    let options = ResynthOptions {
+        onsets: Some(OnsetOptions::default()),
+        analysis_sample_rate: Some(22050),
+        ..Default::default()
+    };
+    let onsets = resynth_onsets(input, sample_rate, &options);
+    assert_eq!(onsets.len(), 1, "{onsets:?}");
+    assert!(onsets[0].sample.abs_diff(attack / 2) < 512, "{onsets:?}");
+    assert!((onsets[0].time_s - 0.25).abs() < 0.02, "{onsets:?}");
+}
+
+#[test]
+fn test_resynth_preview_is_continuous_across_hops() {
+    let sample_rate = 44100usize;
+    let samples: Vec<f32> = (0..sample_rate / 2)
+        .map(|i| 0.5 * (2.0 * std::f32::consts::PI * 440.0 * i as f32 / sample_rate as f32).sin())
+        .collect();
+    let chips = vec![(Chip::Ym2203, 3usize)];
+    let max_step = |v: &[f32]| {
+        v.windows(2)
+            .map(|w| (w[1] - w[0]).abs())
+            .fold(0.0f32, f32::max)
+    };
+
+    for overlap_samples in [0, 64] {
+        let mut options = ResynthOptions::default();
+        options.synthesis.overlap_samples = overlap_samples;
+        let out = process_samples_resynth_multi(
+            &samples,
+            sample_rate,
+            512,
+            sample_rate,
+            &chips,
+            &options,
+        )
+        .expect("resynth");
+        assert_eq!(out.len(), samples.len());
+        let held = &out[1024..out.len() - 1024];
+        let peak = held.iter().fold(0.0f32, |m, v| m.max(v.abs()));
+        assert!(peak > 0.05, "{peak}");
+        // no steps beyond what the sine itself makes between two samples
+        let slope = peak * 2.0 * std::f32::consts::PI * 440.0 / sample_rate as f32;
+        assert!(
+            max_step(held) < slope * 1.2,
+            "{overlap_samples}: {} vs {slope}",
+            max_step(held)
+        );
+    }
+}
+
+#[test]
+fn test_stereo_input_places_voices() {
+    let sample_rate = 44100usize;
+    let len = sample_rate / 2;
+    // 440 Hz hard left, 1000 Hz hard right
+    let left = generate_test_sine(440.0, sample_rate, len, 0.4);
+    let right = generate_test_sine(1000.0, sample_rate, len, 0.4);
+    let input = InputChannels::Stereo {
+        left: &left,
+        right: &right,
+    };
+    let chips = vec![(Chip::Ymf262, 2usize)];
+    let rms = |v: &[f32]| (v.iter().map(|x| x * x).sum::<f32>() / v.len() as f32).sqrt();
+
+    for options in [
+        ResynthOptions::default(),
+        ResynthOptions {
+            stereo: StereoOptions {
+                analysis: StereoAnalysis::PerChannel,
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+    ] {
+        let doc =
+            process_stereo_resynth_multi_to_vgm(input, sample_rate, 1024, 0x10, &chips, &options)
+                .expect("vgm");
+        let outputs: Vec<u8> = doc
+            .commands
+            .iter()
+            .filter_map(|cmd| match cmd {
+                VgmCommand::Ymf262Write(_, w) if (0xC0..=0xC8).contains(&w.register) => {
+                    Some(w.value & 0x30)
+                }
+                _ => None,
+            })
+            .collect();
+        assert!(outputs.contains(&0x10), "{:?}: {outputs:?}", options.stereo);
+        assert!(outputs.contains(&0x20), "{:?}: {outputs:?}", options.stereo);
+
+        // each tone comes out on its own side of the preview
+        let (out_left, out_right) =
+            process_stereo_resynth_multi(input, sample_rate, 1024, sample_rate, &chips, &options)
+                .expect("wav");
+        assert_eq!(out_left.len(), len);
+        let body = 4096..len - 4096;
+        let analyze = |v: &[f32]| analyze_pcm_peaks(&v[body.clone()], sample_rate, 2);
+        let lp = analyze(&out_left);
+        let rp = analyze(&out_right);
+        assert!((lp[0].freq_hz - 440.0).abs() < 10.0, "{lp:?}");
+        assert!((rp[0].freq_hz - 1000.0).abs() < 10.0, "{rp:?}");
+        assert!(rms(&out_left[body.clone()]) > 0.05);
+        assert!(rms(&out_right[body.clone()]) > 0.05);
+    }
+
+    // a mono two-tone input spreads the bass left and the treble right,
+    // past the OPL3 routing threshold at the ends of its range
+    let mono: Vec<f32> = generate_test_sine(40.0, sample_rate, len, 0.4)
+        .iter()
+        .zip(generate_test_sine(3000.0, sample_rate, len, 0.4))
+        .map(|(a, b)| a + b)
+        .collect();
+    let mut options = ResynthOptions::default();
+    options.stereo.mono_spread = 1.0;
+    let (out_left, out_right) = process_stereo_resynth_multi(
+        InputChannels::Mono(&mono),
+        sample_rate,
+        4096,
+        sample_rate,
+        &chips,
+        &options,
+    )
+    .expect("spread");
+    let body = 8192..len - 8192;
+    let lp = analyze_pcm_peaks(&out_left[body.clone()], sample_rate, 1);
+    let rp = analyze_pcm_peaks(&out_right[body], sample_rate, 1);
+    assert!(lp[0].freq_hz < 100.0, "{lp:?}");
+    assert!((rp[0].freq_hz - 3000.0).abs() < 10.0, "{rp:?}");
+}
+
+#[test]
+fn test_analysis_rate_normalizes_input() {
+    let chips = vec![(Chip::Ym2203, 3usize)];
+    let options = ResynthOptions {
+        analysis_sample_rate: Some(44100),
+        ..Default::default()
+    };
+    // frequency and level writes of the first channel, which plays the tone
+    let writes = |input_rate: usize| {
+        let samples = generate_test_sine(440.0, input_rate, input_rate / 2, 0.5);
+        let doc = process_samples_resynth_multi_to_vgm(
+            &samples, input_rate, 1024, 0x10, &chips, &options,
+        )
+        .expect("vgm");
+        let waits: usize = doc
+            .commands
+            .iter()
+            .filter_map(|c| match c {
+                VgmCommand::WaitSamples(w) => Some(w.0 as usize),
+                _ => None,
+            })
+            .sum();
+        assert_eq!(waits, 22050);
+        doc.commands
+            .iter()
+            .filter_map(|c| match c {
+                VgmCommand::Ym2203Write(_, w) if [0x40, 0xA0, 0xA4].contains(&w.register) => {
+                    Some((w.register, w.value))
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+    };
+    // 48 kHz and 22.05 kHz files are analyzed the same way
+    let reference = writes(44100);
+    assert_eq!(writes(48000), reference);
+    assert_eq!(writes(22050), reference);
+
+    // WAV output at another rate is a resample of the preview
+    let samples = generate_test_sine(440.0, 44100, 22050, 0.5);
+    let out =
+        process_samples_resynth_multi(&samples, 44100, 1024, 22050, &chips, &options).expect("wav");
+    assert_eq!(out.len(), 11025);
+    let peaks = analyze_pcm_peaks(&out[2048..2048 + 4096], 22050, 1);
+    assert!((peaks[0].freq_hz - 440.0).abs() < 3.0, "{peaks:?}");
+}
+
+#[test]
+fn test_speech_mode_follows_formants() {
+    let sample_rate = 44100usize;
+    // 130 Hz pulses through resonances at 700 and 1900 Hz; neither is a
+    // harmonic of 130 Hz
+    let vowel = synthetic_vowel(
+        130.0,
+        &[(700.0, 60.0), (1900.0, 80.0)],
+        sample_rate,
+        sample_rate / 2,
+    );
+
+    let chips = vec![(Chip::Ymf262, 3usize)];
+    let speech = SpeechOptions {
+        formants: FormantOptions {
+            max_formants: 2,
+            ..Default::default()
+        },
+        f0: Some(F0Options::default()),
+    };
+    let options = ResynthOptions {
+        speech: Some(speech),
+        ..Default::default()
+    };
+    let out =
+        process_samples_resynth_multi(&vowel, sample_rate, 2048, sample_rate, &chips, &options)
+            .expect("speech resynth");
+    let mut peaks = analyze_pcm_peaks(&out[8192..8192 + 8192], sample_rate, 3);
+    peaks.sort_by(|a, b| a.freq_hz.total_cmp(&b.freq_hz));
+    let freqs: Vec<f32> = peaks.iter().map(|p| p.freq_hz).collect();
+    // the fundamental, then one voice per formant
+    assert!((freqs[0] - 130.0).abs() < 5.0, "{freqs:?}");
+    assert!((freqs[1] / 700.0 - 1.0).abs() < 0.05, "{freqs:?}");
+    assert!((freqs[2] / 1900.0 - 1.0).abs() < 0.05, "{freqs:?}");
+
+    // peak mode lands on harmonics instead
+    let out = process_samples_resynth_multi(
+        &vowel,
+        sample_rate,
+        2048,
+        sample_rate,
+        &chips,
+        &ResynthOptions::default(),
+    )
+    .expect("peak resynth");
+    let peaks = analyze_pcm_peaks(&out[8192..8192 + 8192], sample_rate, 3);
+    for p in &peaks {
+        let harmonic = p.freq_hz / 130.0;
+        assert!((harmonic - harmonic.round()).abs() < 0.1, "{peaks:?}");
+    }
+}
+
+#[test]
+fn test_speech_mode_plays_a_missing_fundamental() {
+    let sample_rate = 44100usize;
+    // harmonics 2 to 5 of 220 Hz, none at 220 Hz itself
+    let mut tone = vec![0.0f32; sample_rate / 2];
+    for h in 2..=5 {
+        let partial = generate_test_sine(220.0 * h as f32, sample_rate, tone.len(), 0.1);
+        tone.iter_mut().zip(&partial).for_each(|(t, p)| *t += p);
+    }
+    let chips = vec![(Chip::Ymf262, 2usize)];
+    let options = ResynthOptions {
+        speech: Some(SpeechOptions {
+            formants: FormantOptions {
+                max_formants: 1,
+                ..Default::default()
+            },
+            f0: Some(F0Options::default()),
+        }),
+        ..Default::default()
+    };
+    let out =
+        process_samples_resynth_multi(&tone, sample_rate, 2048, sample_rate, &chips, &options)
+            .expect("speech resynth");
+    // the f0 voice sounds at the estimated pitch
+    let peaks = analyze_pcm_peaks(&out[8192..8192 + 8192], sample_rate, 2);
+    assert!(
+        peaks
+            .iter()
+            .any(|p| (p.freq_hz - 220.0).abs() < 5.0 && p.magnitude_db > -30.0),
+        "{peaks:?}"
+    );
+}
+
+#[test]
+fn test_speech_mode_finds_low_f0_in_short_windows() {
+    let sample_rate = 44100usize;
+    // 100 Hz lies below what YIN finds in a 512-sample frame (172 Hz)
+    let vowel = synthetic_vowel(
+        100.0,
+        &[(700.0, 60.0), (1900.0, 80.0)],
+        sample_rate,
+        sample_rate / 2,
+    );
+    let chips = vec![(Chip::Ymf262, 3usize)];
+    let options = ResynthOptions {
+        speech: Some(SpeechOptions {
+            formants: FormantOptions {
+                max_formants: 2,
+                ..Default::default()
+            },
+            f0: Some(F0Options::default()),
+        }),
+        ..Default::default()
+    };
+    let out =
+        process_samples_resynth_multi(&vowel, sample_rate, 512, sample_rate, &chips, &options)
+            .expect("speech resynth");
+    let peaks = analyze_pcm_peaks(&out[8192..8192 + 8192], sample_rate, 3);
+    assert!(
+        peaks.iter().any(|p| (p.freq_hz - 100.0).abs() < 5.0),
+        "{peaks:?}"
+    );
+}
+
+#[test]
+fn test_quantize_snaps_voices_to_notes() {
+    let sample_rate = 44100usize;
+    let chips = vec![(Chip::Ym2203, 3usize)];
+    // 450 Hz sits 39 cents above A4
+    let samples = generate_test_sine(450.0, sample_rate, sample_rate / 2, 0.5);
+    let options = ResynthOptions {
+        quantize: Some(QuantizeOptions::default()),
+        ..Default::default()
+    };
+    let out =
+        process_samples_resynth_multi(&samples, sample_rate, 2048, sample_rate, &chips, &options)
+            .expect("quantized resynth");
+    let peaks = analyze_pcm_peaks(&out[8192..8192 + 8192], sample_rate, 1);
+    assert!((peaks[0].freq_hz - 440.0).abs() < 2.0, "{peaks:?}");
+
+    let out = process_samples_resynth_multi(
+        &samples,
+        sample_rate,
+        2048,
+        sample_rate,
+        &chips,
+        &ResynthOptions::default(),
+    )
+    .expect("resynth");
+    let peaks = analyze_pcm_peaks(&out[8192..8192 + 8192], sample_rate, 1);
+    assert!((peaks[0].freq_hz - 450.0).abs() < 2.0, "{peaks:?}");
+}
+
+#[test]
+fn test_vgm_voices_keep_channels_and_key_off() {
+    let sample_rate = 44100usize;
+    let part = sample_rate / 4;
+    let tone = |freq: f32, mags: [f32; 3]| -> Vec<f32> {
+        mags.iter()
+            .flat_map(|&mag| generate_test_sine(freq, sample_rate, part, mag))
+            .collect()
+    };
+    // 660 Hz grows louder than 440 Hz, then stops
+    let a = tone(440.0, [0.4, 0.2, 0.4]);
+    let b = tone(660.0, [0.1, 0.5, 0.0]);
+    let samples: Vec<f32> = a.iter().zip(&b).map(|(x, y)| x + y).collect();
+    let chips = vec![(Chip::Ym2203, 2usize)];
+    let doc = process_samples_resynth_multi_to_vgm(
+        &samples,
+        sample_rate,
+        2048,
+        0x10,
+        &chips,
+        &ResynthOptions::default(),
+    )
+    .expect("vgm");
+    let writes: Vec<(u8, u8)> = doc
+        .commands
+        .iter()
+        .filter_map(|c| match c {
+            VgmCommand::Ym2203Write(_, w) => Some((w.register, w.value)),
+            _ => None,
+        })
+        .collect();
+    let key_ons: Vec<u8> = writes
+        .iter()
+        .filter(|&&(r, v)| r == 0x28 && v & 0xF0 != 0)
+        .map(|&(_, v)| v & 0x03)
+        .collect();
+    let key_offs: Vec<u8> = writes
+        .iter()
+        .filter(|&&(r, v)| r == 0x28 && v & 0xF0 == 0)
+        .map(|&(_, v)| v & 0x03)
+        .collect();
+    // each tone keys on once, and 660 Hz is keyed off when it stops
+    assert_eq!(key_ons, vec![0, 1], "{writes:?}");
+    assert_eq!(key_offs, vec![1], "{writes:?}");
+    // and its carrier is fully attenuated (OPN TL is 7 bits)
+    let off = writes
+        .iter()
+        .position(|&(r, v)| r == 0x28 && v & 0xF0 == 0)
+        .unwrap();
+    assert_eq!(writes[off + 1], (0x41, 0x7F), "{writes:?}");
+
+    // each channel keeps its tone: its pitch (F-number scaled by its
+    // block) at every hop stays within a semitone
+    let mut registers = [0u8; 256];
+    let mut fnums: [Vec<u32>; 2] = Default::default();
+    for command in &doc.commands {
+        match command {
+            VgmCommand::Ym2203Write(_, w) => registers[w.register as usize] = w.value,
+            VgmCommand::WaitSamples(_) => {
+                for (ch, fnums) in fnums.iter_mut().enumerate() {
+                    let high = registers[0xA4 + ch] as u32;
+                    let fnum = ((high & 0x07) << 8) | registers[0xA0 + ch] as u32;
+                    fnums.push(fnum << ((high >> 3) & 0x07));
+                }
+            }
+            _ => {}
+        }
+    }
+    for (ch, fnums) in fnums.iter().enumerate() {
+        let (lo, hi) = (fnums.iter().min().unwrap(), fnums.iter().max().unwrap());
+        assert!((*hi as f32) < *lo as f32 * 1.06, "channel {ch}: {fnums:?}");
+    }
+    // unchanged frequencies are not rewritten at every hop
+    let hops = samples.len().div_ceil(2048);
+    let frequency_writes = writes.iter().filter(|&&(r, _)| r == 0xA0).count();
+    assert!(frequency_writes < hops, "{frequency_writes} of {hops}");
+}
+
+#[test]
+fn test_steal_policy_decides_spare_peaks() {
+    let sample_rate = 44100usize;
+    let half = sample_rate / 4;
+    // a quieter 660 Hz tone joins 440 Hz on a single channel
+    let a = generate_test_sine(440.0, sample_rate, 2 * half, 0.4);
+    let b: Vec<f32> = vec![0.0; half]
+        .into_iter()
+        .chain(generate_test_sine(660.0, sample_rate, half, 0.1))
+        .collect();
+    let samples: Vec<f32> = a.iter().zip(&b).map(|(x, y)| x + y).collect();
+    let chips = vec![(Chip::Ym2203, 1usize)];
+    let key_ons = |mode: AssignmentMode, steal: StealPolicy| -> usize {
+        let mut options = ResynthOptions::default();
+        options.assignment.mode = mode;
+        options.voices.steal = steal;
+        let doc = process_samples_resynth_multi_to_vgm(
+            &samples,
+            sample_rate,
+            2048,
+            0x10,
+            &chips,
+            &options,
+        )
+        .expect("vgm");
+        doc.commands
+            .iter()
+            .filter(|c| {
+                matches!(c, VgmCommand::Ym2203Write(_, w) if w.register == 0x28 && w.value & 0xF0 != 0)
+            })
+            .count()
+    };
+    // the quieter newcomer does not take the louder tone's channel
+    assert_eq!(key_ons(AssignmentMode::Greedy, StealPolicy::Quietest), 1);
+    // the oldest voice gives its channel up to it
+    assert!(key_ons(AssignmentMode::Greedy, StealPolicy::Oldest) > 1);
+    // a peak the optimal solver drops stays dropped
+    assert_eq!(key_ons(AssignmentMode::Optimal, StealPolicy::Oldest), 1);
+}
+
+#[test]
+fn test_optimal_assignment_uses_each_chip_in_range() {
+    let sample_rate = 44100usize;
+    // the YM2203 tunes 1000 Hz a little better, but 6500 Hz is beyond the
+    // OPL3's range; taking the YM2203 for 1000 Hz leaves 6500 Hz off pitch
+    let tones = [(1000.0f32, 0.5f32), (6500.0, 0.2)];
+    let samples: Vec<f32> = tones
+        .iter()
+        .map(|&(freq, mag)| generate_test_sine(freq, sample_rate, sample_rate / 2, mag))
+        .fold(vec![0.0; sample_rate / 2], |acc, tone| {
+            acc.iter().zip(&tone).map(|(a, b)| a + b).collect()
+        });
+    let chips = vec![(Chip::Ymf262, 1usize), (Chip::Ym2203, 1usize)];
+    let render = |mode: AssignmentMode| {
+        let mut options = ResynthOptions::default();
+        options.assignment.mode = mode;
+        let out = process_samples_resynth_multi(
+            &samples,
+            sample_rate,
+            2048,
+            sample_rate,
+            &chips,
+            &options,
+        )
+        .expect("resynth");
+        let mut peaks = analyze_pcm_peaks(&out[8192..8192 + 8192], sample_rate, 2);
+        peaks.sort_by(|a, b| a.freq_hz.total_cmp(&b.freq_hz));
+        peaks.iter().map(|p| p.freq_hz).collect::<Vec<f32>>()
+    };
+    let greedy = render(AssignmentMode::Greedy);
+    assert!((greedy[1] - 6500.0).abs() > 200.0, "{greedy:?}");
+    let optimal = render(AssignmentMode::Optimal);
+    assert!((optimal[0] - 1000.0).abs() < 3.0, "{optimal:?}");
+    assert!((optimal[1] - 6500.0).abs() < 5.0, "{optimal:?}");
+}
+
+// Frequency channel 0 of the first YM2203 plays in the middle of `doc`,
+// decoded from its registers at `clock`.
+fn ym2203_channel0_freq(doc: &soundlog::VgmDocument, clock: u32) -> f32 {
+    let mut registers = [0u8; 256];
+    let waits = doc
+        .commands
+        .iter()
+        .filter(|c| matches!(c, VgmCommand::WaitSamples(_)))
+        .count();
+    let mut seen = 0;
+    for command in &doc.commands {
+        match command {
+            VgmCommand::Ym2203Write(_, w) => registers[w.register as usize] = w.value,
+            VgmCommand::WaitSamples(_) => {
+                seen += 1;
+                if seen == waits / 2 {
+                    break;
+                }
+            }
+            _ => {}
+        }
+    }
+    let fnum = ((registers[0xA4] as u32 & 0x07) << 8) | registers[0xA0] as u32;
+    let block = (registers[0xA4] >> 3) & 0x07;
+    OpnSpec::fnum_block_to_freq(fnum, block, clock as f32).unwrap()
+}
+
+#[test]
+fn test_master_clock_follows_each_instance() {
+    let sample_rate = 44100usize;
+    let samples = generate_test_sine(440.0, sample_rate, sample_rate / 2, 0.5);
+    let chips = vec![(Chip::Ym2203, 3usize)];
+    // an NTSC colour-burst clock, 192 cents below the default 4 MHz
+    let ntsc = 3579545u32;
+    let options = ResynthOptions {
+        master_clocks: vec![Some(ntsc)],
+        ..Default::default()
+    };
+    let doc =
+        process_samples_resynth_multi_to_vgm(&samples, sample_rate, 2048, 0x10, &chips, &options)
+            .expect("vgm");
+    assert_eq!(doc.header.ym2203_clock, ntsc);
+    let freq = ym2203_channel0_freq(&doc, ntsc);
+    assert!((1200.0 * (freq / 440.0).log2()).abs() < 5.0, "{freq}");
+    // the default clock writes other F-numbers for the same pitch
+    let default_clock = default_master_clock(&Chip::Ym2203).unwrap();
+    let doc = process_samples_resynth_multi_to_vgm(
+        &samples,
+        sample_rate,
+        2048,
+        0x10,
+        &chips,
+        &ResynthOptions::default(),
+    )
+    .expect("vgm");
+    assert_eq!(doc.header.ym2203_clock, default_clock);
+    let freq = ym2203_channel0_freq(&doc, default_clock);
+    assert!((1200.0 * (freq / 440.0).log2()).abs() < 5.0, "{freq}");
+    assert!(ym2203_channel0_freq(&doc, ntsc) < 420.0);
+
+    // the playable band scales with the clock
+    let (lo, hi) = chip_frequency_range(&Chip::Ym2203).unwrap();
+    let (half_lo, half_hi) = chip_frequency_range_at(&Chip::Ym2203, default_clock / 2).unwrap();
+    assert!((half_lo * 2.0 / lo - 1.0).abs() < 1e-4 && (half_hi * 2.0 / hi - 1.0).abs() < 1e-4);
+
+    // a preview may mix clocks, a VGM header holds one per chip type
+    let chips = vec![(Chip::Ym2203, 3usize), (Chip::Ym2203, 3usize)];
+    let options = ResynthOptions {
+        master_clocks: vec![Some(4_000_000), Some(3_993_600)],
+        ..Default::default()
+    };
+    let out =
+        process_samples_resynth_multi(&samples, sample_rate, 2048, sample_rate, &chips, &options)
+            .expect("preview");
+    let peaks = analyze_pcm_peaks(&out[8192..8192 + 8192], sample_rate, 1);
+    assert!((peaks[0].freq_hz - 440.0).abs() < 2.0, "{peaks:?}");
+    assert!(
+        process_samples_resynth_multi_to_vgm(&samples, sample_rate, 2048, 0x10, &chips, &options)
+            .is_err()
+    );
+}
+
+#[test]
+fn test_level_refinement_corrects_leaked_levels() {
+    // the refinement's level scale is the one `mag_to_tl` writes
+    for max_tl in [0u8, 0x10, 0x30] {
+        let scale = tl_level_scale(max_tl);
+        for i in 0..200 {
+            let mag = 10f32.powf(-(i as f32) * 0.37 / 20.0);
+            assert_eq!(
+                mag_to_tl(scale.quantize(mag), max_tl),
+                mag_to_tl(mag, max_tl),
+                "{mag} at max TL {max_tl}"
+            );
+        }
+    }
+
+    // two equal partials 100 Hz apart: each measured peak carries some of
+    // the other's leakage, the refined voices play the input level
+    let sample_rate = 44100usize;
+    let a = generate_test_sine(1000.0, sample_rate, sample_rate / 2, 0.25);
+    let b = generate_test_sine(1100.0, sample_rate, sample_rate / 2, 0.25);
+    let samples: Vec<f32> = a.iter().zip(&b).map(|(x, y)| x + y).collect();
+    let chips = vec![(Chip::Ym2203, 2usize)];
+    let levels = |options: &ResynthOptions| -> Vec<[u8; 2]> {
+        let doc =
+            process_samples_resynth_multi_to_vgm(&samples, sample_rate, 2048, 0, &chips, options)
+                .expect("vgm");
+        let mut registers = [0u8; 256];
+        let mut levels = Vec::new();
+        for command in &doc.commands {
+            match command {
+                VgmCommand::Ym2203Write(_, w) => registers[w.register as usize] = w.value,
+                VgmCommand::WaitSamples(_) => levels.push([registers[0x40], registers[0x41]]),
+                _ => {}
+            }
+        }
+        levels
+    };
+    let expected = mag_to_tl(0.25, 0);
+    let refined = levels(&ResynthOptions {
+        refine: Some(RefineOptions::default()),
+        ..Default::default()
+    });
+    assert!(
+        refined.iter().all(|tls| tls == &[expected; 2]),
+        "{refined:?}"
+    );
+    let measured = levels(&ResynthOptions::default());
+    assert!(
+        measured.iter().any(|tls| tls != &[expected; 2]),
+        "{measured:?}"
+    );
+}
@@ -0,0 +1,131 @@
+use nanonanoda::pcm::Peak;
+use nanonanoda::resynth::{ResynthOptions, process_samples_resynth_multi_to_vgm};
+use nanonanoda::track::{
+    PartialTracker, TrackEvent, TrackingOptions, track_partials, tracks_to_frames,
+};
+use soundlog::chip::Chip;
+
+fn peak(freq_hz: f32, magnitude_db: f32) -> Peak {
+    Peak {
+        freq_hz,
+        magnitude: 10f32.powf(magnitude_db / 20.0),
+        magnitude_db,
+        bin: 0,
+    }
+}
+
+#[test]
+fn test_tracks_follow_partials_across_loudness_changes() {
+    // a gliding partial and a steady one that swap loudness order halfway
+    let frames: Vec<Vec<Peak>> = (0..8)
+        .map(|i| {
+            let glide = peak(
+                440.0 * 2f32.powf(i as f32 * 0.25 / 12.0),
+                -6.0 - i as f32 * 2.0,
+            );
+            let steady = peak(1000.0, -20.0 + i as f32 * 2.0);
+            if glide.magnitude > steady.magnitude {
+                vec![glide, steady]
+            } else {
+                vec![steady, glide]
+            }
+        })
+        .collect();
+
+    let tracks = track_partials(&frames, &TrackingOptions::default());
+    assert_eq!(tracks.len(), 2, "{tracks:?}");
+    for track in &tracks {
+        assert_eq!(track.birth_frame(), 0);
+        assert_eq!(track.death_frame(), 7);
+        // each track stays on its own partial
+        let steady = track.points[0].peak.freq_hz == 1000.0;
+        assert!(
+            track
+                .points
+                .iter()
+                .all(|p| (p.peak.freq_hz == 1000.0) == steady)
+        );
+    }
+
+    // per-frame order follows birth, not loudness
+    let laid_out = tracks_to_frames(&tracks, frames.len());
+    let ids: Vec<Vec<usize>> = laid_out
+        .iter()
+        .map(|f| f.iter().map(|t| t.track_id).collect())
+        .collect();
+    assert!(ids.iter().all(|f| f == &ids[0]));
+    assert_eq!(laid_out[0][0].event, TrackEvent::Birth);
+    assert_eq!(laid_out[3][0].event, TrackEvent::Continuation);
+    assert_eq!(laid_out[7][0].event, TrackEvent::Death);
+}
+
+#[test]
+fn test_deviation_limits_gaps_and_short_tracks() {
+    let options = TrackingOptions {
+        max_gap_frames: 1,
+        min_track_frames: 3,
+        ..Default::default()
+    };
+    let mut tracker = PartialTracker::new(options);
+    tracker.push_frame(&[peak(500.0, -10.0)]);
+    tracker.push_frame(&[peak(505.0, -12.0)]);
+    // one-frame dropout is bridged; a jump beyond the pitch limit starts a
+    // new (too short) track instead of continuing
+    tracker.push_frame(&[peak(800.0, -12.0)]);
+    tracker.push_frame(&[peak(510.0, -16.0)]);
+    tracker.push_frame(&[peak(512.0, -16.0)]);
+    assert_eq!(tracker.frame_count(), 5);
+
+    let tracks = tracker.finish();
+    assert_eq!(tracks.len(), 1, "{tracks:?}");
+    let track = &tracks[0];
+    assert_eq!(track.points.len(), 5);
+    let filled = track.point_at(2).expect("gap point");
+    assert!(filled.interpolated);
+    assert!(filled.peak.freq_hz > 505.0 && filled.peak.freq_hz < 510.0);
+    assert!((filled.peak.magnitude_db + 14.0).abs() < 1e-3);
+
+    // a longer dropout ends the track
+    let frames = vec![
+        vec![peak(500.0, -10.0)],
+        vec![peak(500.0, -10.0)],
+        vec![peak(500.0, -10.0)],
+        vec![],
+        vec![],
+        vec![peak(500.0, -10.0)],
+        vec![peak(500.0, -10.0)],
+        vec![peak(500.0, -10.0)],
+    ];
+    let tracks = track_partials(
+        &frames,
+        &TrackingOptions {
+            max_gap_frames: 1,
+            ..Default::default()
+        },
+    );
+    assert_eq!(tracks.len(), 2);
+    assert_eq!(tracks[0].death_frame(), 2);
+    assert_eq!(tracks[1].birth_frame(), 5);
+}
+
+#[test]
+fn test_resynth_with_tracking() {
+    let sample_rate = 44100usize;
+    let samples: Vec<f32> = (0..sample_rate / 2)
+        .map(|i| 0.5 * (2.0 * std::f32::consts::PI * 440.0 * i as f32 / sample_rate as f32).sin())
+        .collect();
+    let options = ResynthOptions {
+        tracking: Some(TrackingOptions::default()),
+        ..Default::default()
+    };
+    let doc = process_samples_resynth_multi_to_vgm(
+        &samples,
+        sample_rate,
+        1024,
+        0x10,
+        &[(Chip::Ym2203, 3)],
+        &options,
+    )
+    .expect("vgm with tracking");
+    assert!(!doc.commands.is_empty());
+}
@@ -0,0 +1,109 @@
+use nanonanoda::pcm::Peak;
+use nanonanoda::voice::{StealPolicy, VoiceAllocator, VoiceEvent, VoiceOptions, VoiceUpdate};
+
+fn peak(freq_hz: f32, magnitude: f32) -> Peak {
+    Peak {
+        freq_hz,
+        magnitude,
+        magnitude_db: 20.0 * magnitude.log10(),
+        bin: 0,
+    }
+}
+
+// channel and event of each peak, in peak order
+fn placement(updates: &[VoiceUpdate], peaks: usize) -> Vec<Option<(usize, VoiceEvent)>> {
+    (0..peaks)
+        .map(|p| {
+            updates
+                .iter()
+                .find(|u| u.peak == Some(p))
+                .map(|u| (u.channel, u.event))
+        })
+        .collect()
+}
+
+#[test]
+fn test_continuing_partials_keep_their_channels() {
+    let mut allocator = VoiceAllocator::new(3, VoiceOptions::default());
+    let updates = allocator.allocate(&[peak(440.0, 0.5), peak(660.0, 0.2)]);
+    assert_eq!(
+        placement(&updates, 2),
+        vec![Some((0, VoiceEvent::New)), Some((1, VoiceEvent::New))]
+    );
+
+    // the loudness order swaps and 440 Hz drifts: both stay put
+    let updates = allocator.allocate(&[peak(665.0, 0.6), peak(438.0, 0.1)]);
+    assert_eq!(
+        placement(&updates, 2),
+        vec![
+            Some((1, VoiceEvent::Continued)),
+            Some((0, VoiceEvent::Continued))
+        ]
+    );
+
+    // 440 Hz ends and a new partial takes its channel; idle channel 2 is
+    // left alone
+    let updates = allocator.allocate(&[peak(660.0, 0.6), peak(1000.0, 0.3)]);
+    assert_eq!(
+        placement(&updates, 2),
+        vec![Some((1, VoiceEvent::Continued)), Some((0, VoiceEvent::New))]
+    );
+    assert_eq!(updates.len(), 2);
+
+    // silence releases every channel
+    let updates = allocator.allocate(&[]);
+    assert!(updates.iter().all(|u| u.event == VoiceEvent::Released));
+    assert_eq!(
+        updates.iter().map(|u| u.channel).collect::<Vec<_>>(),
+        vec![0, 1]
+    );
+    assert_eq!(allocator.active_count(), 0);
+}
+
+#[test]
+fn test_steal_policies() {
+    // two channels hold 200 Hz (quiet, older) and 800 Hz (loud); 210 Hz and
+    // a third partial then compete for them
+    let steal = |steal: StealPolicy, third: Peak| {
+        let mut allocator = VoiceAllocator::new(
+            2,
+            VoiceOptions {
+                steal,
+                ..Default::default()
+            },
+        );
+        allocator.allocate(&[peak(200.0, 0.1)]);
+        allocator.allocate(&[peak(200.0, 0.1), peak(800.0, 0.5)]);
+        let updates = allocator.allocate(&[peak(800.0, 0.5), peak(200.0, 0.1), third]);
+        placement(&updates, 3)
+    };
+
+    // quietest: the 200 Hz voice gives way to a louder one only
+    assert_eq!(
+        steal(StealPolicy::Quietest, peak(3000.0, 0.3)),
+        vec![
+            Some((1, VoiceEvent::Continued)),
+            None,
+            Some((0, VoiceEvent::New))
+        ]
+    );
+    assert_eq!(steal(StealPolicy::Quietest, peak(3000.0, 0.05))[2], None);
+    // oldest: 200 Hz started first
+    assert_eq!(
+        steal(StealPolicy::Oldest, peak(3000.0, 0.05))[2],
+        Some((0, VoiceEvent::New))
+    );
+    // furthest in pitch: 200 Hz for a high partial, 800 Hz for a low one
+    assert_eq!(
+        steal(StealPolicy::FurthestPitch, peak(3000.0, 0.05))[2],
+        Some((0, VoiceEvent::New))
+    );
+    assert_eq!(
+        steal(StealPolicy::FurthestPitch, peak(50.0, 0.05)),
+        vec![
+            None,
+            Some((0, VoiceEvent::Continued)),
+            Some((1, VoiceEvent::New))
+        ]
+    );
+}